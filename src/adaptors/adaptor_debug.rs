@@ -1,31 +1,83 @@
 #![allow(clippy::use_debug)]
 
-use std::str::from_utf8;
+use std::path::Path;
 use std::sync::Arc;
-use std::time::Duration;
 use anyhow::anyhow;
 use async_trait::async_trait;
-use btleplug::api::{CharPropFlags, Peripheral};
+use btleplug::api::CharPropFlags;
+use chrono::Utc;
 use futures::StreamExt;
 use itertools::Itertools;
 use log::{debug, error, info};
 use mac_address::MacAddress;
-use tokio::time::{sleep, timeout};
-use crate::adaptors::{Adaptor, FoundDevice};
+use crate::adaptors::{Adaptor, FoundDevice, MatchConfidence, watch_connection};
+use crate::adaptors::explorer::Explorer;
+use crate::adaptors::capture::{CapturedCharacteristic, CapturedDevice, CapturedNotification, CapturedService, CaptureHeader, CaptureWriter, FORMAT_VERSION, Hex};
 use crate::config::Hrm;
 
 
-fn print_value(name: &str, data: &Vec<u8>) {
-    let s = match from_utf8(data.as_slice()) {
-        Ok(v) => v,
-        Err(e) => &format!("Invalid UTF-8 sequence: {e}"),
-    };
-
-    println!("{name}: {s}");
+pub(super) struct AdaptorDebug {
+    found_device: FoundDevice,
+    /// path of the capture file, if one should be written
+    capture: Option<Box<Path>>,
 }
 
-pub(super) struct AdaptorDebug {
-    found_device: FoundDevice
+impl AdaptorDebug {
+    /// Connects to the device; if `capture` is set, the session is written to this file.
+    pub(super) async fn connect(device: Arc<FoundDevice>, capture: Option<Box<Path>>) -> anyhow::Result<Self> {
+        if !device.peripheral.is_connected().await.unwrap_or(false) {
+            info!("Trying to connect to {:?}...", device.name);
+            if let Err(err) = device.peripheral.connect().await {
+                return Err(anyhow!("Could not connect to {} because of {:?}!", device.name, err));
+            }
+        }
+        if !device.peripheral.is_connected().await.unwrap_or(false) {
+            return Err(anyhow!("Connection to {} failed; check, that your device is not connected to another host!", device.name));
+        }
+
+        debug!("debug adaptor matched device!");
+        Ok(Self {
+            found_device: (*device).clone(),
+            capture,
+        })
+    }
+
+    /// Reads the service tree and the values of all readable characteristics.
+    async fn read_header(&self) -> CaptureHeader {
+        let device = &self.found_device;
+        let mut services = vec![];
+        for service in device.peripheral.services().iter().sorted_by_key(|s| s.uuid.to_string()) {
+            let mut characteristics = vec![];
+            for char in &service.characteristics {
+                let (value, read_error) = if char.properties.contains(CharPropFlags::READ) {
+                    match device.peripheral.read(char).await {
+                        Ok(value) => (Some(Hex(value)), None),
+                        Err(err) => (None, Some(err.to_string())),
+                    }
+                } else {
+                    (None, None)
+                };
+                characteristics.push(CapturedCharacteristic {
+                    uuid: char.uuid,
+                    properties: CapturedCharacteristic::flag_names(char.properties),
+                    descriptors: char.descriptors.iter().map(|d| d.uuid).collect(),
+                    value,
+                    read_error,
+                });
+            }
+            services.push(CapturedService {
+                uuid: service.uuid,
+                primary: service.primary,
+                characteristics,
+            });
+        }
+        CaptureHeader {
+            format_version: FORMAT_VERSION,
+            captured: Utc::now(),
+            device: CapturedDevice::new(&device.name, &device.properties),
+            services,
+        }
+    }
 }
 
 #[async_trait]
@@ -35,6 +87,8 @@ impl Adaptor for AdaptorDebug {
             name: self.found_device.name.clone(),
             mac: MacAddress::from(self.found_device.addr.into_inner()),
             adaptor_id: Some(0),
+            profile: None,
+            wheel_circumference: self.found_device.wheel_circumference,
         }
     }
 
@@ -46,127 +100,65 @@ impl Adaptor for AdaptorDebug {
         let _ = self.found_device.peripheral.disconnect().await;
     }
 
-    #[allow(clippy::too_many_lines)]
     async fn heartbeat_loop(&self) -> anyhow::Result<()> {
         let device = &self.found_device;
         device.peripheral.discover_services().await?;
-        let mut chars = vec![];
-        debug!("Subscribing to all characteristics");
-        println!("Device description:");
-        println!("Name: {}", device.name);
-        println!("Address: {}", device.addr);
-        println!("Address Type: {:?}", device.properties.address_type);
-        println!("TX Power Level: {:?}", device.properties.tx_power_level);
-        println!("RSSI: {:?}", device.properties.rssi);
-        println!("CLASS: {:?}", device.properties.class);
-        println!("Manufacturer Data:");
-        for (x, y) in &device.properties.manufacturer_data {
-            println!("  {x}: {y:?}");
-        }
-        println!("Services:");
-        for service in device.peripheral.services().iter().sorted_by_key(|s| s.uuid.to_string()) {
-            println!("  {}: Primary? {}", service.uuid, service.primary);
-            if service.characteristics.is_empty() {
-                continue;
-            }
-            println!("    Characteristics:");
-            for char in &service.characteristics {
-                println!("      {}:\n        Flags: {:?}", char.uuid, char.properties);
-                if !char.descriptors.is_empty() {
-                    println!("        Descriptors:");
-                    for d in &char.descriptors {
-                        println!("            {d}");
-                    }
-                }
-                if char.properties.contains(CharPropFlags::NOTIFY) {
-                    device.peripheral.subscribe(char).await?;
-                    chars.push(char.clone());
-                }
-                if char.properties.contains(CharPropFlags::READ) { 
-                    let val = device.peripheral.read(char).await?;
-                    println!("        Value: {val:?}");
-                    if service.uuid.as_u128() == 0x0000180a_0000_1000_8000_00805f9b34fb {
-                        match char.uuid.as_u128() { 
-                            0x00002a23_0000_1000_8000_00805f9b34fb => {
-                                print_value("          System ID", &val);
-                            }
-                            0x00002a24_0000_1000_8000_00805f9b34fb => {
-                                print_value("          Model Number", &val);
-                            }
-                            0x00002a25_0000_1000_8000_00805f9b34fb => {
-                                print_value("          Serial Number", &val);
-                            }
-                            0x00002a26_0000_1000_8000_00805f9b34fb => {
-                                print_value("          Firmware Revision", &val);
-                            }
-                            0x00002a27_0000_1000_8000_00805f9b34fb => {
-                                print_value("          Hardware Revision", &val);
-                            }
-                            0x00002a28_0000_1000_8000_00805f9b34fb => {
-                                print_value("          Software Revision", &val);
-                            }
-                            0x00002a29_0000_1000_8000_00805f9b34fb => {
-                                print_value("          Manufacture Name", &val);
-                            }
-                            0x00002a2a_0000_1000_8000_00805f9b34fb | 0x00002a50_0000_1000_8000_00805f9b34fb => {
-                            }
-                            _ => {
-                                println!("        Unknown characteristic!");
-                            }
-                        }
-                    }
-                }
+        let header = self.read_header().await;
+        header.print();
+
+        let mut writer = match self.capture {
+            Some(ref path) => {
+                info!("Writing capture to \"{}\"", path.display());
+                Some(CaptureWriter::create(path, &header)?)
             }
-            println!();
+            None => None,
+        };
+
+        debug!("Subscribing to all characteristics");
+        let chars = device.peripheral.characteristics()
+            .into_iter()
+            .filter(|c| c.properties.intersects(CharPropFlags::NOTIFY | CharPropFlags::INDICATE))
+            .collect::<Vec<_>>();
+        for char in &chars {
+            device.peripheral.subscribe(char).await?;
         }
 
         let mut notification_stream = device.peripheral.notifications().await?;
         info!("Device ready!");
+        let explorer = Arc::new(Explorer::new(Arc::clone(&device.peripheral)));
+        let explorer_clone = Arc::clone(&explorer);
         let handle = tokio::spawn(async move {
             // Process while the BLE connection is not broken or stopped.
             while let Some(data) = notification_stream.next().await {
-                println!(
-                    "Received data from [{:?}]: {:?}",
-                    data.uuid, data.value
-                );
-            }
-        });
-        loop {
-            sleep(Duration::from_secs(1)).await;
-            debug!("Testing connectivity...");
-            match device.peripheral.is_connected().await {
-                Ok(c) => {
-                    debug!("Connectivity successful!");
-                    if c {
-                        continue;
-                    }
-                    debug!("Disconnected...");
-                }
-                Err(err) => {
-                    error!("Checking connection returned error: {err}");
+                let notification = CapturedNotification::from(data);
+                if explorer_clone.shows(notification.uuid).await {
+                    notification.print();
                 }
-            }
-            debug!("Reconnecting...");
-            if let Ok(value) = timeout(Duration::from_secs(2), device.peripheral.connect()).await {
-                match value {
-                    Ok(()) => {
-                        debug!("Reconnected!");
-                        continue;
-                    }
-                    Err(err) => {
-                        error!("Reconnecting returned error: {err}");
+                if let Some(ref mut capture) = writer {
+                    if let Err(err) = capture.notification(&notification) {
+                        error!("Could not write notification to capture, stopping capture: {err}");
+                        writer = None;
                     }
                 }
             }
-            error!("Timeout while reconnecting to device!");
-            handle.abort();
-            for char in chars  {
-                device.peripheral.unsubscribe(&char).await?;
-            }
-            info!("Disconnecting from peripheral {:?}...", device.name);
-            device.peripheral.disconnect().await?;
-            return Ok(());
+        });
+        let explorer_handle = tokio::spawn(async move { explorer.run().await });
+        watch_connection(device).await;
+        handle.abort();
+        explorer_handle.abort();
+        for char in chars  {
+            device.peripheral.unsubscribe(&char).await?;
         }
+        info!("Disconnecting from peripheral {:?}...", device.name);
+        device.peripheral.disconnect().await?;
+        Ok(())
+    }
+
+    fn rate(_device: &FoundDevice) -> Result<MatchConfidence, String>
+    where
+        Self: Sized
+    {
+        Err("the debug adaptor is only used with \"debug-device\"".to_owned())
     }
 
     async fn try_wrap(device: Arc<FoundDevice>) -> anyhow::Result<Option<Arc<dyn Adaptor>>>
@@ -174,20 +166,6 @@ impl Adaptor for AdaptorDebug {
         Self: Sized
     {
         debug!("Trying debug adaptor as matcher...");
-
-        if !device.peripheral.is_connected().await.unwrap_or(false) {
-            info!("Trying to connect to {:?}...", device.name);
-            if let Err(err) = device.peripheral.connect().await {
-                return Err(anyhow!("Could not connect to {} because of {:?}!", device.name, err));
-            }
-        }
-        if !device.peripheral.is_connected().await.unwrap_or(false) {
-            return Err(anyhow!("Connection to {} failed; check, that your device is not connected to another host!", device.name));
-        }
-
-        debug!("debug adaptor matched device!");
-        return Ok(Some(Arc::new(Self {
-            found_device: (*device).clone()
-        })));
+        Ok(Some(Arc::new(Self::connect(device, None).await?)))
     }
 }
\ No newline at end of file
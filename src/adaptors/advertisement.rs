@@ -0,0 +1,61 @@
+//! Heart rate from advertisements
+//!
+//! Some watches and straps in broadcast mode put their heart rate into the manufacturer or service data of their
+//! advertisements, so it can be read without connecting. Every [`AdvertisementDecoder`] knows the format of one
+//! vendor; [`decode_advertisement`] tries all of them in [`DECODERS`] order.
+
+use btleplug::api::PeripheralProperties;
+use uuid::Uuid;
+
+use crate::adaptors::HrData;
+use crate::adaptors::type_1::parse_heart_rate_measurement;
+
+/// Decodes the heart rate from the advertisement of a specific vendor.
+pub(super) struct AdvertisementDecoder {
+    pub(super) id: &'static str,
+    /// Shown when listing all adaptors
+    pub(super) description: &'static str,
+    /// Returns [`None`], if the advertisement is not in the format of this decoder or contains no heart rate
+    decode: fn(&PeripheralProperties) -> Option<HrData>,
+}
+
+/// All decoders in priority order.
+pub(super) static DECODERS: [AdvertisementDecoder; 2] = [
+    AdvertisementDecoder {
+        id: "heart_rate_service",
+        description: "Heart Rate Measurement (0x2A37) format as service data of the Heart Rate service (0x180D)",
+        decode: decode_heart_rate_service,
+    },
+    AdvertisementDecoder {
+        id: "huami",
+        description: "Huami manufacturer data (company 0x0157), like Mi Band and Amazfit watches with heart rate broadcast enabled",
+        decode: decode_huami,
+    },
+];
+
+/// Decodes the heart rate of an advertisement with the first matching decoder.
+///
+/// Returns the id of the decoder and the decoded data.
+pub fn decode_advertisement(properties: &PeripheralProperties) -> Option<(&'static str, HrData)> {
+    DECODERS.iter().find_map(|d| (d.decode)(properties).map(|data| (d.id, data)))
+}
+
+/// Service data of the Heart Rate service (0x180D) in the format of a Heart Rate Measurement.
+fn decode_heart_rate_service(properties: &PeripheralProperties) -> Option<HrData> {
+    let value = properties.service_data.get(&Uuid::from_u128(0x0000180d_0000_1000_8000_00805f9b34fb))?;
+    let mut data = HrData::default();
+    parse_heart_rate_measurement(value, &mut data).ok()?;
+    Some(data)
+}
+
+/// Manufacturer data of Huami (0x0157); the heart rate is the 4th byte, `0` and `0xFF` mean "not measured".
+fn decode_huami(properties: &PeripheralProperties) -> Option<HrData> {
+    let hr = *properties.manufacturer_data.get(&0x0157)?.get(3)?;
+    if hr == 0 || hr == u8::MAX {
+        return None;
+    }
+    Some(HrData {
+        hr: u16::from(hr),
+        ..HrData::default()
+    })
+}
@@ -0,0 +1,324 @@
+//! GATT session captures
+//!
+//! With `debug-device` and `capture`, the debug adaptor writes everything it learns about a device to a capture
+//! file: the advertised properties, the service tree with the initial values and every notification.\
+//! A capture is a [JSON Lines](https://jsonlines.org) file: the first line is a [`CaptureHeader`], every following
+//! line a [`CapturedNotification`]. Each line is flushed on its own, so a capture stays readable, even if the
+//! program is killed. Binary values are lowercase hex strings without separators.
+
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+use std::fs::File;
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::str::from_utf8;
+
+use anyhow::{anyhow, Context, Result};
+use btleplug::api::{AddressType, CharPropFlags, PeripheralProperties, ValueNotification};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use uuid::Uuid;
+
+/// Version of the capture format; increased on incompatible changes.
+pub const FORMAT_VERSION: u32 = 1;
+
+/// Raw bytes, serialized as hex string.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Hex(pub Vec<u8>);
+
+impl Display for Hex {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        for byte in &self.0 {
+            write!(f, "{byte:02x}")?;
+        }
+        Ok(())
+    }
+}
+
+impl Hex {
+    /// Parses a hex string; whitespace between the bytes is ignored.
+    pub fn parse(value: &str) -> Result<Self> {
+        let digits = value.chars().filter(|c| !c.is_whitespace()).collect::<String>();
+        if digits.len() % 2 != 0 {
+            return Err(anyhow!("\"{value}\" has an odd number of hex digits"));
+        }
+        (0..digits.len())
+            .step_by(2)
+            .map(|i| {
+                digits.get(i..i + 2)
+                    .and_then(|d| u8::from_str_radix(d, 16).ok())
+                    .ok_or(anyhow!("\"{value}\" is not a valid hex string"))
+            })
+            .collect::<Result<Vec<_>>>()
+            .map(Hex)
+    }
+}
+
+impl Serialize for Hex {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Hex {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = String::deserialize(deserializer)?;
+        Hex::parse(&value).map_err(serde::de::Error::custom)
+    }
+}
+
+/// First line of a capture.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct CaptureHeader {
+    /// Always [`FORMAT_VERSION`]
+    pub format_version: u32,
+    /// Start of the capture
+    pub captured: DateTime<Utc>,
+    pub device: CapturedDevice,
+    pub services: Vec<CapturedService>,
+}
+
+/// Properties advertised by the device.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct CapturedDevice {
+    pub name: String,
+    /// mac address, like `AA:BB:CC:DD:EE:FF`
+    pub address: String,
+    /// `public` or `random`
+    pub address_type: Option<String>,
+    pub tx_power_level: Option<i16>,
+    pub rssi: Option<i16>,
+    pub class: Option<u32>,
+    /// manufacturer data by company identifier
+    pub manufacturer_data: BTreeMap<u16, Hex>,
+    pub service_data: BTreeMap<Uuid, Hex>,
+    /// advertised services
+    pub services: Vec<Uuid>,
+}
+
+impl CapturedDevice {
+    pub fn new(name: &str, properties: &PeripheralProperties) -> Self {
+        Self {
+            name: name.to_owned(),
+            address: properties.address.to_string(),
+            address_type: properties.address_type.map(|t| match t {
+                AddressType::Public => "public".to_owned(),
+                AddressType::Random => "random".to_owned(),
+            }),
+            tx_power_level: properties.tx_power_level,
+            rssi: properties.rssi,
+            class: properties.class,
+            manufacturer_data: properties.manufacturer_data.iter().map(|(k, v)| (*k, Hex(v.clone()))).collect(),
+            service_data: properties.service_data.iter().map(|(k, v)| (*k, Hex(v.clone()))).collect(),
+            services: properties.services.clone(),
+        }
+    }
+}
+
+/// A discovered service.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct CapturedService {
+    pub uuid: Uuid,
+    pub primary: bool,
+    pub characteristics: Vec<CapturedCharacteristic>,
+}
+
+/// A discovered characteristic and its value after connecting.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct CapturedCharacteristic {
+    pub uuid: Uuid,
+    /// names of the properties, like `READ` or `NOTIFY`
+    pub properties: Vec<String>,
+    /// uuids of the descriptors
+    pub descriptors: Vec<Uuid>,
+    /// value read after connecting; only set for readable characteristics
+    pub value: Option<Hex>,
+    /// error while reading the value
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub read_error: Option<String>,
+}
+
+impl CapturedCharacteristic {
+    /// Names of all set `flags`.
+    pub fn flag_names(flags: CharPropFlags) -> Vec<String> {
+        flags.iter_names().map(|(name, _)| name.to_owned()).collect()
+    }
+}
+
+impl CaptureHeader {
+    /// Prints the device description and the service tree.
+    pub fn print(&self) {
+        let device = &self.device;
+        println!("Device description:");
+        println!("Name: {}", device.name);
+        println!("Address: {}", device.address);
+        println!("Address Type: {}", device.address_type.as_deref().unwrap_or("unknown"));
+        println!("TX Power Level: {}", optional(device.tx_power_level));
+        println!("RSSI: {}", optional(device.rssi));
+        println!("CLASS: {}", optional(device.class));
+        println!("Manufacturer Data:");
+        for (company, data) in &device.manufacturer_data {
+            println!("  {company}: {data}");
+        }
+        println!("Service Data:");
+        for (uuid, data) in &device.service_data {
+            println!("  {uuid}: {data}");
+        }
+        println!("Services:");
+        for service in &self.services {
+            println!("  {}: Primary? {}", service.uuid, service.primary);
+            if service.characteristics.is_empty() {
+                continue;
+            }
+            println!("    Characteristics:");
+            for char in &service.characteristics {
+                println!("      {}:\n        Flags: {}", char.uuid, char.properties.join(" | "));
+                if !char.descriptors.is_empty() {
+                    println!("        Descriptors:");
+                    for d in &char.descriptors {
+                        println!("            {d}");
+                    }
+                }
+                if let Some(ref err) = char.read_error {
+                    println!("        Could not read value: {err}");
+                }
+                let Some(ref value) = char.value else {
+                    continue;
+                };
+                println!("        Value: {value}");
+                if service.uuid.as_u128() == 0x0000180a_0000_1000_8000_00805f9b34fb {
+                    match char.uuid.as_u128() {
+                        0x00002a23_0000_1000_8000_00805f9b34fb => print_value("          System ID", value),
+                        0x00002a24_0000_1000_8000_00805f9b34fb => print_value("          Model Number", value),
+                        0x00002a25_0000_1000_8000_00805f9b34fb => print_value("          Serial Number", value),
+                        0x00002a26_0000_1000_8000_00805f9b34fb => print_value("          Firmware Revision", value),
+                        0x00002a27_0000_1000_8000_00805f9b34fb => print_value("          Hardware Revision", value),
+                        0x00002a28_0000_1000_8000_00805f9b34fb => print_value("          Software Revision", value),
+                        0x00002a29_0000_1000_8000_00805f9b34fb => print_value("          Manufacture Name", value),
+                        0x00002a2a_0000_1000_8000_00805f9b34fb | 0x00002a50_0000_1000_8000_00805f9b34fb => {}
+                        _ => println!("        Unknown characteristic!"),
+                    }
+                }
+            }
+            println!();
+        }
+    }
+}
+
+fn optional<T: Display>(value: Option<T>) -> String {
+    value.map_or("unknown".to_owned(), |v| v.to_string())
+}
+
+fn print_value(name: &str, data: &Hex) {
+    let s = match from_utf8(&data.0) {
+        Ok(v) => v,
+        Err(e) => &format!("Invalid UTF-8 sequence: {e}"),
+    };
+
+    println!("{name}: {s}");
+}
+
+/// A notification or indication received from the device.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct CapturedNotification {
+    pub timestamp: DateTime<Utc>,
+    pub uuid: Uuid,
+    pub value: Hex,
+}
+
+impl From<ValueNotification> for CapturedNotification {
+    /// Timestamps a notification received now.
+    fn from(notification: ValueNotification) -> Self {
+        Self {
+            timestamp: Utc::now(),
+            uuid: notification.uuid,
+            value: Hex(notification.value),
+        }
+    }
+}
+
+impl CapturedNotification {
+    pub fn print(&self) {
+        println!("Received data from [{}] at {}: {}", self.uuid, self.timestamp.format("%H:%M:%S%.3f"), self.value);
+    }
+}
+
+/// A capture loaded from a file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Capture {
+    pub header: CaptureHeader,
+    pub notifications: Vec<CapturedNotification>,
+}
+
+impl Capture {
+    /// Loads the capture at `path`.
+    ///
+    /// A broken last line is ignored, because it is most likely the result of an interrupted write.
+    pub fn load(path: &Path) -> Result<Self> {
+        let file = File::open(path).with_context(|| format!("Could not open capture \"{}\"", path.display()))?;
+        let mut lines = BufReader::new(file).lines();
+        let header: CaptureHeader = serde_json::from_str(&lines.next().ok_or(anyhow!("The capture is empty"))??)
+            .context("Line 1 is not a valid capture header")?;
+        if header.format_version != FORMAT_VERSION {
+            return Err(anyhow!("Capture format version {} is not supported", header.format_version));
+        }
+
+        let lines = lines.collect::<Result<Vec<_>, _>>()?;
+        let mut notifications = vec![];
+        for (index, line) in lines.iter().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            match serde_json::from_str(line) {
+                Ok(notification) => notifications.push(notification),
+                Err(_) if index + 1 == lines.len() => break,
+                Err(err) => return Err(anyhow!("Line {} is not a valid notification: {err}", index + 2)),
+            }
+        }
+        Ok(Self { header, notifications })
+    }
+}
+
+/// Returns the file for the `session`th connection (first is 1) to the device, whose capture is written to `path`.
+///
+/// The first session is written to `path`; later ones (after a connection loss) get their number appended to the
+/// file name, like `session-2.jsonl`, so no session overwrites an earlier one.
+pub fn session_path(path: &Path, session: u32) -> PathBuf {
+    if session <= 1 {
+        return path.to_path_buf();
+    }
+    let stem = path.file_stem().map(|s| s.to_string_lossy().into_owned()).unwrap_or_default();
+    let name = match path.extension() {
+        Some(extension) => format!("{stem}-{session}.{}", extension.to_string_lossy()),
+        None => format!("{stem}-{session}"),
+    };
+    path.with_file_name(name)
+}
+
+/// Writes a capture line by line.
+pub struct CaptureWriter {
+    file: File,
+}
+
+impl CaptureWriter {
+    /// Creates the file at `path` (replacing an existing one) and writes the header.
+    pub fn create(path: &Path, header: &CaptureHeader) -> Result<Self> {
+        let file = File::create(path).with_context(|| format!("Could not create capture \"{}\"", path.display()))?;
+        let mut writer = Self { file };
+        writer.write_line(header)?;
+        Ok(writer)
+    }
+
+    /// Appends a notification.
+    pub fn notification(&mut self, notification: &CapturedNotification) -> Result<()> {
+        self.write_line(notification)
+    }
+
+    fn write_line(&mut self, value: &impl Serialize) -> Result<()> {
+        let mut line = serde_json::to_vec(value)?;
+        line.push(b'\n');
+        self.file.write_all(&line)?;
+        self.file.flush()?;
+        Ok(())
+    }
+}
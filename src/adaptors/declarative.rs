@@ -0,0 +1,349 @@
+//! Adaptors described in the config file instead of code.
+//!
+//! An [`AdaptorDefinition`] tells [`DeclarativeAdaptor`] which devices it should match
+//! and where to find heart rate, battery and contact in the characteristic values.
+
+use std::collections::HashSet;
+use std::sync::Arc;
+
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use btleplug::api::{Characteristic, CharPropFlags};
+use log::{debug, info, warn};
+use mac_address::MacAddress;
+use itertools::Itertools;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use tokio::sync::RwLock;
+use uuid::Uuid;
+
+use crate::adaptors::{Adaptor, FoundDevice, get_adaptor, HrData, HrmState, MatchConfidence, read_device_profile, run_notification_loop};
+use crate::config::{DeviceProfile, Hrm};
+
+/// Description of an adaptor, read from the config file.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AdaptorDefinition {
+    /// Id to register this adaptor under; must not be used by any other adaptor
+    pub adaptor_id: u16,
+    /// Rules a device must fulfill to be matched automatically
+    #[serde(default, rename = "match")]
+    pub match_rules: MatchRules,
+    /// Where to find the heart rate
+    pub heart_rate: FieldDefinition,
+    /// Where to find the battery level in %
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub battery: Option<FieldDefinition>,
+    /// Where to find the contact state; contact is ok, if the value is not 0
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub contact: Option<FieldDefinition>,
+}
+
+/// Rules to match a device; all given rules must match.
+///
+/// A definition without any rule only matches devices, which have its `adaptor_id` set in the config.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct MatchRules {
+    /// Regular expression the advertised name must match
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name_regex: Option<String>,
+    /// The device must have one of these mac addresses
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub macs: Vec<MacAddress>,
+    /// The device must advertise all of these services
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub services: Vec<Uuid>,
+}
+
+impl MatchRules {
+    fn is_empty(&self) -> bool {
+        self.name_regex.is_none() && self.macs.is_empty() && self.services.is_empty()
+    }
+}
+
+/// Byte order of a value.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Endianness {
+    #[default]
+    Little,
+    Big,
+}
+
+/// Position and encoding of a value inside a characteristic.
+///
+/// The value is read as unsigned integer, masked and scaled afterward: `(raw & mask) * scale`.\
+/// The characteristic is read once after connecting, if it is readable, and subscribed to, if it supports notifications.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FieldDefinition {
+    /// Service containing the characteristic; if not set, the first characteristic with a matching uuid is used
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub service: Option<Uuid>,
+    pub characteristic: Uuid,
+    /// Offset of the first byte
+    #[serde(default)]
+    pub offset: usize,
+    /// Number of bytes (1, 2 or 4)
+    #[serde(default = "default_width")]
+    pub width: u8,
+    #[serde(default)]
+    pub endianness: Endianness,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mask: Option<u32>,
+    #[serde(default = "default_scale")]
+    pub scale: f64,
+}
+
+fn default_width() -> u8 {
+    1
+}
+
+fn default_scale() -> f64 {
+    1.0
+}
+
+impl FieldDefinition {
+    /// Extracts the value from the raw bytes of the characteristic.
+    fn extract(&self, value: &[u8]) -> Option<f64> {
+        let bytes = value.get(self.offset..self.offset + usize::from(self.width))?;
+        let mut raw: u32 = 0;
+        for byte in match self.endianness {
+            Endianness::Little => bytes.iter().rev().collect::<Vec<_>>(),
+            Endianness::Big => bytes.iter().collect(),
+        } {
+            raw = (raw << 8) | u32::from(*byte);
+        }
+        if let Some(mask) = self.mask {
+            raw &= mask;
+        }
+        Some(f64::from(raw) * self.scale)
+    }
+
+    /// Finds the characteristic this field is read from.
+    fn find_characteristic<'a>(&self, characteristics: &'a [Characteristic]) -> Option<&'a Characteristic> {
+        characteristics.iter().find(|c| self.is_read_from(c.service_uuid, c.uuid))
+    }
+
+    /// Checks, if this field is read from the characteristic `uuid` of `service`.
+    fn is_read_from(&self, service: Uuid, uuid: Uuid) -> bool {
+        self.characteristic == uuid && self.service.is_none_or(|s| s == service)
+    }
+}
+
+/// Checks all definitions for errors, which would prevent them from working.
+pub fn check_definitions(definitions: &[AdaptorDefinition]) -> Result<()> {
+    let mut ids = HashSet::new();
+    for definition in definitions {
+        if get_adaptor(definition.adaptor_id).is_some() || definition.adaptor_id == 0 {
+            return Err(anyhow!("Adaptor id {} is already used by a built-in adaptor", definition.adaptor_id));
+        }
+        if !ids.insert(definition.adaptor_id) {
+            return Err(anyhow!("Adaptor id {} is defined multiple times", definition.adaptor_id));
+        }
+        if let Some(ref regex) = definition.match_rules.name_regex {
+            Regex::new(regex).map_err(|err| anyhow!("Adaptor {}: invalid name regex: {err}", definition.adaptor_id))?;
+        }
+        for field in [Some(&definition.heart_rate), definition.battery.as_ref(), definition.contact.as_ref()].into_iter().flatten() {
+            if ![1, 2, 4].contains(&field.width) {
+                return Err(anyhow!("Adaptor {}: width must be 1, 2 or 4, not {}", definition.adaptor_id, field.width));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Adaptor interpreting an [`AdaptorDefinition`].
+pub(super) struct DeclarativeAdaptor {
+    found_device: FoundDevice,
+    definition: AdaptorDefinition,
+    /// Characteristics to subscribe to
+    characteristics: Vec<Characteristic>,
+    /// Values read once after connecting
+    initial_state: HrData,
+    profile: Option<DeviceProfile>,
+    hrm_state: Arc<RwLock<HrmState>>,
+}
+
+impl DeclarativeAdaptor {
+    /// Sets up the device as described by the definition; the match rules are not checked here.
+    pub(super) async fn try_wrap_definition(
+        definition: &AdaptorDefinition,
+        device: Arc<FoundDevice>,
+    ) -> Result<Option<Arc<dyn Adaptor>>> {
+        debug!("Trying declarative adaptor {} as matcher...", definition.adaptor_id);
+        if !device.peripheral.is_connected().await.unwrap_or(false) {
+            info!("Trying to connect to {:?}...", device.name);
+            if let Err(err) = device.peripheral.connect().await {
+                return Err(anyhow!("Could not connect to {} because of {:?}!", device.name, err));
+            }
+        }
+        debug!("Discover peripheral {:?} services...", device.name);
+        device.peripheral.discover_services().await?;
+        let available = device.peripheral.characteristics().into_iter().collect::<Vec<_>>();
+
+        if definition.heart_rate.find_characteristic(&available).is_none() {
+            debug!("Device does not have the heart rate characteristic {}.", definition.heart_rate.characteristic);
+            return Ok(None);
+        }
+
+        let mut adaptor = Self {
+            found_device: (*device).clone(),
+            definition: definition.clone(),
+            characteristics: vec![],
+            initial_state: HrData::default(),
+            profile: read_device_profile(&device.peripheral).await,
+            hrm_state: Arc::default(),
+        };
+        for field in [Some(&definition.heart_rate), definition.battery.as_ref(), definition.contact.as_ref()].into_iter().flatten() {
+            let Some(characteristic) = field.find_characteristic(&available) else {
+                warn!("Device does not have the characteristic {}, ignoring it.", field.characteristic);
+                continue;
+            };
+            if characteristic.properties.intersects(CharPropFlags::NOTIFY | CharPropFlags::INDICATE)
+                && !adaptor.characteristics.contains(characteristic) {
+                adaptor.characteristics.push(characteristic.clone());
+            }
+            // readable fields have a value before the first notification
+            if characteristic.properties.contains(CharPropFlags::READ) {
+                match device.peripheral.read(characteristic).await {
+                    Ok(v) => adaptor.definition.apply(characteristic, &v, &mut adaptor.initial_state),
+                    Err(err) => warn!("Error while reading characteristic {}: {err}", characteristic.uuid),
+                }
+            }
+        }
+
+        debug!("declarative adaptor {} matched device!", definition.adaptor_id);
+        Ok(Some(Arc::new(adaptor)))
+    }
+}
+
+impl AdaptorDefinition {
+    /// Checks the match rules against the device.
+    ///
+    /// Definitions are made for specific devices, so a match has [`MatchConfidence::Model`].
+    pub(super) fn rate(&self, device: &FoundDevice) -> Result<MatchConfidence, String> {
+        let rules = &self.match_rules;
+        if rules.is_empty() {
+            return Err("the definition has no match rules".to_owned());
+        }
+        if !rules.macs.is_empty() && !rules.macs.contains(&MacAddress::from(device.addr.into_inner())) {
+            return Err("the mac address is not listed".to_owned());
+        }
+        if let Some(ref regex) = rules.name_regex {
+            if !Regex::new(regex).map_err(|err| err.to_string())?.is_match(&device.name) {
+                return Err(format!("the name does not match \"{regex}\""));
+            }
+        }
+        if let Some(service) = rules.services.iter().find(|s| !device.properties.services.contains(s)) {
+            return Err(format!("the service {service} is not advertised"));
+        }
+        if self.heart_rate.find_characteristic(&device.peripheral.characteristics().into_iter().collect::<Vec<_>>()).is_none() {
+            return Err(format!("the heart rate characteristic {} is missing", self.heart_rate.characteristic));
+        }
+        Ok(MatchConfidence::Model)
+    }
+
+    /// Checks the match rules, which do not require a connection (all but the heart rate characteristic).
+    pub(super) fn matches_advertisement(&self, device: &FoundDevice) -> bool {
+        let rules = &self.match_rules;
+        !rules.is_empty()
+            && (rules.macs.is_empty() || rules.macs.contains(&MacAddress::from(device.addr.into_inner())))
+            && rules.name_regex.as_ref().is_none_or(|r| Regex::new(r).is_ok_and(|r| r.is_match(&device.name)))
+            && rules.services.iter().all(|s| device.properties.services.contains(s))
+    }
+
+    /// Summarizes the match rules.
+    pub(super) fn describe(&self) -> String {
+        let rules = &self.match_rules;
+        let mut parts = vec![];
+        if let Some(ref regex) = rules.name_regex {
+            parts.push(format!("name matches \"{regex}\""));
+        }
+        if !rules.macs.is_empty() {
+            parts.push(format!("mac is one of {}", rules.macs.iter().join(", ")));
+        }
+        if !rules.services.is_empty() {
+            parts.push(format!("advertises {}", rules.services.iter().join(", ")));
+        }
+        if parts.is_empty() {
+            "only used for devices configured with this adaptor id".to_owned()
+        } else {
+            parts.join(", ")
+        }
+    }
+
+    /// Updates `data` with all fields found in a value of `characteristic`.
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    fn apply(&self, characteristic: &Characteristic, value: &[u8], data: &mut HrData) {
+        let is_read_from = |f: &FieldDefinition| f.is_read_from(characteristic.service_uuid, characteristic.uuid);
+        if is_read_from(&self.heart_rate) {
+            if let Some(hr) = self.heart_rate.extract(value) {
+                data.hr = hr.round() as u16;
+            }
+        }
+        if let Some(battery) = self.battery.as_ref().filter(|f| is_read_from(f)) {
+            if let Some(level) = battery.extract(value) {
+                data.battery = Some(level.round() as u8);
+            }
+        }
+        if let Some(contact) = self.contact.as_ref().filter(|f| is_read_from(f)) {
+            if let Some(state) = contact.extract(value) {
+                data.contact_ok = Some(state != 0.0);
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl Adaptor for DeclarativeAdaptor {
+    async fn to_hrm(&self) -> Hrm {
+        Hrm {
+            name: self.found_device.name.clone(),
+            mac: MacAddress::from(self.found_device.addr.into_inner()),
+            adaptor_id: Some(self.definition.adaptor_id),
+            profile: self.profile.clone(),
+            wheel_circumference: self.found_device.wheel_circumference,
+        }
+    }
+
+    fn get_addr(&self) -> MacAddress {
+        MacAddress::from(self.found_device.addr.into_inner())
+    }
+
+    async fn shutdown(&self) {
+        let _ = self.found_device.peripheral.disconnect().await;
+    }
+
+    async fn heartbeat_loop(&self) -> Result<()> {
+        let definition = self.definition.clone();
+        // notifications only carry the uuid, so the service is taken from the subscribed characteristic
+        let subscribed = self.characteristics.clone();
+        run_notification_loop(
+            &self.found_device,
+            &self.characteristics,
+            &self.hrm_state,
+            self.initial_state.clone(),
+            move |received_data, data| {
+                if let Some(characteristic) = subscribed.iter().find(|c| c.uuid == received_data.uuid) {
+                    definition.apply(characteristic, &received_data.value, data);
+                }
+            },
+        ).await
+    }
+
+    /// Declarative adaptors need a definition; use [`AdaptorDefinition::rate`] instead.
+    fn rate(_device: &FoundDevice) -> Result<MatchConfidence, String>
+    where
+        Self: Sized
+    {
+        Err("declarative adaptors need a definition".to_owned())
+    }
+
+    /// Declarative adaptors need a definition; use [`DeclarativeAdaptor::try_wrap_definition`] instead.
+    async fn try_wrap(_device: Arc<FoundDevice>) -> Result<Option<Arc<dyn Adaptor>>>
+    where
+        Self: Sized
+    {
+        Ok(None)
+    }
+}
@@ -0,0 +1,197 @@
+//! Interactive GATT explorer for `debug-device`
+//!
+//! While the debug adaptor is connected, lines entered on stdin are parsed as [`ExplorerCommand`]s and executed
+//! against the device. This allows reading and writing characteristics, changing subscriptions and hiding
+//! notifications of characteristics, which are not of interest right now.
+
+use std::collections::BTreeSet;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use btleplug::api::{CharPropFlags, Characteristic, WriteType};
+use btleplug::api::bleuuid::uuid_from_u16;
+use itertools::Itertools;
+use tokio::sync::RwLock;
+use uuid::Uuid;
+
+use crate::adaptors::capture::Hex;
+use crate::adaptors::transport::BlePeripheral;
+use crate::stdin::next_line;
+
+/// Shown for `help` and unknown commands.
+const HELP: &str = "\
+Commands (uuids can be written in full or as 16 bit short form, like \"2a37\"):
+  services                    list all services and characteristics
+  read <uuid>                 read a characteristic
+  write <uuid> <hex>          write bytes to a characteristic and wait for the response
+  write_nr <uuid> <hex>       write bytes to a characteristic without response
+  subscribe <uuid>            subscribe to notifications or indications
+  unsubscribe <uuid>          unsubscribe from notifications or indications
+  filter [<uuid> ...]         only show notifications of these characteristics; without uuids, show all
+  help                        show this help";
+
+/// A command of the explorer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(super) enum ExplorerCommand {
+    Help,
+    Services,
+    Read(Uuid),
+    Write {
+        uuid: Uuid,
+        value: Vec<u8>,
+        with_response: bool,
+    },
+    Subscribe(Uuid),
+    Unsubscribe(Uuid),
+    /// Only show notifications of these characteristics; all are shown, if empty
+    Filter(BTreeSet<Uuid>),
+}
+
+/// Parses a full uuid or a 16 bit short form of a Bluetooth SIG uuid.
+fn parse_uuid(value: &str) -> Result<Uuid, String> {
+    if value.len() == 4 {
+        return u16::from_str_radix(value, 16)
+            .map(uuid_from_u16)
+            .map_err(|_| format!("\"{value}\" is not a valid short uuid"));
+    }
+    Uuid::parse_str(value).map_err(|_| format!("\"{value}\" is not a valid uuid"))
+}
+
+impl FromStr for ExplorerCommand {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut words = s.split_whitespace();
+        let command = words.next().unwrap_or_default();
+        let uuid = |word: Option<&str>| word.ok_or(format!("\"{command}\" requires a uuid")).and_then(parse_uuid);
+        let parsed = match command {
+            "help" | "" => Self::Help,
+            "services" => Self::Services,
+            "read" => Self::Read(uuid(words.next())?),
+            "write" | "write_nr" => {
+                let uuid = uuid(words.next())?;
+                let value = Hex::parse(&words.by_ref().join("")).map_err(|err| err.to_string())?;
+                if value.0.is_empty() {
+                    return Err(format!("\"{command}\" requires the bytes to write"));
+                }
+                Self::Write {
+                    uuid,
+                    value: value.0,
+                    with_response: command == "write",
+                }
+            }
+            "subscribe" => Self::Subscribe(uuid(words.next())?),
+            "unsubscribe" => Self::Unsubscribe(uuid(words.next())?),
+            "filter" => Self::Filter(words.by_ref().map(parse_uuid).collect::<Result<_, _>>()?),
+            _ => return Err(format!("Unknown command \"{command}\"")),
+        };
+        if words.next().is_some() {
+            return Err(format!("Too many arguments for \"{command}\""));
+        }
+        Ok(parsed)
+    }
+}
+
+/// Executes commands against a connected device.
+pub(super) struct Explorer {
+    peripheral: Arc<dyn BlePeripheral>,
+    filter: RwLock<BTreeSet<Uuid>>,
+}
+
+impl Explorer {
+    pub(super) fn new(peripheral: Arc<dyn BlePeripheral>) -> Self {
+        Self {
+            peripheral,
+            filter: RwLock::default(),
+        }
+    }
+
+    /// Checks, if notifications of `uuid` should be shown.
+    pub(super) async fn shows(&self, uuid: Uuid) -> bool {
+        let filter = self.filter.read().await;
+        filter.is_empty() || filter.contains(&uuid)
+    }
+
+    /// Reads commands from stdin and executes them until the task is aborted.
+    pub(super) async fn run(&self) {
+        println!("GATT explorer ready; enter \"help\" to list all commands.");
+        // drop lines entered before the device was connected
+        let mut clear = true;
+        loop {
+            let Some(line) = next_line(clear, Some(Duration::from_secs(1))).await else {
+                clear = false;
+                continue;
+            };
+            clear = false;
+            let output = match line.parse::<ExplorerCommand>() {
+                Ok(command) => self.execute(command).await.unwrap_or_else(|err| format!("Error: {err}")),
+                Err(err) => format!("{err}\n{HELP}"),
+            };
+            println!("{output}");
+        }
+    }
+
+    /// Executes a command and returns the text to show.
+    pub(super) async fn execute(&self, command: ExplorerCommand) -> Result<String> {
+        match command {
+            ExplorerCommand::Help => Ok(HELP.to_owned()),
+            ExplorerCommand::Services => Ok(self.describe_services()),
+            ExplorerCommand::Read(uuid) => {
+                let value = self.peripheral.read(&self.find(uuid, CharPropFlags::READ)?).await?;
+                Ok(format!("{uuid}: {}", Hex(value)))
+            }
+            ExplorerCommand::Write { uuid, value, with_response } => {
+                let (flag, write_type) = if with_response {
+                    (CharPropFlags::WRITE, WriteType::WithResponse)
+                } else {
+                    (CharPropFlags::WRITE_WITHOUT_RESPONSE, WriteType::WithoutResponse)
+                };
+                self.peripheral.write(&self.find(uuid, flag)?, &value, write_type).await?;
+                Ok(format!("Wrote {} to {uuid}", Hex(value)))
+            }
+            ExplorerCommand::Subscribe(uuid) => {
+                self.peripheral.subscribe(&self.find(uuid, CharPropFlags::NOTIFY | CharPropFlags::INDICATE)?).await?;
+                Ok(format!("Subscribed to {uuid}"))
+            }
+            ExplorerCommand::Unsubscribe(uuid) => {
+                self.peripheral.unsubscribe(&self.find(uuid, CharPropFlags::NOTIFY | CharPropFlags::INDICATE)?).await?;
+                Ok(format!("Unsubscribed from {uuid}"))
+            }
+            ExplorerCommand::Filter(uuids) => {
+                let output = if uuids.is_empty() {
+                    "Showing all notifications".to_owned()
+                } else {
+                    format!("Only showing notifications of {}", uuids.iter().join(", "))
+                };
+                *self.filter.write().await = uuids;
+                Ok(output)
+            }
+        }
+    }
+
+    /// Finds the characteristic `uuid`, which must support any of `flags`.
+    fn find(&self, uuid: Uuid, flags: CharPropFlags) -> Result<Characteristic> {
+        let characteristic = self.peripheral.characteristics()
+            .into_iter()
+            .find(|c| c.uuid == uuid)
+            .ok_or(anyhow!("The device has no characteristic {uuid}"))?;
+        if !characteristic.properties.intersects(flags) {
+            return Err(anyhow!("Characteristic {uuid} does not support this (flags: {})",
+                characteristic.properties.iter_names().map(|(name, _)| name).join(" | ")));
+        }
+        Ok(characteristic)
+    }
+
+    fn describe_services(&self) -> String {
+        let mut lines = vec![];
+        for service in self.peripheral.services() {
+            lines.push(format!("{}{}", service.uuid, if service.primary { "" } else { " (secondary)" }));
+            for char in &service.characteristics {
+                lines.push(format!("  {}: {}", char.uuid, char.properties.iter_names().map(|(name, _)| name).join(" | ")));
+            }
+        }
+        lines.join("\n")
+    }
+}
@@ -1,4 +1,4 @@
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::io;
 use std::io::Write;
 use std::sync::{Arc, LazyLock};
@@ -7,30 +7,87 @@ use std::time::Duration;
 
 use anyhow::anyhow;
 use async_trait::async_trait;
-use btleplug::api::{BDAddr, Central, Manager as _, Peripheral as _, ScanFilter};
-use btleplug::platform::Manager;
+use btleplug::api::{BDAddr, ScanFilter};
+use chrono::Utc;
+use futures::{FutureExt, StreamExt};
+use futures::future::join_all;
+use futures::stream::select_all;
 use itertools::Itertools;
 use log::{debug, error, info, warn};
 use mac_address::MacAddress;
-use tokio::sync::RwLock;
-use tokio::time;
-use tokio::time::sleep;
+use serde::Serialize;
+use tokio::sync::{Mutex, RwLock, watch};
+use tokio::time::{Instant, sleep};
+use tokio_util::sync::CancellationToken;
+use uuid::Uuid;
 
-use crate::adaptors::{Adaptor, find_matching_adaptor, FoundDevice};
+use crate::adaptors::{Adaptor, ChannelTransferObject, find_matching_adaptor, FoundDevice, HrmState, predict_adaptor, SENDER, supported_services};
 use crate::adaptors::adaptor_debug::AdaptorDebug;
-use crate::ProgramData;
+use crate::adaptors::capture::session_path;
+use crate::adaptors::advertisement::decode_advertisement;
+use crate::adaptors::reconnect::{ConnectionStats, GiveUp, ReconnectStats, ReconnectStrategy};
+use crate::adaptors::selection::{decide, Decision, Fallback};
+use crate::adaptors::transport::platform::PlatformTransport;
+use crate::adaptors::transport::{BleAdapter, BlePeripheral, Transport};
+use crate::config::{Hrm, MergedConfig};
+use crate::{CANCELLATION_TOKEN, ProgramData};
 use crate::shutdown_handler::{Shutdown, ShutdownHandler};
 use crate::stdin::next_line;
 
 // storage for HRM to be accessible from "outside"
-pub static HRM: LazyLock<HrManager> = LazyLock::new(|| HrManager {
-    connected_device: Arc::default(),
-    hook_registered: AtomicBool::new(false),
-});
+pub static HRM: LazyLock<HrManager> = LazyLock::new(|| HrManager::new(Arc::new(PlatformTransport)));
 
+/// Time between two checks of the advertisements, while watching them
+const ADVERTISEMENT_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Time between two checks for wanted devices, while scanning
+const SCAN_POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Parts of an advertisement, which change with every advertisement received (rssi, manufacturer and service data)
+type Advertisement = (Option<i16>, HashMap<u16, Vec<u8>>, HashMap<Uuid, Vec<u8>>);
+
+/// Remote control of a slot, changed over HTTP; every change closes the current connection.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+struct SlotControl {
+    /// Device chosen remotely; it is preferred over all other devices, even if it is not known
+    requested: Option<MacAddress>,
+    /// The slot stays disconnected, until a device is chosen or reconnected
+    paused: bool,
+}
+
+/// A device found by a scan, as returned by the api.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct ScannedDevice {
+    pub name: String,
+    pub mac: MacAddress,
+    /// signal strength in dBm
+    pub rssi: Option<i16>,
+    /// if the device is stored in `hrm_list`
+    pub known: bool,
+    /// id of the adaptor, which probably fits the device; only known for sure after connecting
+    pub adaptor: Option<u16>,
+    pub connected: bool,
+}
 
 pub struct HrManager {
-    connected_device: Arc<RwLock<Option<Arc<dyn Adaptor>>>>,
+    /// Used to find and connect to devices
+    transport: Arc<dyn Transport>,
+    /// Connected (or last connected) device of each slot
+    ///
+    /// With configured `devices`, there is a slot for each of their mac addresses.
+    /// Otherwise, there is a single slot ([`None`]) for the device chosen at runtime.
+    #[allow(clippy::type_complexity)]
+    connected_devices: Arc<RwLock<HashMap<Option<MacAddress>, Arc<dyn Adaptor>>>>,
+    /// Connection statistics of each slot
+    stats: RwLock<HashMap<Option<MacAddress>, Arc<ConnectionStats>>>,
+    /// Remote control of each slot
+    controls: RwLock<HashMap<Option<MacAddress>, watch::Sender<SlotControl>>>,
+    /// Devices found by the last scan requested remotely
+    scanned: RwLock<Vec<ScannedDevice>>,
+    /// Lock of each adapter (by its description), held while it scans
+    ///
+    /// An adapter runs a single scan; without the lock, the slots would stop each other's scans.
+    scan_locks: Mutex<HashMap<String, Arc<Mutex<()>>>>,
     hook_registered: AtomicBool,
 }
 
@@ -44,8 +101,8 @@ impl Shutdown for HrManager {
 
         shutdown_handler.register_hook(
             Box::new(|| Box::pin(async {
-                if let Some(device) = HRM.connected_device.read().await.as_ref() {
-                    info!("Disconnecting from device...");
+                for device in HRM.connected_devices.read().await.values() {
+                    info!("Disconnecting from device {}...", device.get_addr());
                     let () = device.shutdown().await;
                 }
             }))
@@ -54,124 +111,599 @@ impl Shutdown for HrManager {
 }
 
 impl HrManager {
+    pub fn new(transport: Arc<dyn Transport>) -> Self {
+        Self {
+            transport,
+            connected_devices: Arc::default(),
+            stats: RwLock::default(),
+            controls: RwLock::default(),
+            scanned: RwLock::default(),
+            scan_locks: Mutex::default(),
+            hook_registered: AtomicBool::new(false),
+        }
+    }
+
+    /// Connects to the configured devices (or a single one chosen at runtime) and keeps them connected.
     pub async fn run(&self, program_data: Arc<ProgramData>) {
-        loop {
+        let read = program_data.merged_config.read().await;
+        let devices = read.devices.clone();
+        let debug_device = read.args.debug_device;
+        drop(read);
+
+        if devices.is_empty() || debug_device {
+            if !devices.is_empty() {
+                warn!("Ignoring configured devices, because \"debug device\" is active.");
+            }
+            self.run_slot(&program_data, None).await;
+        } else {
+            info!("Connecting to {} devices: {}", devices.len(), devices.iter().join(", "));
+            join_all(devices.into_iter().map(|mac| self.run_slot(&program_data, Some(mac)))).await;
+        }
+    }
+
+    /// Keeps a device connected.
+    ///
+    /// If `target` is set, only this device is connected; it is paired automatically, if it is not known yet.
+    async fn run_slot(&self, program_data: &Arc<ProgramData>, target: Option<MacAddress>) {
+        let strategy = program_data.merged_config.read().await.reconnect;
+        let stats = self.slot_stats(target).await;
+        let mut control = self.slot_control(target).await;
+        // failed attempts since the last connection
+        let mut failures = 0;
+        // connections of the debug device; each one is captured to its own file
+        let mut debug_sessions = 0;
+        while !CANCELLATION_TOKEN.is_cancelled() {
+            // disconnected on request
+            if control.borrow_and_update().paused {
+                tokio::select! {
+                    () = CANCELLATION_TOKEN.cancelled() => return,
+                    _ = control.changed() => continue,
+                }
+            }
+
             // search for existing devices
-            let devices = match self.search(&program_data).await {
+            let devices = match self.search(program_data, target, true).await {
                 Ok(v) => { v }
                 Err(err) => {
-                    error!("Got error while searching for devices; retrying in 1 second: {}", err);
-                    sleep(Duration::from_secs(1)).await;
+                    error!("Got error while searching for devices: {}", err);
+                    if !retry_later(&strategy, &stats, &mut failures, &mut control).await {
+                        return;
+                    }
                     continue;
                 }
             };
             if devices.is_empty() {
-                warn!("Found no devices at all! Repeating search in 1 second...");
-                sleep(Duration::from_secs(1)).await;
+                warn!("Found no devices at all!");
+                if !retry_later(&strategy, &stats, &mut failures, &mut control).await {
+                    return;
+                }
                 continue;
             }
 
             // let the user choose one (or chose automatically, if configured)
-            let was_connected = self.connected_device.read().await.is_some();
-            let Some(device) = self.choose_device(devices, was_connected, &program_data).await else { continue };
+            let was_connected = self.connected_devices.read().await.contains_key(&target);
+            let chosen = match target {
+                Some(mac) => devices.into_iter().find(|d| d.addr == BDAddr::from(mac.bytes())),
+                None => self.choose_device(devices, was_connected, program_data, &mut control).await,
+            };
+            let Some(device) = chosen else {
+                if let Some(mac) = target {
+                    warn!("Device {mac} was not found!");
+                    if !retry_later(&strategy, &stats, &mut failures, &mut control).await {
+                        return;
+                    }
+                }
+                continue;
+            };
+
+            // disconnected on request while scanning
+            if control.borrow().paused {
+                continue;
+            }
 
             // try to connect
-            if !device.peripheral.is_connected().await.unwrap_or(false) {
-                info!("Trying to connect to {:?}...", device.name);
-                if let Err(err) = device.peripheral.connect().await {
-                    error!("Could not connect to {} because of {:?}!", device.name, err);
-                    continue;
+            if !connect(&device).await {
+                if !retry_later(&strategy, &stats, &mut failures, &mut control).await {
+                    return;
                 }
-            }
-            if !device.peripheral.is_connected().await.unwrap_or(false) {
-                error!("Connection to {} failed; check, that your device is not connected to another host!", device.name);
                 continue;
             }
+            stats.connected();
+            failures = 0;
 
+            let interrupt = device.interrupt.clone();
             if program_data.merged_config.read().await.args.debug_device {
-                match AdaptorDebug::try_wrap(Arc::new(device)).await {
-                    Ok(Some(dev)) => {
-                        if let Err(err) = dev.heartbeat_loop().await {
+                debug_sessions += 1;
+                let capture = program_data.merged_config.read().await.args.capture.as_deref()
+                    .map(|path| Box::from(session_path(path, debug_sessions)));
+                match AdaptorDebug::connect(Arc::new(device), capture).await {
+                    Ok(dev) => {
+                        if let Err(err) = interruptible(dev.heartbeat_loop(), &interrupt, &mut control).await {
                             error!("Error while running heart rate loop for debug device: {err}");
-                        };
-                        continue;
+                        }
                     }
                     Err(err) => {
                         error!("Error while creating debug device: {err}");
                         continue;
                     }
-                    _ => {
+                }
+            } else if let Some(adaptor) = self.set_up(program_data, target, &device).await {
+                if let Err(error) = interruptible(adaptor.heartbeat_loop(), &interrupt, &mut control).await {
+                    error!("Error in heartbeat loop: {error}");
+                }
+            } else {
+                continue;
+            }
+            if interrupt.is_cancelled() {
+                info!("Disconnected on request.");
+            } else {
+                stats.disconnected();
+            }
+        }
+    }
+
+    /// Finds the adaptor for a connected device and stores the device in the config file.
+    ///
+    /// Returns [`None`], if no adaptor fits the device.
+    async fn set_up(&self, program_data: &Arc<ProgramData>, target: Option<MacAddress>, device: &FoundDevice) -> Option<Arc<dyn Adaptor>> {
+        // check if device is compatible
+        let addr = MacAddress::from(device.addr.into_inner());
+        let read = program_data.merged_config.read().await;
+        let known_hrm = read.program_config.hrm_list.iter().find(|d| d.mac == addr).cloned();
+        let definitions = read.program_config.adaptors.clone();
+        drop(read);
+        let adaptor = match find_matching_adaptor(device, known_hrm.as_ref(), &definitions).await {
+            Ok(Some(device)) => {
+                device
+            }
+            Ok(None) => {
+                warn!("No matching adaptor could be found for device {}. You may need to add it to the config manually.", device.name);
+                return None;
+            }
+            Err(error) => {
+                error!("Error while matching adaptor: {error}");
+                return None;
+            }
+        };
+        info!("Found matching peripheral {:?}...", device.name);
+
+        // store new device in config file or update the profile of a known one
+        let hrm = adaptor.to_hrm().await;
+        if device.is_known {
+            program_data.merged_config.write().await.program_config.update_profile(hrm.mac, hrm.profile);
+        } else {
+            program_data.merged_config.write().await.program_config.add_hrm(hrm);
+        }
+        self.connected_devices.write().await.insert(target, Arc::clone(&adaptor));
+        Some(adaptor)
+    }
+
+    /// Returns a receiver for the remote control of a slot, creating the control, if necessary.
+    async fn slot_control(&self, target: Option<MacAddress>) -> watch::Receiver<SlotControl> {
+        self.controls.write().await.entry(target).or_insert_with(|| watch::channel(SlotControl::default()).0).subscribe()
+    }
+
+    /// Returns the statistics of a slot, creating them, if necessary.
+    async fn slot_stats(&self, target: Option<MacAddress>) -> Arc<ConnectionStats> {
+        Arc::clone(self.stats.write().await.entry(target).or_default())
+    }
+
+    /// Returns the connection statistics of each slot with the mac address of its (last) device, ordered by it.
+    ///
+    /// Slots, which were never connected, have no mac address, unless they are configured in `devices`.
+    pub async fn reconnect_stats(&self) -> Vec<(Option<MacAddress>, ReconnectStats)> {
+        let connected = self.connected_devices.read().await;
+        self.stats.read().await.iter()
+            .map(|(target, stats)| (target.or(connected.get(target).map(|d| d.get_addr())), stats.report()))
+            .sorted_by_key(|(mac, _)| mac.map(MacAddress::bytes))
+            .collect()
+    }
+
+    /// Publishes the heart rate broadcast in advertisements, without connecting to any device.
+    ///
+    /// If `devices` are configured, only these are watched; otherwise every device with a decodable advertisement.\
+    /// A device is reported as disconnected, if no advertisement of it was received for `advertisement_timeout`.
+    pub async fn watch_advertisements(&self, program_data: Arc<ProgramData>) {
+        let read = program_data.merged_config.read().await;
+        let devices = read.devices.clone();
+        let timeout = read.advertisement_timeout;
+        let selector = read.adapter.clone();
+        drop(read);
+
+        let adapters = loop {
+            match self.adapters(selector.as_deref()).await {
+                Ok(v) => break v,
+                Err(err) => error!("Got error while searching for adapters; retrying in 1 second: {err}"),
+            }
+            sleep(Duration::from_secs(1)).await;
+        };
+        // the adapters keep scanning, until the program shuts down
+        let mut scan_locks = vec![];
+        let mut streams = vec![];
+        for adapter in &adapters {
+            scan_locks.push(self.scan_lock(adapter).await.lock_owned().await);
+            match adapter.advertisements().await {
+                Ok(stream) => streams.push(stream),
+                Err(err) => warn!("Could not listen for advertisements; only changed advertisements are noticed: {err}"),
+            }
+            if let Err(err) = adapter.start_scan(ScanFilter::default()).await {
+                error!("Could not start scan: {err}");
+            }
+        }
+        info!("Watching advertisements for heart rate...");
+
+        let mut advertisements = select_all(streams);
+        // last advertisement of each device and when an advertisement was received
+        let mut seen: HashMap<MacAddress, (Instant, Advertisement)> = HashMap::new();
+        while !CANCELLATION_TOKEN.is_cancelled() {
+            // devices, whose advertisement was received since the last check; it may repeat the previous one
+            let mut received = HashSet::new();
+            while let Some(Some(address)) = advertisements.next().now_or_never() {
+                received.insert(address);
+            }
+            for adapter in &adapters {
+                let peripherals = match adapter.peripherals().await {
+                    Ok(v) => v,
+                    Err(err) => {
+                        warn!("Could not get peripherals: {err}");
+                        continue;
+                    }
+                };
+                for peripheral in peripherals {
+                    let mac = MacAddress::from(peripheral.address().into_inner());
+                    if !devices.is_empty() && !devices.contains(&mac) {
+                        continue;
+                    }
+                    let Ok(Some(properties)) = peripheral.properties().await else {
                         continue;
+                    };
+                    let advertisement = (properties.rssi, properties.manufacturer_data.clone(), properties.service_data.clone());
+                    let repeated = received.contains(&peripheral.address());
+                    if !repeated && seen.get(&mac).is_some_and(|(_, last)| *last == advertisement) {
+                        continue;
+                    }
+                    let Some((decoder, data)) = decode_advertisement(&properties) else {
+                        continue;
+                    };
+                    if !seen.contains_key(&mac) {
+                        info!(
+                            "Receiving heart rate of {} ({mac}) from advertisements ({decoder}).",
+                            properties.local_name.as_deref().unwrap_or("[peripheral name unknown]")
+                        );
                     }
+                    seen.insert(mac, (Instant::now(), advertisement));
+                    let _ = SENDER.send(ChannelTransferObject {
+                        timestamp: Utc::now(),
+                        device: Some(mac),
+                        hr_state: Some(HrmState::Ok(data)),
+                    });
                 }
             }
 
-            // check if device is compatible
-            let addr = MacAddress::from(device.addr.into_inner());
-            let adaptor = match find_matching_adaptor(
-                &device,
-                program_data
-                    .merged_config
-                    .read()
-                    .await
-                    .program_config
-                    .hrm_list
-                    .iter()
-                    .find(|d| d.mac == addr)
-            ).await {
-                Ok(Some(device)) => {
-                    device
-                }
-                Ok(None) => {
-                    warn!("No matching adaptor could be found for device {}. You may need to add it to the config manually.", device.name);
-                    continue;
-                }
-                Err(error) => {
-                    error!("Error while matching adaptor: {error}");
-                    continue;
+            // devices, which stopped advertising
+            seen.retain(|mac, (last_received, _)| {
+                if last_received.elapsed() < timeout {
+                    return true;
                 }
-            };
-            info!("Found matching peripheral {:?}...", device.name);
+                info!("No advertisement of {mac} for {} seconds; disconnected.", timeout.as_secs());
+                let _ = SENDER.send(ChannelTransferObject {
+                    timestamp: Utc::now(),
+                    device: Some(*mac),
+                    hr_state: Some(HrmState::Disconnected),
+                });
+                false
+            });
+            sleep(ADVERTISEMENT_POLL_INTERVAL).await;
+        }
+        for adapter in &adapters {
+            if let Err(err) = adapter.stop_scan().await {
+                warn!("Could not stop scan: {err}");
+            }
+        }
+        drop(scan_locks);
+    }
 
-            // store new device in config file
-            if !device.is_known {
-                program_data.merged_config.write().await.program_config.add_hrm(adaptor.to_hrm().await);
+    /// Returns the connected (or last connected) device.
+    ///
+    /// If several devices are configured, the first one connected is returned.
+    pub async fn connected_hrm(&self) -> Option<Hrm> {
+        self.connected_hrms().await.into_iter().next()
+    }
+
+    /// Returns all connected (or last connected) devices, ordered by mac address.
+    pub async fn connected_hrms(&self) -> Vec<Hrm> {
+        let mut hrms = vec![];
+        for device in self.connected_devices.read().await.values() {
+            hrms.push(device.to_hrm().await);
+        }
+        hrms.sort_by_key(|h| h.mac.bytes());
+        hrms
+    }
+
+    /// Resets the energy expended counter of all connected devices supporting it.
+    ///
+    /// A device failing to reset does not stop the others; all errors are returned together.
+    pub async fn reset_energy_expended(&self) -> anyhow::Result<()> {
+        let devices = self.connected_devices.read().await;
+        if devices.is_empty() {
+            return Err(anyhow!("No device connected"));
+        }
+        let supported = devices.values().filter(|d| d.supports_energy_reset()).collect::<Vec<_>>();
+        if supported.is_empty() {
+            return Err(anyhow!("No connected device supports resetting energy expended"));
+        }
+        let mut errors = vec![];
+        for device in supported {
+            if let Err(err) = device.reset_energy_expended().await {
+                errors.push(format!("{}: {err}", device.get_addr()));
             }
-            let clone = Arc::clone(&adaptor);
-            *self.connected_device.write().await = Some(adaptor);
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(anyhow!(errors.join("; ")))
+        }
+    }
+
+    /// Resets the energy expended counter of the connected device `mac`.
+    pub async fn reset_device_energy_expended(&self, mac: MacAddress) -> anyhow::Result<()> {
+        let devices = self.connected_devices.read().await;
+        let device = devices.values().find(|d| d.get_addr() == mac).ok_or(anyhow!("Device {mac} is not connected"))?;
+        device.reset_energy_expended().await
+    }
 
-            if let Err(error) = clone.heartbeat_loop().await {
-                error!("Error in heartbeat loop: {error}");
+    /// Scans for all devices in range, without connecting to any of them; the result is kept for
+    /// [`HrManager::scanned_devices`].
+    ///
+    /// Like the scans of the slots, it waits for other scans of the same adapter to finish.
+    pub async fn scan(&self, program_data: &Arc<ProgramData>) -> anyhow::Result<Vec<ScannedDevice>> {
+        let devices = self.search(program_data, None, false).await?;
+        let read = program_data.merged_config.read().await;
+        let mut scanned = vec![];
+        for device in &devices {
+            let mac = MacAddress::from(device.addr.into_inner());
+            let hrm = read.program_config.hrm_list.iter().find(|h| h.mac == mac);
+            scanned.push(ScannedDevice {
+                name: device.name.clone(),
+                mac,
+                rssi: device.properties.rssi,
+                known: hrm.is_some(),
+                adaptor: predict_adaptor(device, hrm, &read.program_config.adaptors),
+                connected: device.peripheral.is_connected().await.unwrap_or(false),
+            });
+        }
+        drop(read);
+        self.scanned.write().await.clone_from(&scanned);
+        Ok(scanned)
+    }
+
+    /// Returns the devices found by the last [`HrManager::scan`].
+    pub async fn scanned_devices(&self) -> Vec<ScannedDevice> {
+        self.scanned.read().await.clone()
+    }
+
+    /// Returns the slot, which connects (or is connected) to `mac`.
+    async fn slot_of(&self, mac: MacAddress) -> Option<Option<MacAddress>> {
+        let controls = self.controls.read().await;
+        if controls.contains_key(&Some(mac)) {
+            return Some(Some(mac));
+        }
+        let connected = self.connected_devices.read().await.get(&None).map(|d| d.get_addr());
+        let requested = controls.get(&None).and_then(|c| c.borrow().requested);
+        (connected == Some(mac) || requested == Some(mac)).then_some(None)
+    }
+
+    /// Changes the remote control of the slot `target`; the current connection of the slot is closed.
+    async fn control(&self, target: Option<MacAddress>, modify: impl FnOnce(&mut SlotControl)) -> anyhow::Result<()> {
+        let controls = self.controls.read().await;
+        let control = controls.get(&target).ok_or(anyhow!("No device is connected"))?;
+        control.send_modify(modify);
+        Ok(())
+    }
+
+    /// Connects to `mac` instead of the current device, even if it is not known yet.
+    ///
+    /// If several devices are configured, only these can be connected.
+    pub async fn connect(&self, mac: MacAddress) -> anyhow::Result<()> {
+        let controls = self.controls.read().await;
+        let target = if controls.contains_key(&Some(mac)) {
+            Some(mac)
+        } else if controls.contains_key(&None) {
+            None
+        } else {
+            return Err(anyhow!("Device {mac} is not configured"));
+        };
+        drop(controls);
+        info!("Connecting to {mac} on request...");
+        self.control(target, |c| {
+            c.requested = Some(mac);
+            c.paused = false;
+        }).await
+    }
+
+    /// Disconnects from `mac`; it stays disconnected, until it is connected or reconnected again.
+    pub async fn disconnect(&self, mac: MacAddress) -> anyhow::Result<()> {
+        let target = self.slot_of(mac).await.ok_or(anyhow!("Device {mac} is not connected"))?;
+        info!("Disconnecting from {mac} on request...");
+        self.control(target, |c| c.paused = true).await
+    }
+
+    /// Closes the connection to `mac` and connects to it again at once.
+    pub async fn reconnect(&self, mac: MacAddress) -> anyhow::Result<()> {
+        let target = self.slot_of(mac).await.ok_or(anyhow!("Device {mac} is not connected"))?;
+        info!("Reconnecting to {mac} on request...");
+        self.control(target, |c| {
+            c.requested = Some(mac);
+            c.paused = false;
+        }).await
+    }
+
+    /// Removes `mac` from the known devices and disconnects from it.
+    pub async fn forget(&self, mac: MacAddress, program_data: &Arc<ProgramData>) -> anyhow::Result<()> {
+        let removed = program_data.merged_config.write().await.program_config.remove_hrm(mac);
+        let slot = self.slot_of(mac).await;
+        if !removed && slot.is_none() {
+            return Err(anyhow!("Device {mac} is not known"));
+        }
+        let Some(target) = slot else {
+            return Ok(());
+        };
+        let current = self.connected_devices.read().await.get(&target).map(|d| d.get_addr());
+        if target == Some(mac) || current == Some(mac) {
+            // a configured device stays disconnected; otherwise another device is chosen
+            self.connected_devices.write().await.remove(&target);
+            self.control(target, |c| {
+                c.requested = None;
+                c.paused = target.is_some();
+            }).await?;
+        } else if let Some(control) = self.controls.read().await.get(&target) {
+            // only requested; the current device of the slot stays connected
+            control.send_if_modified(|c| {
+                c.requested = None;
+                false
+            });
+        }
+        Ok(())
+    }
+
+    /// Scans once and prints, which device would be chosen and why, without connecting to it.
+    pub async fn explain_selection(&self, program_data: &Arc<ProgramData>) -> anyhow::Result<()> {
+        let devices = self.search(program_data, None, true).await?;
+        let read = program_data.merged_config.read().await;
+        let known = read.program_config.hrm_list.iter().map(|h| h.mac).collect::<Vec<_>>();
+        let policy = read.program_config.selection.as_ref();
+        let (decision, reasons) = decide(&devices, read.args.accept_new_device, policy, &known)?;
+
+        println!("Found {} devices{}:", devices.len(), if policy.is_some() { "" } else { " (no selection policy configured)" });
+        for reason in reasons {
+            println!("  {reason}");
+        }
+        match decision {
+            Decision::Chosen(index) => {
+                if let Some(device) = devices.get(index) {
+                    println!("Would connect to {} ({}).", device.name, device.addr);
+                }
             }
+            Decision::Fallback(Fallback::Wait) => println!("Would ask which device to connect to."),
+            Decision::Fallback(Fallback::Rescan) => println!("Would scan again."),
+            Decision::Fallback(Fallback::Fail) => println!("Would shut down."),
         }
+        Ok(())
     }
 
-    async fn search(&self, program_data: &Arc<ProgramData>) -> anyhow::Result<Vec<FoundDevice>> {
+    /// Returns the adapters to scan with: the one chosen by `selector` or all, if it is [`None`].
+    ///
+    /// `selector` is the index of the adapter (first is 1) or a part of its name (case insensitive).
+    async fn adapters(&self, selector: Option<&str>) -> anyhow::Result<Vec<Arc<dyn BleAdapter>>> {
+        let adapters = self.transport.adapters().await?;
+        if adapters.is_empty() {
+            return Err(anyhow!("No Bluetooth adapters found"));
+        }
+        let Some(selector) = selector else {
+            return Ok(adapters);
+        };
+        let index = select_adapter(&adapters, selector).await?;
+        Ok(adapters.into_iter().skip(index).take(1).collect())
+    }
+
+    /// Returns the lock, which must be held while `adapter` scans.
+    async fn scan_lock(&self, adapter: &Arc<dyn BleAdapter>) -> Arc<Mutex<()>> {
+        let info = adapter.info().await.unwrap_or_default();
+        Arc::clone(self.scan_locks.lock().await.entry(info).or_default())
+    }
+
+    /// Scans with `adapter` for `duration`; waits for other scans of the adapter to finish first.
+    async fn scan_adapter(
+        &self,
+        adapter: &Arc<dyn BleAdapter>,
+        filter: ScanFilter,
+        duration: Duration,
+        wanted: &[BDAddr],
+    ) -> anyhow::Result<Vec<Arc<dyn BlePeripheral>>> {
+        let scan_lock = self.scan_lock(adapter).await;
+        let _scanning = scan_lock.lock().await;
+        info!("Starting scan for devices...");
+        adapter.start_scan(filter).await?;
+        let peripherals = scan(adapter, duration, wanted).await;
+        if let Err(err) = adapter.stop_scan().await {
+            warn!("Could not stop scan: {err}");
+        }
+        peripherals
+    }
+
+    /// Checks, that `selector` chooses one of the Bluetooth adapters.
+    pub async fn check_adapter(&self, selector: &str) -> anyhow::Result<()> {
+        self.adapters(Some(selector)).await.map(|_| ())
+    }
+
+    /// Prints all Bluetooth adapters and marks the ones used with `selector`.
+    pub async fn list_adapters(&self, selector: Option<&str>) -> anyhow::Result<()> {
+        let adapters = self.transport.adapters().await?;
+        let selected = match selector {
+            Some(selector) => match select_adapter(&adapters, selector).await {
+                Ok(index) => Some(index),
+                Err(err) => {
+                    warn!("{err}");
+                    None
+                }
+            },
+            None => None,
+        };
+        println!("Found {} Bluetooth adapters:", adapters.len());
+        println!("{0: <10} | {1: <10} | Adapter", "Index", "Used");
+        for (i, adapter) in adapters.iter().enumerate() {
+            let used = selector.is_none() || selected == Some(i);
+            println!("{0: <10} | {1: <10} | {2}", i + 1, if used { "yes" } else { "no" }, adapter.info().await?);
+        }
+        Ok(())
+    }
+
+    /// Returns the devices requested for the slot `target`: the device of the slot, the pinned one or the one
+    /// requested with "hrm-mac" or "hrm-index".
+    async fn requested_devices(&self, config: &MergedConfig, target: Option<MacAddress>) -> HashSet<MacAddress> {
         let mut filter: HashSet<MacAddress> = HashSet::default();
-        let read = program_data.merged_config.read().await;
 
         // check rules
+        // device of this slot
+        if let Some(mac) = target {
+            filter.insert(mac);
+        }
+        // device chosen remotely
+        if filter.is_empty() {
+            if let Some(mac) = self.controls.read().await.get(&target).and_then(|c| c.borrow().requested) {
+                filter.insert(mac);
+            }
+        }
         // pinned device
-        if read.args.pin_device {
-            if let Some(device) = self.connected_device.read().await.as_ref() {
+        if config.args.pin_device && filter.is_empty() {
+            if let Some(device) = self.connected_devices.read().await.get(&target) {
                 filter.insert(device.get_addr());
             }
         }
 
         // device not pinned
         if filter.is_empty() {
-            if read.args.accept_new_device {
+            if config.args.accept_new_device {
                 // check mac address
-                if let Some(mac) = read.args.hrm_mac {
+                if let Some(mac) = config.args.hrm_mac {
                     filter.insert(mac);
                 }
-            } else if let Some(index) = read.args.hrm_index {
+            } else if let Some(index) = config.args.hrm_index {
                 // device index
-                if let Some(device) = read.program_config.hrm_list.get((index - 1) as usize) {
+                if let Some(device) = config.program_config.hrm_list.get((index - 1) as usize) {
                     filter.insert(device.mac);
                 }
             }
         }
+        filter
+    }
+
+    /// Scans for devices; requested devices come first, known devices next.
+    ///
+    /// With `stop_early`, the scan ends as soon as a requested (or known) device is found.
+    pub(super) async fn search(&self, program_data: &Arc<ProgramData>, target: Option<MacAddress>, stop_early: bool) -> anyhow::Result<Vec<FoundDevice>> {
+        let read = program_data.merged_config.read().await;
+        let filter = self.requested_devices(&read, target).await;
         let filter_bdaddr: Vec<BDAddr> = filter.iter().map(|a| BDAddr::from(a.bytes())).collect();
 
         let known_bdaddr: Vec<BDAddr> = read
@@ -180,23 +712,41 @@ impl HrManager {
             .iter()
             .map(|d| BDAddr::from(d.mac.bytes()))
             .collect();
+        let wheel_circumferences: HashMap<BDAddr, f32> = read
+            .program_config
+            .hrm_list
+            .iter()
+            .filter_map(|d| Some((BDAddr::from(d.mac.bytes()), d.wheel_circumference?)))
+            .collect();
+        // the scan ends, as soon as the requested device (or any known device, if none was requested) is found
+        let wanted = if !stop_early {
+            vec![]
+        } else if !filter_bdaddr.is_empty() {
+            filter_bdaddr.clone()
+        } else if read.args.accept_new_device {
+            vec![]
+        } else {
+            known_bdaddr.clone()
+        };
+        // the debug device may be any device
+        let services = if read.filter_scan && !read.args.debug_device {
+            supported_services(&read.program_config.adaptors)
+        } else {
+            None
+        };
+        let scan_filter = ScanFilter { services: services.unwrap_or_default() };
+        let scan_duration = read.scan_duration;
+        let reconnect = read.reconnect;
+        let selector = read.adapter.clone();
         drop(read);
 
         let mut found: Vec<FoundDevice> = vec![];
+        let stats = self.stats.read().await.get(&target).cloned().unwrap_or_default();
 
-        let manager = Manager::new().await?;
-        let adapter_list = manager.adapters().await?;
-        if adapter_list.is_empty() {
-            return Err(anyhow!("No Bluetooth adapters found"));
-        }
+        let adapter_list = self.adapters(selector.as_deref()).await?;
 
         for adapter in &adapter_list {
-            info!("Starting scan for devices...");
-            adapter
-                .start_scan(ScanFilter::default())
-                .await?;
-            time::sleep(Duration::from_secs(2)).await;
-            let peripherals = adapter.peripherals().await?;
+            let peripherals = self.scan_adapter(adapter, scan_filter.clone(), scan_duration, &wanted).await?;
             if peripherals.is_empty() {
                 warn!("Did not find any devices (unfiltered). Make sure your device is visible!");
                 continue;
@@ -217,10 +767,15 @@ impl HrManager {
                     FoundDevice {
                         name: local_name,
                         addr: peripheral.address(),
-                        peripheral: peripheral.clone(),
+                        peripheral: Arc::clone(peripheral),
                         is_known: known_bdaddr.contains(&peripheral.address()),
                         filtered: filter_bdaddr.contains(&peripheral.address()),
                         properties: clone,
+                        reconnect,
+                        stats: Arc::clone(&stats),
+                        interrupt: CancellationToken::new(),
+                        reconnected: Arc::default(),
+                        wheel_circumference: wheel_circumferences.get(&peripheral.address()).copied(),
                     }
                 );
             }
@@ -255,23 +810,42 @@ impl HrManager {
         devices: Vec<FoundDevice>,
         is_reconnect: bool,
         program_data: &Arc<ProgramData>,
+        control: &mut watch::Receiver<SlotControl>,
     ) -> Option<FoundDevice> {
-        let read = program_data.merged_config.read().await;
+        // device chosen remotely; scan again, until it is found
+        // the change must not be marked as seen, so a later change (like a disconnect) still closes the connection
+        if let Some(mac) = control.borrow().requested {
+            return devices.into_iter().find(|d| d.addr == BDAddr::from(mac.bytes()));
+        }
 
         // device found automatically
-        let first = devices.first()?;
-        if read.args.accept_new_device {
-            let first_device = first;
-            if first_device.filtered {
-                return Some(first_device.clone());
+        let read = program_data.merged_config.read().await;
+        let known = read.program_config.hrm_list.iter().map(|h| h.mac).collect::<Vec<_>>();
+        let decided = decide(&devices, read.args.accept_new_device, read.program_config.selection.as_ref(), &known);
+        drop(read);
+        let (decision, reasons) = match decided {
+            Ok(v) => v,
+            Err(err) => {
+                error!("Could not apply the selection policy: {err}");
+                return None;
             }
-        } else {
-            let first_device = first;
-            if first_device.is_known {
-                return Some(first_device.clone());
+        };
+        for reason in reasons {
+            debug!("{reason}");
+        }
+        match decision {
+            Decision::Chosen(index) => return devices.into_iter().nth(index),
+            Decision::Fallback(Fallback::Rescan) => {
+                info!("No device fulfills the selection policy; rescanning...");
+                return None;
             }
+            Decision::Fallback(Fallback::Fail) => {
+                error!("No device fulfills the selection policy; shutting down!");
+                CANCELLATION_TOKEN.cancel();
+                return None;
+            }
+            Decision::Fallback(Fallback::Wait) => {}
         }
-        drop(read);
 
         // choose manually
         let mut first_run = true;
@@ -284,7 +858,7 @@ impl HrManager {
             } else {
                 timeout = None;
                 timeout_hint = "";
-            };
+            }
 
             if first_run {
                 println!("Device to connect to could not be determined automatically. Please select{timeout_hint}:");
@@ -301,7 +875,15 @@ impl HrManager {
             print!("Choose: ");
 
             let _ = io::stdout().flush();
-            match next_line(true, timeout).await {
+            let line = tokio::select! {
+                line = next_line(true, timeout) => line,
+                _ = control.changed() => {
+                    println!();
+                    info!("The connection was changed remotely.");
+                    return None;
+                }
+            };
+            match line {
                 None => {
                     return None;
                 }
@@ -317,9 +899,110 @@ impl HrManager {
                         return devices.get((number - 1) as usize).cloned();
                     }
                     println!("Invalid input, please try again!");
-                    continue;
                 }
             }
         }
     }
 }
+
+/// Returns the position of the adapter chosen by `selector`: its index (first is 1) or a part of its name.
+async fn select_adapter(adapters: &[Arc<dyn BleAdapter>], selector: &str) -> anyhow::Result<usize> {
+    if let Ok(index) = selector.parse::<usize>() {
+        if !(1..=adapters.len()).contains(&index) {
+            return Err(anyhow!("Adapter index {index} is out of range (1 - {})", adapters.len()));
+        }
+        return Ok(index - 1);
+    }
+    let lowercase = selector.to_lowercase();
+    for (position, adapter) in adapters.iter().enumerate() {
+        if adapter.info().await?.to_lowercase().contains(&lowercase) {
+            return Ok(position);
+        }
+    }
+    Err(anyhow!("No Bluetooth adapter matches \"{selector}\""))
+}
+
+/// Collects the peripherals found by a running scan within `duration`.
+///
+/// Returns early, as soon as one of the `wanted` devices is found.
+async fn scan(adapter: &Arc<dyn BleAdapter>, duration: Duration, wanted: &[BDAddr]) -> anyhow::Result<Vec<Arc<dyn BlePeripheral>>> {
+    let start = Instant::now();
+    let deadline = start + duration;
+    loop {
+        let peripherals = adapter.peripherals().await?;
+        if let Some(peripheral) = peripherals.iter().find(|p| wanted.contains(&p.address())) {
+            debug!("Found {} after {} ms; stopping scan.", peripheral.address(), start.elapsed().as_millis());
+            return Ok(peripherals);
+        }
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Ok(peripherals);
+        }
+        sleep(remaining.min(SCAN_POLL_INTERVAL)).await;
+    }
+}
+
+/// Connects to the device, if it is not connected yet; returns, if it is connected.
+async fn connect(device: &FoundDevice) -> bool {
+    if !device.peripheral.is_connected().await.unwrap_or(false) {
+        info!("Trying to connect to {:?}...", device.name);
+        if let Err(err) = device.peripheral.connect().await {
+            error!("Could not connect to {} because of {:?}!", device.name, err);
+            return false;
+        }
+    }
+    if !device.peripheral.is_connected().await.unwrap_or(false) {
+        error!("Connection to {} failed; check, that your device is not connected to another host!", device.name);
+        return false;
+    }
+    true
+}
+
+/// Records a failed attempt and waits with backoff before the next one; a remote change of the slot ends the wait.
+///
+/// Returns `false`, if the lost device of the slot is given up instead.
+async fn retry_later(
+    strategy: &ReconnectStrategy,
+    stats: &ConnectionStats,
+    failures: &mut u32,
+    control: &mut watch::Receiver<SlotControl>,
+) -> bool {
+    stats.failed();
+    *failures += 1;
+    if stats.is_lost() && strategy.give_up_after.is_some_and(|max| *failures >= max) {
+        stats.given_up();
+        match strategy.give_up {
+            GiveUp::Stop => error!("Could not reconnect after {failures} attempts; giving up!"),
+            GiveUp::Shutdown => {
+                error!("Could not reconnect after {failures} attempts; shutting down!");
+                CANCELLATION_TOKEN.cancel();
+            }
+        }
+        return false;
+    }
+    let backoff = strategy.backoff(*failures);
+    info!("Retrying in {:.1} seconds...", backoff.as_secs_f64());
+    tokio::select! {
+        () = sleep(backoff) => {}
+        _ = control.changed() => {}
+    }
+    true
+}
+
+/// Runs a heartbeat loop, until the connection is lost for good.
+///
+/// A remote change of the slot cancels `interrupt`, so the loop closes the connection without reconnecting.
+async fn interruptible(
+    heartbeat: impl std::future::Future<Output = anyhow::Result<()>>,
+    interrupt: &CancellationToken,
+    control: &mut watch::Receiver<SlotControl>,
+) -> anyhow::Result<()> {
+    tokio::pin!(heartbeat);
+    tokio::select! {
+        result = &mut heartbeat => result,
+        _ = control.changed() => {
+            interrupt.cancel();
+            heartbeat.await
+        }
+    }
+}
@@ -1,26 +1,185 @@
 use std::{future::Future, pin::Pin, sync::LazyLock};
-use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
 use std::sync::Arc;
 use async_trait::async_trait;
-use btleplug::api::{BDAddr, PeripheralProperties};
-use btleplug::platform::Peripheral;
+use btleplug::api::{BDAddr, Characteristic, CharPropFlags, PeripheralProperties, ValueNotification};
 use chrono::{DateTime, Utc};
+use futures::StreamExt;
+use itertools::Itertools;
+use log::{debug, error, info, warn};
 use mac_address::MacAddress;
 use serde::Serialize;
+use tokio::sync::{Notify, RwLock};
 use tokio::sync::broadcast::{channel, Receiver, Sender};
+use tokio::time::{sleep, timeout};
+use tokio_util::sync::CancellationToken;
+use uuid::Uuid;
 
-use crate::config::Hrm;
+use crate::adaptors::declarative::{AdaptorDefinition, DeclarativeAdaptor};
+use crate::adaptors::reconnect::{ConnectionStats, ReconnectStrategy};
+use crate::adaptors::transport::BlePeripheral;
+use crate::config::{BodySensorLocation, DeviceProfile, Hrm};
 use crate::ProgramData;
 
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 
 pub mod type_1;
+pub mod type_2;
+pub mod type_3;
+pub mod type_4;
+pub mod type_5;
+pub mod type_6;
+pub mod type_7;
 pub mod hrm;
+pub mod transport;
+pub mod declarative;
 mod adaptor_debug;
+pub mod advertisement;
+pub mod capture;
+mod explorer;
+mod parse;
+pub mod reconnect;
+pub mod selection;
+#[cfg(test)]
+mod tests;
 
-static ADAPTORS: LazyLock<HashMap<u16, GetAdaptorFn>> = LazyLock::new(|| HashMap::from([
-    (1_u16, Box::new(type_1::Adaptor1::try_wrap) as _)
-]));
+const HEART_RATE_SERVICE: Uuid = Uuid::from_u128(0x0000180d_0000_1000_8000_00805f9b34fb);
+const RUNNING_SPEED_AND_CADENCE_SERVICE: Uuid = Uuid::from_u128(0x00001814_0000_1000_8000_00805f9b34fb);
+const CYCLING_SPEED_AND_CADENCE_SERVICE: Uuid = Uuid::from_u128(0x00001816_0000_1000_8000_00805f9b34fb);
+const CYCLING_POWER_SERVICE: Uuid = Uuid::from_u128(0x00001818_0000_1000_8000_00805f9b34fb);
+const PULSE_OXIMETER_SERVICE: Uuid = Uuid::from_u128(0x00001822_0000_1000_8000_00805f9b34fb);
+const FITNESS_MACHINE_SERVICE: Uuid = Uuid::from_u128(0x00001826_0000_1000_8000_00805f9b34fb);
+
+/// All built-in adaptors in priority order; if several adaptors fit a device equally well, the first one is used.
+static ADAPTORS: LazyLock<Vec<RegisteredAdaptor>> = LazyLock::new(|| vec![
+    // fitness machines often provide the heart rate service as well; adaptor 7 publishes both
+    RegisteredAdaptor {
+        id: 7,
+        description: "Fitness Machine service (0x1826) with treadmill, indoor bike or rower data, including the heart rate (0x180D) if present",
+        services: &[FITNESS_MACHINE_SERVICE],
+        rate: type_7::Adaptor7::rate,
+        try_wrap: Box::new(type_7::Adaptor7::try_wrap),
+    },
+    RegisteredAdaptor {
+        id: 1,
+        description: "Heart Rate service (0x180D), including running speed and cadence (0x1814) if present",
+        services: &[HEART_RATE_SERVICE],
+        rate: type_1::Adaptor1::rate,
+        try_wrap: Box::new(type_1::Adaptor1::try_wrap),
+    },
+    RegisteredAdaptor {
+        id: 2,
+        description: "Running Speed and Cadence service (0x1814), like footpods",
+        services: &[RUNNING_SPEED_AND_CADENCE_SERVICE],
+        rate: type_2::Adaptor2::rate,
+        try_wrap: Box::new(type_2::Adaptor2::try_wrap),
+    },
+    RegisteredAdaptor {
+        id: 3,
+        description: "Cycling Power service (0x1818), like power meters and smart trainers",
+        services: &[CYCLING_POWER_SERVICE],
+        rate: type_3::Adaptor3::rate,
+        try_wrap: Box::new(type_3::Adaptor3::try_wrap),
+    },
+    RegisteredAdaptor {
+        id: 4,
+        description: "Cycling Speed and Cadence service (0x1816)",
+        services: &[CYCLING_SPEED_AND_CADENCE_SERVICE],
+        rate: type_4::Adaptor4::rate,
+        try_wrap: Box::new(type_4::Adaptor4::try_wrap),
+    },
+    RegisteredAdaptor {
+        id: 5,
+        description: "Pulse Oximeter service (0x1822)",
+        services: &[PULSE_OXIMETER_SERVICE],
+        rate: type_5::Adaptor5::rate,
+        try_wrap: Box::new(type_5::Adaptor5::try_wrap),
+    },
+    RegisteredAdaptor {
+        id: 6,
+        description: "Polar Measurement Data service with raw ECG and accelerometer streams, like the Polar H10",
+        services: &[HEART_RATE_SERVICE],
+        rate: type_6::Adaptor6::rate,
+        try_wrap: Box::new(type_6::Adaptor6::try_wrap),
+    },
+]);
+
+/// A built-in adaptor.
+struct RegisteredAdaptor {
+    id: u16,
+    /// Shown when listing all adaptors
+    description: &'static str,
+    /// Advertised services of the devices this adaptor supports; scans are filtered by them
+    services: &'static [Uuid],
+    rate: fn(&FoundDevice) -> Result<MatchConfidence, String>,
+    try_wrap: GetAdaptorFn,
+}
+
+/// Returns the built-in adaptor with `id`.
+fn get_adaptor(id: u16) -> Option<&'static RegisteredAdaptor> {
+    ADAPTORS.iter().find(|a| a.id == id)
+}
+
+/// Describes all built-in adaptors and the adaptors in `definitions`, one per line.
+pub fn describe_adaptors(definitions: &[AdaptorDefinition]) -> String {
+    let mut lines = vec!["Built-in adaptors (in priority order):".to_owned()];
+    lines.extend(ADAPTORS.iter().map(|a| format!("  {}: {}", a.id, a.description)));
+    if !definitions.is_empty() {
+        lines.push("Adaptors from the config file:".to_owned());
+        lines.extend(definitions.iter().map(|d| format!("  {}: {}", d.adaptor_id, d.describe())));
+    }
+    lines.push("Advertisement decoders (for \"advertisements\"):".to_owned());
+    lines.extend(advertisement::DECODERS.iter().map(|d| format!("  {}: {}", d.id, d.description)));
+    lines.join("\n")
+}
+
+/// Returns the services a device must advertise to be supported by any adaptor, to filter scans by.
+///
+/// Returns [`None`], if a definition may match devices regardless of their services, so scans must not be filtered.
+pub fn supported_services(definitions: &[AdaptorDefinition]) -> Option<Vec<Uuid>> {
+    let mut services = ADAPTORS.iter().flat_map(|a| a.services.iter().copied()).collect::<Vec<_>>();
+    for definition in definitions {
+        if definition.match_rules.services.is_empty() {
+            return None;
+        }
+        services.extend(&definition.match_rules.services);
+    }
+    Some(services.into_iter().unique().collect())
+}
+
+/// Guesses the adaptor for a device from its advertisement, without connecting to it.
+///
+/// This is the adaptor configured for a known device, the first definition whose rules match the advertisement or the
+/// first built-in adaptor supporting an advertised service. The adaptor used after connecting may differ, because only
+/// then the characteristics of the device are known.
+fn predict_adaptor(device: &FoundDevice, hrm: Option<&Hrm>, definitions: &[AdaptorDefinition]) -> Option<u16> {
+    hrm.and_then(|h| h.adaptor_id)
+        .or_else(|| definitions.iter().find(|d| d.matches_advertisement(device)).map(|d| d.adaptor_id))
+        .or_else(|| ADAPTORS.iter().find(|a| a.services.iter().any(|s| device.properties.services.contains(s))).map(|a| a.id))
+}
+
+/// How well an adaptor fits a device; the adaptor with the highest confidence is used.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum MatchConfidence {
+    /// The device provides a standard service the adaptor supports
+    Generic,
+    /// The adaptor is made for this device model or its vendor specific features
+    Model,
+}
+
+impl Display for MatchConfidence {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MatchConfidence::Generic => write!(f, "generic"),
+            MatchConfidence::Model => write!(f, "model"),
+        }
+    }
+}
+
+/// Checks, if the discovered services of `device` contain the characteristic `uuid` with any of `flags`.
+fn has_characteristic(device: &FoundDevice, uuid: Uuid, flags: CharPropFlags) -> bool {
+    device.peripheral.characteristics().iter().any(|c| c.uuid == uuid && c.properties.intersects(flags))
+}
 
 // subscribe to this to get updates on HR data
 pub static SENDER: LazyLock<Sender<ChannelTransferObject>> = LazyLock::new(|| channel::<ChannelTransferObject>(256).0);
@@ -29,26 +188,151 @@ pub static SENDER: LazyLock<Sender<ChannelTransferObject>> = LazyLock::new(|| ch
 pub type BoxFuture<T> = Pin<Box<dyn Future<Output=T> + Send>>;
 type GetAdaptorFn = Box<dyn Fn(Arc<FoundDevice>) -> BoxFuture<Result<Option<Arc<dyn Adaptor>>>> + Send + Sync>;
 
+// subscribe to this to get raw samples (ECG, accelerometer) of devices streaming them
+// this channel has a much higher rate than `SENDER`, so it is kept separate
+pub static RAW_SENDER: LazyLock<Sender<RawTransferObject>> = LazyLock::new(|| channel::<RawTransferObject>(1024).0);
+
 /// use this to get a receiver for `SENDER`, which notifies you about new data
 pub fn get_receiver() -> Receiver<ChannelTransferObject> {
     SENDER.subscribe()
 }
 
+/// use this to get a receiver for `RAW_SENDER`, which notifies you about new raw samples
+pub fn get_raw_receiver() -> Receiver<RawTransferObject> {
+    RAW_SENDER.subscribe()
+}
+
 /// contains update data sent through the channel for all receivers
 #[derive(Clone, Serialize)]
 #[serde(rename_all = "snake_case")]
 pub struct ChannelTransferObject {
     pub timestamp: DateTime<Utc>,
+    /// mac address of the device, which sent the data; [`None`] for simulated or replayed data
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub device: Option<MacAddress>,
     pub hr_state: Option<HrmState>,
 }
 
+/// raw samples sent through `RAW_SENDER`
+#[derive(Clone, Serialize, Debug)]
+#[serde(rename_all = "snake_case")]
+pub struct RawTransferObject {
+    /// time the samples were received
+    pub timestamp: DateTime<Utc>,
+    /// mac address of the device, which sent the samples
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub device: Option<MacAddress>,
+    pub measurement: RawMeasurement,
+    /// Samples per second
+    pub sample_rate: u16,
+    /// Time of the last sample in ns, as reported by the device
+    pub sensor_timestamp: u64,
+    /// One entry per sample, each containing one value per channel (ECG: µV; accelerometer: x, y and z in mG)
+    pub samples: Vec<Vec<i32>>,
+}
+
+/// kind of raw samples
+#[derive(Clone, Copy, Serialize, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum RawMeasurement {
+    Ecg,
+    Acc,
+}
+
+impl RawMeasurement {
+    pub fn name(self) -> &'static str {
+        match self {
+            RawMeasurement::Ecg => "ecg",
+            RawMeasurement::Acc => "acc",
+        }
+    }
+}
+/// Type of a fitness machine
+#[derive(Debug, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum MachineKind {
+    Treadmill,
+    IndoorBike,
+    Rower,
+}
+
+/// values of the Fitness Machine service
+#[derive(Debug, Serialize, Clone, Default)]
+#[serde(rename_all = "snake_case")]
+pub struct MachineData {
+    /// Type of the machine, which sent the last data
+    pub kind: Option<MachineKind>,
+    /// Speed in m/s; derived from the pace for rowers
+    pub speed: Option<f32>,
+    /// Inclination in % (treadmills)
+    pub incline: Option<f32>,
+    /// Cadence in revolutions per minute (indoor bikes)
+    pub cadence: Option<f32>,
+    /// Strokes per minute (rowers)
+    pub stroke_rate: Option<f32>,
+    /// Total strokes (rowers)
+    pub stroke_count: Option<u16>,
+    /// Instantaneous power in W
+    pub power: Option<i16>,
+    /// Total distance in m
+    pub distance: Option<u32>,
+    /// Total energy expended in kcal
+    pub energy: Option<u16>,
+    /// Elapsed time of the workout in s
+    pub elapsed_time: Option<u16>,
+}
+
 /// state of the worn herat rate monitor
-#[derive(Debug, Serialize, Clone)]
+#[derive(Debug, Serialize, Clone, Default)]
 #[serde(rename_all = "snake_case")]
 pub struct HrData {
     pub hr: u16,
     pub contact_ok: Option<bool>,
-    pub battery: Option<u8>
+    pub battery: Option<u8>,
+    /// RR intervals (time between two beats) in milliseconds received with the last measurement
+    pub rr_intervals: Vec<u16>,
+    /// Cumulative energy expended in kJ, as reported by the device
+    pub energy_expended: Option<u16>,
+    /// Running speed and cadence, if the device supports it
+    pub running: Option<RunningData>,
+    /// Cycling power, speed and cadence, if the device supports it
+    pub cycling: Option<CyclingData>,
+    /// Oxygen saturation in %, if the device supports it
+    pub spo2: Option<f32>,
+    /// Metrics of a fitness machine, if the device is one
+    pub machine: Option<MachineData>,
+}
+
+/// values of the Running Speed and Cadence service
+#[derive(Debug, Serialize, Clone, Default)]
+#[serde(rename_all = "snake_case")]
+pub struct RunningData {
+    /// Speed in m/s
+    pub speed: f32,
+    /// Cadence in steps per minute
+    pub cadence: u8,
+    /// Length of the last stride in m
+    pub stride_length: Option<f32>,
+    /// Total distance in m
+    pub total_distance: Option<f32>,
+    /// If the user is running (or walking)
+    pub is_running: bool,
+}
+
+/// values of the Cycling Power and Cycling Speed and Cadence services
+#[derive(Debug, Serialize, Clone, Default)]
+#[serde(rename_all = "snake_case")]
+pub struct CyclingData {
+    /// Instantaneous power in W
+    pub power: Option<i16>,
+    /// Share of the right pedal in % of the power
+    pub pedal_balance: Option<f32>,
+    /// Cadence in revolutions per minute, derived from crank revolutions
+    pub cadence: Option<f32>,
+    /// Speed in m/s, derived from wheel revolutions
+    pub speed: Option<f32>,
+    /// Distance in m, derived from the cumulative wheel revolutions
+    pub distance: Option<f32>,
 }
 
 
@@ -68,6 +352,9 @@ impl HrmState {
             let mut receiver = SENDER.subscribe();
             loop {
                 if let Ok(received) = receiver.recv().await {
+                    if let Some(device) = received.device {
+                        data.device_data.write().await.insert(device, received.clone());
+                    }
                     *data.hr_data.write().await = received;
                 }
             }
@@ -80,10 +367,20 @@ impl HrmState {
 struct FoundDevice {
     pub name: String,
     pub addr: BDAddr,
-    pub peripheral: Peripheral,
+    pub peripheral: Arc<dyn BlePeripheral>,
     pub is_known: bool,
     pub filtered: bool,
     properties: PeripheralProperties,
+    /// How to reconnect, if the connection is lost
+    reconnect: ReconnectStrategy,
+    /// Statistics of the slot, this device was found for
+    stats: Arc<ConnectionStats>,
+    /// Cancelled to close the connection on request, without reconnecting
+    interrupt: CancellationToken,
+    /// Notified after every reconnect, so adaptors can set up the device again
+    reconnected: Arc<Notify>,
+    /// Wheel circumference configured for this device
+    wheel_circumference: Option<f32>,
 }
 
 
@@ -95,28 +392,293 @@ trait Adaptor: Send + Sync {
 
     async fn shutdown(&self);
 
+    /// Returns, if [`Adaptor::reset_energy_expended`] is supported by the device.
+    fn supports_energy_reset(&self) -> bool {
+        false
+    }
+
+    /// Resets the cumulative energy expended counter on the device.
+    async fn reset_energy_expended(&self) -> Result<()> {
+        Err(anyhow!("Resetting energy expended is not supported by this adaptor"))
+    }
+
     async fn heartbeat_loop(&self) -> Result<()>;
 
+    /// Rates how well this adaptor fits the device; returns the reason, if it does not fit at all.
+    ///
+    /// The device is connected and its services are discovered.
+    fn rate(device: &FoundDevice) -> Result<MatchConfidence, String>
+    where
+        Self: Sized;
+
     /// This should ONLY return an error, if it is a real error! It will cancel all other matching attempts!
     async fn try_wrap(device: Arc<FoundDevice>) -> Result<Option<Arc<dyn Adaptor>>>
     where
         Self: Sized;
 }
 
-async fn find_matching_adaptor(found_device: &FoundDevice, hrm_opt: Option<&Hrm>) -> Result<Option<Arc<dyn Adaptor>>> {
+/// An adaptor, which may fit a device.
+enum Candidate<'a> {
+    Declarative(&'a AdaptorDefinition),
+    BuiltIn(&'static RegisteredAdaptor),
+}
+
+impl Candidate<'_> {
+    fn id(&self) -> u16 {
+        match self {
+            Candidate::Declarative(definition) => definition.adaptor_id,
+            Candidate::BuiltIn(adaptor) => adaptor.id,
+        }
+    }
+
+    async fn try_wrap(&self, device: Arc<FoundDevice>) -> Result<Option<Arc<dyn Adaptor>>> {
+        match self {
+            Candidate::Declarative(definition) => DeclarativeAdaptor::try_wrap_definition(definition, device).await,
+            Candidate::BuiltIn(adaptor) => (adaptor.try_wrap)(device).await,
+        }
+    }
+}
+
+/// Finds the adaptor, which fits the connected device best.
+///
+/// The adaptor configured for a known device is used, if it can set up the device.
+/// Otherwise, all adaptors rate the device; the one with the highest [`MatchConfidence`] is used.
+/// For equal confidence, adaptors from the config file come first, followed by the built-in ones in [`ADAPTORS`] order.
+async fn find_matching_adaptor(
+    found_device: &FoundDevice,
+    hrm_opt: Option<&Hrm>,
+    definitions: &[AdaptorDefinition],
+) -> Result<Option<Arc<dyn Adaptor>>> {
     let arc = Arc::new(found_device.clone());
-    if let Some(adaptor_matcher) = hrm_opt.and_then(
-        |hrm| hrm.adaptor_id.and_then(|a| ADAPTORS.get(&a))
-    ) {
-        if let Some(adaptor) = adaptor_matcher(Arc::clone(&arc)).await? {
-            return Ok(Some(adaptor));
+    debug!("Discover peripheral {:?} services...", found_device.name);
+    found_device.peripheral.discover_services().await?;
+
+    if let Some(adaptor_id) = hrm_opt.and_then(|hrm| hrm.adaptor_id) {
+        let configured = definitions.iter().find(|d| d.adaptor_id == adaptor_id).map(Candidate::Declarative)
+            .or(get_adaptor(adaptor_id).map(Candidate::BuiltIn));
+        if let Some(candidate) = configured {
+            if let Some(adaptor) = candidate.try_wrap(Arc::clone(&arc)).await? {
+                return Ok(Some(adaptor));
+            }
+            info!("Configured adaptor {adaptor_id} could not set up device {}; searching for another one...", found_device.name);
         }
     }
 
-    for (_, adaptor_matcher) in ADAPTORS.iter() {
-        if let Some(adaptor) = adaptor_matcher(Arc::clone(&arc)).await? {
+    let mut candidates = vec![];
+    let ratings = definitions.iter()
+        .map(|d| (Candidate::Declarative(d), d.rate(found_device)))
+        .chain(ADAPTORS.iter().map(|a| (Candidate::BuiltIn(a), (a.rate)(found_device))));
+    for (candidate, rating) in ratings {
+        match rating {
+            Ok(confidence) => candidates.push((confidence, candidate)),
+            Err(reason) => info!("Adaptor {} rejected device {}: {reason}", candidate.id(), found_device.name),
+        }
+    }
+    // sorting is stable, so the priority order is kept for equal confidence
+    candidates.sort_by(|(a, _), (b, _)| b.cmp(a));
+
+    for (confidence, candidate) in candidates {
+        debug!("Trying adaptor {} ({confidence} match)...", candidate.id());
+        if let Some(adaptor) = candidate.try_wrap(Arc::clone(&arc)).await? {
+            info!("Using adaptor {} ({confidence} match) for device {}.", candidate.id(), found_device.name);
             return Ok(Some(adaptor));
         }
+        info!("Adaptor {} rejected device {}: it could not set up the device", candidate.id(), found_device.name);
     }
     Ok(None)
-}
\ No newline at end of file
+}
+
+/// Reads the Device Information Service (0x180A) and the Body Sensor Location (0x2A38) of a connected device.
+///
+/// Services must have been discovered before. Returns [`None`], if the device provides none of these values.
+async fn read_device_profile(peripheral: &Arc<dyn BlePeripheral>) -> Option<DeviceProfile> {
+    let mut profile = DeviceProfile::default();
+    let mut found = false;
+    for characteristic in peripheral.characteristics() {
+        if !characteristic.properties.contains(CharPropFlags::READ) {
+            continue;
+        }
+        let target = match characteristic.uuid.as_u128() {
+            0x00002a29_0000_1000_8000_00805f9b34fb => &mut profile.manufacturer,
+            0x00002a24_0000_1000_8000_00805f9b34fb => &mut profile.model,
+            0x00002a25_0000_1000_8000_00805f9b34fb => &mut profile.serial_number,
+            0x00002a26_0000_1000_8000_00805f9b34fb => &mut profile.firmware_revision,
+            0x00002a27_0000_1000_8000_00805f9b34fb => &mut profile.hardware_revision,
+            0x00002a28_0000_1000_8000_00805f9b34fb => &mut profile.software_revision,
+            0x00002a38_0000_1000_8000_00805f9b34fb => {
+                match peripheral.read(&characteristic).await {
+                    Ok(v) => {
+                        profile.body_sensor_location = v.first().map(|l| BodySensorLocation::from(*l));
+                        found = true;
+                    }
+                    Err(err) => warn!("Error while reading body sensor location: {err}"),
+                }
+                continue;
+            }
+            _ => continue,
+        };
+        match peripheral.read(&characteristic).await {
+            Ok(v) => {
+                let value = String::from_utf8_lossy(&v).trim_end_matches('\0').trim().to_owned();
+                debug!("Read {}: {value}", characteristic.uuid);
+                *target = Some(value);
+                found = true;
+            }
+            Err(err) => warn!("Error while reading characteristic {}: {err}", characteristic.uuid),
+        }
+    }
+    found.then_some(profile)
+}
+
+/// Reads the Battery Level (0x2A19) of a connected device.
+///
+/// Returns the level and the characteristic, if it supports notifications.
+async fn read_battery(peripheral: &Arc<dyn BlePeripheral>) -> (Option<u8>, Option<Characteristic>) {
+    let Some(characteristic) = peripheral.characteristics().into_iter().find(|c| c.uuid == Uuid::from_u128(0x00002a19_0000_1000_8000_00805f9b34fb)) else {
+        return (None, None);
+    };
+    let level = match peripheral.read(&characteristic).await {
+        Ok(v) => v.first().copied(),
+        Err(err) => {
+            warn!("Error while reading battery value: {err}");
+            None
+        }
+    };
+    (level, characteristic.properties.contains(CharPropFlags::NOTIFY).then_some(characteristic))
+}
+
+/// Subscribes to `characteristics` and publishes the state after every notification, until the connection is lost for good.
+///
+/// `handle` updates the state with a received notification; `initial` is used, when the first notification arrives.\
+/// Notifications of other characteristics are ignored, so adaptors can process them separately.
+async fn run_notification_loop<F>(
+    device: &FoundDevice,
+    characteristics: &[Characteristic],
+    hrm_state: &Arc<RwLock<HrmState>>,
+    initial: HrData,
+    mut handle: F,
+) -> Result<()>
+where
+    F: FnMut(&ValueNotification, &mut HrData) + Send + 'static,
+{
+    debug!("Subscribing to characteristics {:?}", characteristics.iter().map(|c| c.uuid).join(","));
+    for c in characteristics {
+        device.peripheral.subscribe(c).await?;
+    }
+    let mut notification_stream = device.peripheral.notifications().await?;
+    info!("Device ready!");
+    let state_clone = Arc::clone(hrm_state);
+    let mac = MacAddress::from(device.addr.into_inner());
+    let uuids = characteristics.iter().map(|c| c.uuid).collect::<Vec<_>>();
+    let task = tokio::spawn(async move {
+        // Process while the BLE connection is not broken or stopped.
+        while let Some(received_data) = notification_stream.next().await {
+            if !uuids.contains(&received_data.uuid) {
+                continue;
+            }
+            debug!("Received data from [{:?}]: {:?}", received_data.uuid, received_data.value);
+            let mut write = state_clone.write().await;
+            let state = &mut *write;
+            if let HrmState::Disconnected = state {
+                *state = HrmState::Ok(initial.clone());
+            }
+            if let HrmState::Ok(ref mut data) = state {
+                handle(&received_data, data);
+            }
+
+            let _ = SENDER.send(ChannelTransferObject {
+                timestamp: Utc::now(),
+                device: Some(mac),
+                hr_state: Some(state.clone()),
+            });
+        }
+    });
+
+    // returns, when the connection is lost for good
+    watch_connection(device).await;
+
+    // kill loop, which handles heart rate events
+    task.abort();
+    *hrm_state.write().await = HrmState::Disconnected;
+
+    // tell the rest of program, that we disconnected (the program assumes, that this function is never finished)
+    close_connection(device, characteristics).await
+}
+
+/// Checks the connection to the device regularly and tries to reconnect, if it was lost.
+///
+/// Returns, when the connection could not be restored within the attempts of the [`ReconnectStrategy`].
+async fn watch_connection(device: &FoundDevice) {
+    let strategy = device.reconnect;
+    'watch: loop {
+        tokio::select! {
+            () = sleep(strategy.poll_interval()) => {}
+            () = device.interrupt.cancelled() => {
+                debug!("Closing connection on request...");
+                return;
+            }
+        }
+        debug!("Testing connectivity...");
+        // check connection to device
+        match device.peripheral.is_connected().await {
+            // connection check not broken
+            Ok(c) => {
+                debug!("Connectivity successful!");
+                // if device is connected
+                if c {
+                    // loop again
+                    continue;
+                }
+                // device connection lost
+                debug!("Disconnected...");
+            }
+            // checking connection returned an error
+            Err(err) => {
+                error!("Checking connection returned error: {err}");
+            }
+        }
+
+        // try to reconnect
+        device.stats.disconnected();
+        for attempt in 1..=strategy.max_attempts {
+            if attempt > 1 {
+                sleep(strategy.backoff(attempt - 1)).await;
+            }
+            debug!("Reconnecting (attempt {attempt} of {})...", strategy.max_attempts);
+            match timeout(strategy.reconnect_timeout(), device.peripheral.connect()).await {
+                // connection successful
+                Ok(Ok(())) => {
+                    debug!("Reconnected!");
+                    device.stats.connected();
+                    device.reconnected.notify_one();
+                    continue 'watch;
+                }
+                // connection got an error
+                Ok(Err(err)) => error!("Reconnecting returned error: {err}"),
+                Err(_) => error!("Timeout while reconnecting to device!"),
+            }
+            device.stats.failed();
+        }
+        return;
+    }
+}
+
+/// Unsubscribes from all `characteristics`, publishes [`HrmState::Disconnected`] and disconnects properly.
+async fn close_connection(device: &FoundDevice, characteristics: &[Characteristic]) -> Result<()> {
+    // deactivate all events
+    for c in characteristics {
+        device.peripheral.unsubscribe(c).await?;
+    }
+
+    // tell the api, that we are not connected anymore
+    let _ = SENDER.send(ChannelTransferObject {
+        timestamp: Utc::now(),
+        device: Some(MacAddress::from(device.addr.into_inner())),
+        hr_state: Some(HrmState::Disconnected)
+    });
+
+    // disconnect properly
+    info!("Disconnecting from peripheral {:?}...", device.name);
+    device.peripheral.disconnect().await?;
+    Ok(())
+}
@@ -0,0 +1,106 @@
+//! Helpers to read little endian values from characteristic values.
+
+use anyhow::{anyhow, Result};
+
+/// Reads a u8 from `value` at `offset`.
+pub fn read_u8(value: &[u8], offset: usize) -> Result<u8> {
+    value.get(offset).copied().ok_or(anyhow!("Value too short to read u8 at offset {offset}"))
+}
+
+/// Reads a little endian u16 from `value` at `offset`.
+pub fn read_u16(value: &[u8], offset: usize) -> Result<u16> {
+    match value.get(offset..offset + 2) {
+        Some(&[low, high]) => Ok(u16::from_le_bytes([low, high])),
+        _ => Err(anyhow!("Value too short to read u16 at offset {offset}")),
+    }
+}
+
+/// Reads a little endian 24 bit unsigned integer from `value` at `offset`.
+pub fn read_u24(value: &[u8], offset: usize) -> Result<u32> {
+    match value.get(offset..offset + 3) {
+        Some(&[b0, b1, b2]) => Ok(u32::from_le_bytes([b0, b1, b2, 0])),
+        _ => Err(anyhow!("Value too short to read u24 at offset {offset}")),
+    }
+}
+
+/// Reads a little endian u32 from `value` at `offset`.
+pub fn read_u32(value: &[u8], offset: usize) -> Result<u32> {
+    match value.get(offset..offset + 4) {
+        Some(&[b0, b1, b2, b3]) => Ok(u32::from_le_bytes([b0, b1, b2, b3])),
+        _ => Err(anyhow!("Value too short to read u32 at offset {offset}")),
+    }
+}
+
+/// Reads a little endian u64 from `value` at `offset`.
+pub fn read_u64(value: &[u8], offset: usize) -> Result<u64> {
+    value.get(offset..offset + 8)
+        .and_then(|bytes| bytes.try_into().ok())
+        .map(u64::from_le_bytes)
+        .ok_or(anyhow!("Value too short to read u64 at offset {offset}"))
+}
+
+/// Circumference of a 700x25c wheel in m, used to convert wheel revolutions into speed and distance, unless the
+/// device has its own `wheel_circumference`
+pub const DEFAULT_WHEEL_CIRCUMFERENCE: f32 = 2.105;
+
+/// Derives a rate from cumulative revolution counters (crank or wheel), as reported by cycling sensors.
+///
+/// Counters and event times wrap around, so only the differences to the previous values are used.
+#[derive(Default)]
+pub struct RevolutionCounter {
+    /// Cumulative revolutions and event time of the previous measurement
+    last: Option<(u32, u16)>,
+    /// Number of measurements without a new revolution
+    unchanged: u8,
+    /// Last calculated rate
+    rate: Option<f32>,
+}
+
+impl RevolutionCounter {
+    /// Measurements without a new revolution, after which the rate is assumed to be 0
+    const MAX_UNCHANGED: u8 = 3;
+
+    /// Updates the counter and returns the revolutions per second.
+    ///
+    /// `revolution_mask` is the maximum value of the revolution counter (it wraps to 0 afterward),
+    /// `time_resolution` the number of event time units per second.
+    pub fn update(&mut self, revolutions: u32, event_time: u16, revolution_mask: u32, time_resolution: f32) -> Option<f32> {
+        if let Some((last_revolutions, last_time)) = self.last {
+            let revolution_delta = revolutions.wrapping_sub(last_revolutions) & revolution_mask;
+            let time_delta = event_time.wrapping_sub(last_time);
+            if revolution_delta == 0 || time_delta == 0 {
+                // no new revolution; the sensor stopped or the notification arrived before the next revolution
+                self.unchanged = self.unchanged.saturating_add(1);
+                if self.unchanged >= Self::MAX_UNCHANGED {
+                    self.rate = Some(0.0);
+                }
+            } else {
+                self.unchanged = 0;
+                #[allow(clippy::cast_precision_loss)]
+                let rate = revolution_delta as f32 / (f32::from(time_delta) / time_resolution);
+                self.rate = Some(rate);
+            }
+        }
+        self.last = Some((revolutions, event_time));
+        self.rate
+    }
+}
+
+/// Reads a little endian IEEE-11073 16 bit SFLOAT from `value` at `offset`.
+///
+/// The value consists of a 4 bit exponent and a 12 bit mantissa (both signed, base 10).
+/// The special values `NaN`, `NRes` (not at this resolution) and the reserved value are returned as [`f32::NAN`],
+/// +INF and -INF as [`f32::INFINITY`] and [`f32::NEG_INFINITY`].
+pub fn read_sfloat(value: &[u8], offset: usize) -> Result<f32> {
+    let raw = read_u16(value, offset)?;
+    match raw {
+        0x07FF..=0x0801 => return Ok(f32::NAN),
+        0x07FE => return Ok(f32::INFINITY),
+        0x0802 => return Ok(f32::NEG_INFINITY),
+        _ => {}
+    }
+    // sign extend both parts
+    let mantissa = (((raw & 0x0FFF) << 4).cast_signed()) >> 4;
+    let exponent = (raw.cast_signed()) >> 12;
+    Ok(f32::from(mantissa) * 10_f32.powi(i32::from(exponent)))
+}
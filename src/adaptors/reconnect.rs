@@ -0,0 +1,212 @@
+//! Reconnecting to lost devices
+//!
+//! A [`ReconnectStrategy`] decides how often the connection is checked, how often and how fast a lost device is
+//! reconnected before scanning again, and when to give up. The delay between failed attempts grows exponentially and
+//! varies randomly, so several devices (or hosts) do not retry in lockstep.\
+//! Every slot of the [`HrManager`](crate::adaptors::hrm::HrManager) counts its disconnects, reconnects and downtime
+//! in [`ConnectionStats`].
+
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::time::Instant;
+
+/// How to reconnect to lost devices, read from the config file.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(default)]
+pub struct ReconnectStrategy {
+    /// Milliseconds between two checks of the connection
+    pub poll_interval_ms: u64,
+    /// Milliseconds a single reconnection attempt may take
+    pub reconnect_timeout_ms: u64,
+    /// Milliseconds to wait after the first failed attempt; doubled after every further one
+    pub initial_backoff_ms: u64,
+    /// Upper limit of the wait time between two attempts in milliseconds
+    pub max_backoff_ms: u64,
+    /// Fraction of the wait time, by which it varies randomly (0 - 1)
+    pub jitter: f64,
+    /// Attempts to reconnect to the lost device, before scanning for devices again
+    pub max_attempts: u32,
+    /// Failed scans after a connection loss, after which the device is given up; [`None`] never gives up
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub give_up_after: Option<u32>,
+    /// What to do, when a device is given up
+    pub give_up: GiveUp,
+}
+
+impl Default for ReconnectStrategy {
+    fn default() -> Self {
+        Self {
+            poll_interval_ms: 1000,
+            reconnect_timeout_ms: 2000,
+            initial_backoff_ms: 1000,
+            max_backoff_ms: 30_000,
+            jitter: 0.1,
+            max_attempts: 1,
+            give_up_after: None,
+            give_up: GiveUp::default(),
+        }
+    }
+}
+
+/// What to do, when a lost device could not be reconnected.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum GiveUp {
+    /// Stop connecting to this device; other devices stay connected
+    #[default]
+    Stop,
+    /// Shut down the program
+    Shutdown,
+}
+
+impl ReconnectStrategy {
+    /// Checks the strategy for values, which would prevent it from working.
+    pub fn check(&self) -> Result<()> {
+        if self.poll_interval_ms == 0 {
+            return Err(anyhow!("The poll interval must not be 0"));
+        }
+        if self.reconnect_timeout_ms == 0 {
+            return Err(anyhow!("The reconnect timeout must not be 0"));
+        }
+        if self.initial_backoff_ms > self.max_backoff_ms {
+            return Err(anyhow!("The initial backoff must not be larger than the maximum backoff"));
+        }
+        if !(0.0..=1.0).contains(&self.jitter) {
+            return Err(anyhow!("The jitter must be between 0 and 1"));
+        }
+        Ok(())
+    }
+
+    pub fn poll_interval(&self) -> Duration {
+        Duration::from_millis(self.poll_interval_ms)
+    }
+
+    pub fn reconnect_timeout(&self) -> Duration {
+        Duration::from_millis(self.reconnect_timeout_ms)
+    }
+
+    /// Returns the time to wait after `failures` failed attempts in a row (at least 1).
+    pub fn backoff(&self, failures: u32) -> Duration {
+        self.backoff_with(failures, random())
+    }
+
+    /// Like [`ReconnectStrategy::backoff`], with `random` between 0 and 1 choosing the jitter.
+    #[allow(clippy::cast_precision_loss, clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    pub(super) fn backoff_with(&self, failures: u32, random: f64) -> Duration {
+        let factor = 1_u64.checked_shl(failures.saturating_sub(1)).unwrap_or(u64::MAX);
+        let backoff = self.initial_backoff_ms.saturating_mul(factor).min(self.max_backoff_ms) as f64;
+        let jitter = backoff * self.jitter * (random * 2.0 - 1.0);
+        Duration::from_millis((backoff + jitter).max(0.0) as u64)
+    }
+}
+
+/// Returns a random number between 0 and 1.
+#[allow(clippy::cast_precision_loss)]
+fn random() -> f64 {
+    // every hasher is seeded randomly
+    RandomState::new().build_hasher().finish() as f64 / u64::MAX as f64
+}
+
+/// Connection statistics of a slot, as returned by the api.
+#[derive(Serialize, Debug, Default, Clone, PartialEq, Eq)]
+pub struct ReconnectStats {
+    /// Connection losses
+    pub disconnects: u32,
+    /// Connections restored after a loss, with or without a scan
+    pub reconnects: u32,
+    /// Failed reconnection attempts and scans
+    pub failed_attempts: u32,
+    /// Time between the last connection loss and the restored connection in ms
+    pub last_reconnect_latency: Option<u64>,
+    /// Average time to restore the connection in ms
+    pub average_reconnect_latency: Option<u64>,
+    /// Longest time to restore the connection in ms
+    pub max_reconnect_latency: Option<u64>,
+    /// Total time without connection after a loss in ms, including the current one
+    pub downtime: u64,
+    /// Since when the device is disconnected; [`None`] while connected
+    pub disconnected_since: Option<DateTime<Utc>>,
+    /// If the device was given up
+    pub given_up: bool,
+}
+
+/// Counts disconnects, reconnects and downtime of a slot.
+#[derive(Default, Debug)]
+pub struct ConnectionStats {
+    inner: Mutex<StatsInner>,
+}
+
+#[derive(Default, Debug)]
+struct StatsInner {
+    stats: ReconnectStats,
+    /// Time of the current connection loss
+    lost_at: Option<Instant>,
+    /// Sum of all reconnect latencies in ms
+    total_latency: u64,
+}
+
+impl ConnectionStats {
+    fn lock(&self) -> std::sync::MutexGuard<'_, StatsInner> {
+        self.inner.lock().unwrap_or_else(std::sync::PoisonError::into_inner)
+    }
+
+    /// Records a connection loss.
+    pub fn disconnected(&self) {
+        let mut inner = self.lock();
+        if inner.lost_at.is_some() {
+            return;
+        }
+        inner.lost_at = Some(Instant::now());
+        inner.stats.disconnects += 1;
+        inner.stats.disconnected_since = Some(Utc::now());
+    }
+
+    /// Records a failed reconnection attempt or scan.
+    pub fn failed(&self) {
+        self.lock().stats.failed_attempts += 1;
+    }
+
+    /// Records a (re)established connection; does nothing, if the connection was not lost before.
+    pub fn connected(&self) {
+        let mut inner = self.lock();
+        let Some(lost_at) = inner.lost_at.take() else {
+            return;
+        };
+        let latency = u64::try_from(lost_at.elapsed().as_millis()).unwrap_or(u64::MAX);
+        inner.total_latency = inner.total_latency.saturating_add(latency);
+        let stats = &mut inner.stats;
+        stats.reconnects += 1;
+        stats.downtime = stats.downtime.saturating_add(latency);
+        stats.last_reconnect_latency = Some(latency);
+        stats.max_reconnect_latency = Some(stats.max_reconnect_latency.unwrap_or(0).max(latency));
+        stats.disconnected_since = None;
+        stats.given_up = false;
+        let (total, reconnects) = (inner.total_latency, u64::from(inner.stats.reconnects));
+        inner.stats.average_reconnect_latency = Some(total / reconnects);
+    }
+
+    /// Records, that the device was given up.
+    pub fn given_up(&self) {
+        self.lock().stats.given_up = true;
+    }
+
+    /// If the connection was lost and not restored yet.
+    pub fn is_lost(&self) -> bool {
+        self.lock().lost_at.is_some()
+    }
+
+    pub fn report(&self) -> ReconnectStats {
+        let inner = self.lock();
+        let mut stats = inner.stats.clone();
+        if let Some(lost_at) = inner.lost_at {
+            stats.downtime = stats.downtime.saturating_add(u64::try_from(lost_at.elapsed().as_millis()).unwrap_or(u64::MAX));
+        }
+        stats
+    }
+}
@@ -0,0 +1,167 @@
+//! Policy for choosing a device without asking the user
+//!
+//! Without a [`SelectionPolicy`], a device is only chosen automatically, if it was requested explicitly or is known;
+//! otherwise the user is asked on stdin. With a policy, all found devices are filtered by its rules and ranked by its
+//! [`Ranking`]s; the best one is chosen. If no device fulfills the rules, the [`Fallback`] decides what happens.
+
+use anyhow::{anyhow, Result};
+use itertools::Itertools;
+use mac_address::MacAddress;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::adaptors::FoundDevice;
+
+/// Rules to choose a device non-interactively, read from the config file.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct SelectionPolicy {
+    /// Regular expression the advertised name must match
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name_regex: Option<String>,
+    /// The device must advertise all of these services
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub services: Vec<Uuid>,
+    /// Minimum signal strength in dBm
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min_rssi: Option<i16>,
+    /// Criteria to rank the remaining devices by; earlier criteria take precedence
+    pub ranking: Vec<Ranking>,
+    /// What to do, if no device fulfills the rules
+    pub fallback: Fallback,
+}
+
+impl Default for SelectionPolicy {
+    fn default() -> Self {
+        Self {
+            name_regex: None,
+            services: vec![],
+            min_rssi: None,
+            ranking: vec![Ranking::Known, Ranking::StrongestSignal],
+            fallback: Fallback::default(),
+        }
+    }
+}
+
+/// Criterion to rank devices by.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Ranking {
+    /// Known devices first, in the order of `hrm_list`
+    Known,
+    /// Devices with a stronger signal first
+    StrongestSignal,
+}
+
+/// What to do, if no device fulfills the rules of the policy.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Fallback {
+    /// Wait for the user to choose a device on stdin
+    Wait,
+    /// Scan again
+    #[default]
+    Rescan,
+    /// Shut down the program
+    Fail,
+}
+
+/// Outcome of choosing a device automatically.
+pub(super) enum Decision {
+    /// Index of the chosen device
+    Chosen(usize),
+    Fallback(Fallback),
+}
+
+impl SelectionPolicy {
+    /// Checks the policy for errors, which would prevent it from working.
+    pub fn check(&self) -> Result<()> {
+        if let Some(ref regex) = self.name_regex {
+            Regex::new(regex).map_err(|err| anyhow!("Invalid name regex: {err}"))?;
+        }
+        Ok(())
+    }
+
+    /// Returns, why the device does not fulfill the rules; [`None`], if it does.
+    fn reject_reason(&self, device: &FoundDevice, regex: Option<&Regex>) -> Option<String> {
+        if let Some(regex) = regex {
+            if !regex.is_match(&device.name) {
+                return Some(format!("the name does not match \"{regex}\""));
+            }
+        }
+        if let Some(service) = self.services.iter().find(|s| !device.properties.services.contains(s)) {
+            return Some(format!("the service {service} is not advertised"));
+        }
+        if let Some(min_rssi) = self.min_rssi {
+            match device.properties.rssi {
+                Some(rssi) if rssi < min_rssi => return Some(format!("the signal ({rssi} dBm) is weaker than {min_rssi} dBm")),
+                None => return Some("the signal strength is unknown".to_owned()),
+                Some(_) => {}
+            }
+        }
+        None
+    }
+
+    /// Chooses the best device, which fulfills the rules.
+    ///
+    /// `known` are the mac addresses of all known devices in the order of `hrm_list`.
+    /// Returns the decision and the reasons for it, one line per device.
+    pub(super) fn select(&self, devices: &[FoundDevice], known: &[MacAddress]) -> Result<(Decision, Vec<String>)> {
+        let regex = self.name_regex.as_deref().map(Regex::new).transpose()?;
+        let mut reasons = vec![];
+        let mut candidates = vec![];
+        for (index, device) in devices.iter().enumerate() {
+            match self.reject_reason(device, regex.as_ref()) {
+                Some(reason) => reasons.push(format!("{} ({}) is rejected: {reason}", device.name, device.addr)),
+                None => candidates.push((index, device)),
+            }
+        }
+
+        let known_position = |device: &FoundDevice| known.iter().position(|mac| mac.bytes() == device.addr.into_inner());
+        let key = |(_, device): &(usize, &FoundDevice)| {
+            self.ranking.iter().map(|ranking| match ranking {
+                Ranking::Known => i32::try_from(known_position(device).unwrap_or(known.len())).unwrap_or(i32::MAX),
+                Ranking::StrongestSignal => device.properties.rssi.map_or(i32::MAX, |rssi| -i32::from(rssi)),
+            }).collect::<Vec<_>>()
+        };
+        let ranked = candidates.into_iter().sorted_by_key(key).collect::<Vec<_>>();
+        for (rank, (_, device)) in ranked.iter().enumerate() {
+            let position = known_position(device).map_or("unknown".to_owned(), |p| format!("known device {}", p + 1));
+            let signal = device.properties.rssi.map_or("unknown signal".to_owned(), |rssi| format!("{rssi} dBm"));
+            reasons.push(format!("{} ({}) is ranked {} ({position}, {signal})", device.name, device.addr, rank + 1));
+        }
+
+        let decision = match ranked.first() {
+            Some((index, _)) => Decision::Chosen(*index),
+            None => Decision::Fallback(self.fallback),
+        };
+        Ok((decision, reasons))
+    }
+}
+
+/// Chooses a device without asking the user, like [`HrManager`](crate::adaptors::hrm::HrManager) does.
+///
+/// `devices` must be sorted like the result of a search: requested devices first, known devices next.\
+/// Returns the decision and the reasons for it; [`Fallback::Wait`] means, that the user has to choose.
+pub(super) fn decide(
+    devices: &[FoundDevice],
+    accept_new_device: bool,
+    policy: Option<&SelectionPolicy>,
+    known: &[MacAddress],
+) -> Result<(Decision, Vec<String>)> {
+    let Some(first) = devices.first() else {
+        return Ok((Decision::Fallback(policy.map_or(Fallback::Wait, |p| p.fallback)), vec!["No devices were found".to_owned()]));
+    };
+    // requested with "hrm-mac" (new devices), "hrm-index" or pinned
+    if first.filtered && (accept_new_device || first.is_known) {
+        return Ok((Decision::Chosen(0), vec![format!("{} ({}) was requested", first.name, first.addr)]));
+    }
+    if let Some(policy) = policy {
+        return policy.select(devices, known);
+    }
+    if !accept_new_device && first.is_known {
+        return Ok((Decision::Chosen(0), vec![format!("{} ({}) is a known device", first.name, first.addr)]));
+    }
+    Ok((Decision::Fallback(Fallback::Wait), vec!["No device is known or was requested".to_owned()]))
+}
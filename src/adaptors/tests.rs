@@ -0,0 +1,1491 @@
+//! Drives the [`HrManager`] end to end against fake peripherals.
+
+#![allow(clippy::expect_used, clippy::unwrap_used, clippy::panic)]
+
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+
+use btleplug::api::bleuuid::uuid_from_u16;
+use btleplug::api::{BDAddr, CharPropFlags, PeripheralProperties};
+use chrono::Utc;
+use clap::Parser;
+use mac_address::MacAddress;
+use tera::Tera;
+use tokio::sync::{Mutex, RwLock};
+use tokio::sync::broadcast::Receiver;
+use tokio::time::{Instant, sleep, timeout};
+use tokio_util::sync::CancellationToken;
+use uuid::Uuid;
+
+use crate::adaptors::advertisement::decode_advertisement;
+use crate::adaptors::capture::{Capture, Hex, session_path};
+use crate::adaptors::reconnect::{GiveUp, ReconnectStrategy};
+use crate::adaptors::selection::{decide, Decision, Fallback, Ranking, SelectionPolicy};
+use crate::adaptors::{ChannelTransferObject, FoundDevice, get_raw_receiver, get_receiver, HrData, HrmState, MachineData, MachineKind, RawMeasurement, supported_services};
+use crate::adaptors::explorer::{Explorer, ExplorerCommand};
+use crate::adaptors::hrm::HrManager;
+use crate::adaptors::parse::read_sfloat;
+use crate::adaptors::type_6::{decode_pmd_frame, negotiate_settings, PMD_CONTROL_POINT, PMD_DATA, start_command};
+use crate::adaptors::type_7::{parse_indoor_bike_data, parse_rower_data, parse_treadmill_data};
+use crate::adaptors::transport::BlePeripheral;
+use crate::adaptors::transport::fake::{FakeAdapter, FakePeripheral, FakeTransport};
+use crate::args::Args;
+use crate::config::{Hrm, MergedConfig, ProgramConfig};
+use crate::ProgramData;
+
+const HR_SERVICE: Uuid = uuid_from_u16(0x180d);
+const HR_MEASUREMENT: Uuid = uuid_from_u16(0x2a37);
+const HR_CONTROL_POINT: Uuid = uuid_from_u16(0x2a39);
+const BATTERY_SERVICE: Uuid = uuid_from_u16(0x180f);
+const BATTERY_LEVEL: Uuid = uuid_from_u16(0x2a19);
+const DEVICE_INFORMATION_SERVICE: Uuid = uuid_from_u16(0x180a);
+const MANUFACTURER_NAME: Uuid = uuid_from_u16(0x2a29);
+
+const STRAP_ADDRESS: [u8; 6] = [0xAA, 0xBB, 0xCC, 0xDD, 0xEE, 0x01];
+
+/// All tests share [`SENDER`](crate::adaptors::SENDER), so they must not run concurrently.
+static SERIAL: Mutex<()> = Mutex::const_new(());
+
+fn strap() -> Arc<FakePeripheral> {
+    Arc::new(
+        FakePeripheral::new(STRAP_ADDRESS, "Strap")
+            .with_service(HR_SERVICE, vec![
+                (HR_MEASUREMENT, CharPropFlags::NOTIFY, vec![]),
+                (HR_CONTROL_POINT, CharPropFlags::WRITE, vec![]),
+            ])
+            .with_service(BATTERY_SERVICE, vec![
+                (BATTERY_LEVEL, CharPropFlags::READ | CharPropFlags::NOTIFY, vec![80]),
+            ])
+            .with_service(DEVICE_INFORMATION_SERVICE, vec![
+                (MANUFACTURER_NAME, CharPropFlags::READ, b"ACME".to_vec()),
+            ])
+    )
+}
+
+fn known_strap() -> Hrm {
+    Hrm {
+        name: "Strap".to_owned(),
+        mac: MacAddress::new(STRAP_ADDRESS),
+        adaptor_id: Some(1),
+        profile: None,
+        wheel_circumference: None,
+    }
+}
+
+fn program_data(args: &[&str], hrm_list: Vec<Hrm>) -> Arc<ProgramData> {
+    let args = Args::parse_from(["HRM"].iter().chain(args));
+    let program_config = ProgramConfig {
+        hrm_list,
+        ..ProgramConfig::default()
+    };
+    Arc::new(ProgramData {
+        merged_config: Arc::new(RwLock::new(MergedConfig::new(program_config, args))),
+        tera: RwLock::new(Tera::default()),
+        hr_data: Arc::new(RwLock::new(ChannelTransferObject {
+            timestamp: Utc::now(),
+            device: None,
+            hr_state: None,
+        })),
+        device_data: Arc::default(),
+    })
+}
+
+/// Starts a manager for the given adapters; it runs until the test ends.
+fn start_manager(adapters: Vec<Arc<FakeAdapter>>, data: &Arc<ProgramData>) -> &'static HrManager {
+    let manager: &'static HrManager = Box::leak(Box::new(HrManager::new(Arc::new(FakeTransport::new(adapters)))));
+    tokio::spawn(manager.run(Arc::clone(data)));
+    manager
+}
+
+async fn wait_until(condition: impl Fn() -> bool) {
+    timeout(Duration::from_mins(1), async {
+        while !condition() {
+            sleep(Duration::from_millis(10)).await;
+        }
+    }).await.expect("condition was not met in time");
+}
+
+async fn next_state(receiver: &mut Receiver<ChannelTransferObject>) -> HrmState {
+    timeout(Duration::from_mins(1), receiver.recv())
+        .await
+        .expect("no state was published in time")
+        .expect("channel closed")
+        .hr_state
+        .expect("state is missing")
+}
+
+async fn next_data(receiver: &mut Receiver<ChannelTransferObject>) -> HrData {
+    match next_state(receiver).await {
+        HrmState::Ok(data) => data,
+        HrmState::Disconnected => panic!("expected data, but device is disconnected"),
+    }
+}
+
+#[tokio::test(start_paused = true)]
+async fn connects_to_known_device_and_publishes_heart_rate() {
+    let _serial = SERIAL.lock().await;
+    let strap = strap();
+    let adapter = FakeAdapter::new(vec![Arc::clone(&strap)]);
+    let data = program_data(&[], vec![known_strap()]);
+    let mut receiver = get_receiver();
+    start_manager(vec![adapter], &data);
+
+    wait_until(|| strap.subscribed(HR_MEASUREMENT)).await;
+    // u8 heart rate, contact detected, one RR interval of 1024/1024 seconds
+    strap.notify(HR_MEASUREMENT, vec![0b1_0110, 72, 0x00, 0x04]);
+
+    let hr = next_data(&mut receiver).await;
+    assert_eq!(hr.hr, 72);
+    assert_eq!(hr.contact_ok, Some(true));
+    assert_eq!(hr.battery, Some(80));
+    assert_eq!(hr.rr_intervals, vec![1000]);
+    assert_eq!(hr.energy_expended, None);
+}
+
+#[tokio::test(start_paused = true)]
+async fn updates_battery_level_of_heart_rate_strap() {
+    let _serial = SERIAL.lock().await;
+    let strap = strap();
+    let adapter = FakeAdapter::new(vec![Arc::clone(&strap)]);
+    let data = program_data(&[], vec![known_strap()]);
+    let mut receiver = get_receiver();
+    start_manager(vec![adapter], &data);
+
+    wait_until(|| strap.subscribed(HR_MEASUREMENT) && strap.subscribed(BATTERY_LEVEL)).await;
+    strap.notify(BATTERY_LEVEL, vec![79]);
+    let hr = next_data(&mut receiver).await;
+    assert_eq!(hr.battery, Some(79));
+    strap.notify(HR_MEASUREMENT, vec![0, 64]);
+    let next = next_data(&mut receiver).await;
+    assert_eq!(next.hr, 64);
+    assert_eq!(next.battery, Some(79));
+}
+
+#[tokio::test(start_paused = true)]
+async fn pairs_new_device_and_stores_its_profile() {
+    let _serial = SERIAL.lock().await;
+    let other = Arc::new(
+        FakePeripheral::new([0xAA, 0xBB, 0xCC, 0xDD, 0xEE, 0x02], "Some speaker")
+            .with_properties(|p| p.local_name = None)
+    );
+    let strap = strap();
+    let adapter = FakeAdapter::new(vec![other, Arc::clone(&strap)]);
+    let data = program_data(&["--accept-new-device", "--hrm-mac", "AA:BB:CC:DD:EE:01"], vec![]);
+    start_manager(vec![adapter], &data);
+
+    wait_until(|| strap.subscribed(HR_MEASUREMENT)).await;
+    let config = data.merged_config.read().await;
+    let [hrm] = config.program_config.hrm_list.as_slice() else {
+        panic!("expected exactly one stored device");
+    };
+    assert_eq!(hrm.mac, MacAddress::new(STRAP_ADDRESS));
+    assert_eq!(hrm.adaptor_id, Some(1));
+    assert_eq!(hrm.profile.as_ref().and_then(|p| p.manufacturer.as_deref()), Some("ACME"));
+}
+
+#[tokio::test(start_paused = true)]
+async fn parses_energy_expended_and_resets_it() {
+    let _serial = SERIAL.lock().await;
+    let strap = strap();
+    let adapter = FakeAdapter::new(vec![Arc::clone(&strap)]);
+    let data = program_data(&[], vec![known_strap()]);
+    let mut receiver = get_receiver();
+    let manager = start_manager(vec![adapter], &data);
+
+    wait_until(|| strap.subscribed(HR_MEASUREMENT)).await;
+    // u16 heart rate, energy expended present
+    strap.notify(HR_MEASUREMENT, vec![0b1001, 130, 0x00, 0x10, 0x01]);
+    let hr = next_data(&mut receiver).await;
+    assert_eq!(hr.hr, 130);
+    assert_eq!(hr.energy_expended, Some(272));
+
+    manager.reset_energy_expended().await.expect("reset failed");
+    assert_eq!(strap.written(), vec![(HR_CONTROL_POINT, vec![0x01])]);
+}
+
+#[tokio::test(start_paused = true)]
+async fn keeps_publishing_after_short_connection_loss() {
+    let _serial = SERIAL.lock().await;
+    let strap = strap();
+    let adapter = FakeAdapter::new(vec![Arc::clone(&strap)]);
+    let data = program_data(&[], vec![known_strap()]);
+    let mut receiver = get_receiver();
+    start_manager(vec![Arc::clone(&adapter)], &data);
+
+    wait_until(|| strap.subscribed(HR_MEASUREMENT)).await;
+    strap.drop_connection();
+    wait_until(|| !strap.connected()).await;
+    wait_until(|| strap.connected()).await;
+
+    strap.notify(HR_MEASUREMENT, vec![0, 65]);
+    assert_eq!(next_data(&mut receiver).await.hr, 65);
+    assert_eq!(adapter.scans.load(Ordering::Acquire), 1);
+}
+
+#[tokio::test(start_paused = true)]
+async fn publishes_disconnect_and_rescans_when_reconnecting_fails() {
+    let _serial = SERIAL.lock().await;
+    let strap = strap();
+    let adapter = FakeAdapter::new(vec![Arc::clone(&strap)]);
+    let data = program_data(&["--pin-device"], vec![known_strap()]);
+    let mut receiver = get_receiver();
+    start_manager(vec![Arc::clone(&adapter)], &data);
+
+    wait_until(|| strap.subscribed(HR_MEASUREMENT)).await;
+    strap.set_connectable(false);
+    strap.drop_connection();
+    assert!(matches!(next_state(&mut receiver).await, HrmState::Disconnected));
+
+    strap.set_value(BATTERY_LEVEL, vec![79]);
+    strap.set_connectable(true);
+    wait_until(|| strap.subscribed(HR_MEASUREMENT)).await;
+    assert!(adapter.scans.load(Ordering::Acquire) >= 2);
+    strap.notify(HR_MEASUREMENT, vec![0, 90]);
+    let hr = next_data(&mut receiver).await;
+    assert_eq!(hr.hr, 90);
+    assert_eq!(hr.battery, Some(79));
+}
+
+#[tokio::test(start_paused = true)]
+async fn rescans_until_device_appears() {
+    let _serial = SERIAL.lock().await;
+    let strap = strap();
+    let adapter = FakeAdapter::new(vec![]);
+    let data = program_data(&[], vec![known_strap()]);
+    let mut receiver = get_receiver();
+    start_manager(vec![Arc::clone(&adapter)], &data);
+
+    wait_until(|| adapter.scans.load(Ordering::Acquire) >= 2).await;
+    assert!(!strap.connected());
+    adapter.add_peripheral(Arc::clone(&strap));
+
+    wait_until(|| strap.subscribed(HR_MEASUREMENT)).await;
+    strap.notify(HR_MEASUREMENT, vec![0, 58]);
+    assert_eq!(next_data(&mut receiver).await.hr, 58);
+}
+
+#[tokio::test(start_paused = true)]
+async fn declarative_adaptor_decodes_vendor_device() {
+    let _serial = SERIAL.lock().await;
+    let vendor_service = uuid_from_u16(0xfff0);
+    let vendor_data = uuid_from_u16(0xfff1);
+    let vendor_battery = uuid_from_u16(0xfff2);
+    let device = Arc::new(
+        FakePeripheral::new(STRAP_ADDRESS, "Vendor HR 2000")
+            .with_service(vendor_service, vec![
+                (vendor_data, CharPropFlags::NOTIFY, vec![]),
+                (vendor_battery, CharPropFlags::READ, vec![55]),
+            ])
+    );
+    let adapter = FakeAdapter::new(vec![Arc::clone(&device)]);
+    let data = program_data(&["--accept-new-device", "--hrm-mac", "AA:BB:CC:DD:EE:01"], vec![]);
+    data.merged_config.write().await.program_config.adaptors = serde_json::from_str(r#"[{
+        "adaptor_id": 100,
+        "match": {"name_regex": "^Vendor HR", "services": ["0000fff0-0000-1000-8000-00805f9b34fb"]},
+        "heart_rate": {"characteristic": "0000fff1-0000-1000-8000-00805f9b34fb", "offset": 1, "width": 2, "endianness": "big"},
+        "contact": {"characteristic": "0000fff1-0000-1000-8000-00805f9b34fb", "mask": 1},
+        "battery": {"characteristic": "0000fff2-0000-1000-8000-00805f9b34fb"}
+    }]"#).expect("invalid definition");
+    let mut receiver = get_receiver();
+    start_manager(vec![adapter], &data);
+
+    wait_until(|| device.subscribed(vendor_data)).await;
+    device.notify(vendor_data, vec![0b11, 0x00, 0x50]);
+    let hr = next_data(&mut receiver).await;
+    assert_eq!(hr.hr, 80);
+    assert_eq!(hr.contact_ok, Some(true));
+    assert_eq!(hr.battery, Some(55));
+    assert_eq!(data.merged_config.read().await.program_config.hrm_list.first().and_then(|h| h.adaptor_id), Some(100));
+}
+
+const RSC_SERVICE: Uuid = uuid_from_u16(0x1814);
+const RSC_MEASUREMENT: Uuid = uuid_from_u16(0x2a53);
+
+/// Running with 3 m/s, 170 steps/min, stride length 1.05 m and a total distance of 1000.5 m
+const RSC_VALUE: [u8; 10] = [0b111, 0x00, 0x03, 170, 105, 0x00, 0x15, 0x27, 0x00, 0x00];
+
+#[tokio::test(start_paused = true)]
+async fn declarative_adaptor_reads_fields_of_configured_service() {
+    let _serial = SERIAL.lock().await;
+    let vendor_data = uuid_from_u16(0xfff1);
+    let vendor_battery = uuid_from_u16(0xfff2);
+    // the same characteristic uuid in two services; only the second one notifies
+    let device = Arc::new(
+        FakePeripheral::new(STRAP_ADDRESS, "Vendor HR 2000")
+            .with_service(uuid_from_u16(0xfff0), vec![(vendor_data, CharPropFlags::READ, vec![0, 0])])
+            .with_service(uuid_from_u16(0xfff8), vec![
+                (vendor_data, CharPropFlags::NOTIFY, vec![0, 0]),
+                (vendor_battery, CharPropFlags::READ | CharPropFlags::NOTIFY, vec![55]),
+            ])
+    );
+    let adapter = FakeAdapter::new(vec![Arc::clone(&device)]);
+    let data = program_data(&["--accept-new-device", "--hrm-mac", "AA:BB:CC:DD:EE:01"], vec![]);
+    data.merged_config.write().await.program_config.adaptors = serde_json::from_str(r#"[{
+        "adaptor_id": 100,
+        "match": {"name_regex": "^Vendor HR"},
+        "heart_rate": {"service": "0000fff8-0000-1000-8000-00805f9b34fb", "characteristic": "0000fff1-0000-1000-8000-00805f9b34fb", "offset": 1},
+        "battery": {"characteristic": "0000fff2-0000-1000-8000-00805f9b34fb"}
+    }]"#).expect("invalid definition");
+    let mut receiver = get_receiver();
+    start_manager(vec![adapter], &data);
+
+    wait_until(|| device.subscribed(vendor_data) && device.subscribed(vendor_battery)).await;
+    device.notify(vendor_data, vec![0, 75]);
+    let hr = next_data(&mut receiver).await;
+    assert_eq!(hr.hr, 75);
+    // read at connect, although it also notifies
+    assert_eq!(hr.battery, Some(55));
+}
+
+#[tokio::test(start_paused = true)]
+async fn publishes_running_speed_and_cadence_of_footpod() {
+    let _serial = SERIAL.lock().await;
+    let footpod = Arc::new(
+        FakePeripheral::new(STRAP_ADDRESS, "Footpod")
+            .with_service(RSC_SERVICE, vec![(RSC_MEASUREMENT, CharPropFlags::NOTIFY, vec![])])
+            .with_service(BATTERY_SERVICE, vec![(BATTERY_LEVEL, CharPropFlags::READ | CharPropFlags::NOTIFY, vec![90])])
+    );
+    let adapter = FakeAdapter::new(vec![Arc::clone(&footpod)]);
+    let mut known = known_strap();
+    known.adaptor_id = None;
+    let data = program_data(&[], vec![known]);
+    let mut receiver = get_receiver();
+    start_manager(vec![adapter], &data);
+
+    wait_until(|| footpod.subscribed(RSC_MEASUREMENT)).await;
+    footpod.notify(RSC_MEASUREMENT, RSC_VALUE.to_vec());
+    let hr = next_data(&mut receiver).await;
+    let running = hr.running.expect("running data is missing");
+    assert!((running.speed - 3.0).abs() < f32::EPSILON);
+    assert_eq!(running.cadence, 170);
+    assert_eq!(running.stride_length, Some(1.05));
+    assert_eq!(running.total_distance, Some(1000.5));
+    assert!(running.is_running);
+    assert_eq!(hr.battery, Some(90));
+
+    footpod.notify(BATTERY_LEVEL, vec![89]);
+    assert_eq!(next_data(&mut receiver).await.battery, Some(89));
+
+    // optional fields missing in the next measurement are not kept
+    footpod.notify(RSC_MEASUREMENT, vec![0b000, 0x00, 0x02, 160]);
+    let walking = next_data(&mut receiver).await.running.expect("running data is missing");
+    assert_eq!(walking.cadence, 160);
+    assert_eq!((walking.stride_length, walking.total_distance), (None, None));
+    assert!(!walking.is_running);
+}
+
+#[tokio::test(start_paused = true)]
+async fn publishes_heart_rate_and_running_data_of_combined_strap() {
+    let _serial = SERIAL.lock().await;
+    let strap = Arc::new(
+        FakePeripheral::new(STRAP_ADDRESS, "Strap")
+            .with_service(HR_SERVICE, vec![(HR_MEASUREMENT, CharPropFlags::NOTIFY, vec![])])
+            .with_service(RSC_SERVICE, vec![(RSC_MEASUREMENT, CharPropFlags::NOTIFY, vec![])])
+    );
+    let adapter = FakeAdapter::new(vec![Arc::clone(&strap)]);
+    let mut known = known_strap();
+    known.adaptor_id = None;
+    let data = program_data(&[], vec![known]);
+    let mut receiver = get_receiver();
+    start_manager(vec![adapter], &data);
+
+    wait_until(|| strap.subscribed(HR_MEASUREMENT) && strap.subscribed(RSC_MEASUREMENT)).await;
+    strap.notify(HR_MEASUREMENT, vec![0, 150]);
+    assert_eq!(next_data(&mut receiver).await.hr, 150);
+    strap.notify(RSC_MEASUREMENT, RSC_VALUE.to_vec());
+    let hr = next_data(&mut receiver).await;
+    assert_eq!(hr.hr, 150);
+    assert_eq!(hr.running.map(|r| r.cadence), Some(170));
+}
+
+const CYCLING_POWER_SERVICE: Uuid = uuid_from_u16(0x1818);
+const CYCLING_POWER_MEASUREMENT: Uuid = uuid_from_u16(0x2a63);
+const CSC_SERVICE: Uuid = uuid_from_u16(0x1816);
+const CSC_MEASUREMENT: Uuid = uuid_from_u16(0x2a5b);
+
+#[tokio::test(start_paused = true)]
+async fn publishes_power_and_cadence_of_power_meter() {
+    let _serial = SERIAL.lock().await;
+    let power_meter = Arc::new(
+        FakePeripheral::new(STRAP_ADDRESS, "Power")
+            .with_service(CYCLING_POWER_SERVICE, vec![(CYCLING_POWER_MEASUREMENT, CharPropFlags::NOTIFY, vec![])])
+    );
+    let adapter = FakeAdapter::new(vec![Arc::clone(&power_meter)]);
+    let data = program_data(&["--accept-new-device", "--hrm-mac", "AA:BB:CC:DD:EE:01"], vec![]);
+    let mut receiver = get_receiver();
+    start_manager(vec![adapter], &data);
+
+    wait_until(|| power_meter.subscribed(CYCLING_POWER_MEASUREMENT)).await;
+    // 200 W, 51 % right pedal, 10 crank revolutions at 0 s
+    power_meter.notify(CYCLING_POWER_MEASUREMENT, vec![0b10_0001, 0x00, 200, 0x00, 102, 10, 0x00, 0x00, 0x00]);
+    let first = next_data(&mut receiver).await.cycling.expect("cycling data is missing");
+    assert_eq!(first.power, Some(200));
+    assert_eq!(first.pedal_balance, Some(51.0));
+    // a single crank event is not enough to calculate the cadence
+    assert_eq!(first.cadence, None);
+
+    // -5 W (braking), one more crank revolution after 1 s
+    power_meter.notify(CYCLING_POWER_MEASUREMENT, vec![0b10_0000, 0x00, 0xFB, 0xFF, 11, 0x00, 0x00, 0x04]);
+    let cycling = next_data(&mut receiver).await.cycling.expect("cycling data is missing");
+    assert_eq!(cycling.power, Some(-5));
+    assert_eq!(cycling.cadence, Some(60.0));
+    assert_eq!(data.merged_config.read().await.program_config.hrm_list.first().and_then(|h| h.adaptor_id), Some(3));
+}
+
+#[tokio::test(start_paused = true)]
+async fn calculates_speed_and_cadence_across_counter_wrap_around() {
+    let _serial = SERIAL.lock().await;
+    let sensor = Arc::new(
+        FakePeripheral::new(STRAP_ADDRESS, "Speed")
+            .with_service(CSC_SERVICE, vec![(CSC_MEASUREMENT, CharPropFlags::NOTIFY, vec![])])
+            .with_service(BATTERY_SERVICE, vec![(BATTERY_LEVEL, CharPropFlags::READ, vec![70])])
+    );
+    let adapter = FakeAdapter::new(vec![Arc::clone(&sensor)]);
+    let mut known = known_strap();
+    known.adaptor_id = None;
+    let data = program_data(&[], vec![known]);
+    let mut receiver = get_receiver();
+    start_manager(vec![adapter], &data);
+
+    wait_until(|| sensor.subscribed(CSC_MEASUREMENT)).await;
+    // wheel and crank counters and event times right before they wrap around
+    let before_wrap = vec![0b11, 0xFF, 0xFF, 0xFF, 0xFF, 0x00, 0xFC, 0xFF, 0xFF, 0x00, 0xFC];
+    sensor.notify(CSC_MEASUREMENT, before_wrap);
+    let hr = next_data(&mut receiver).await;
+    assert_eq!(hr.battery, Some(70));
+    assert_eq!(hr.cycling.and_then(|c| c.speed), None);
+
+    // 4 wheel and 2 crank revolutions within 2 s
+    let after_wrap = vec![0b11, 0x03, 0x00, 0x00, 0x00, 0x00, 0x04, 0x01, 0x00, 0x00, 0x04];
+    sensor.notify(CSC_MEASUREMENT, after_wrap.clone());
+    let moving = next_data(&mut receiver).await.cycling.expect("cycling data is missing");
+    assert!(moving.speed.is_some_and(|s| (s - 2.0 * 2.105).abs() < 0.001));
+    assert_eq!(moving.cadence, Some(60.0));
+
+    // the sensor repeats its last values while standing still
+    for _ in 0..3 {
+        sensor.notify(CSC_MEASUREMENT, after_wrap.clone());
+        next_data(&mut receiver).await;
+    }
+    sensor.notify(CSC_MEASUREMENT, after_wrap);
+    let cycling = next_data(&mut receiver).await.cycling.expect("cycling data is missing");
+    assert_eq!(cycling.speed, Some(0.0));
+    assert_eq!(cycling.cadence, Some(0.0));
+}
+
+#[tokio::test(start_paused = true)]
+async fn uses_wheel_circumference_of_device() {
+    let _serial = SERIAL.lock().await;
+    let sensor = Arc::new(
+        FakePeripheral::new(STRAP_ADDRESS, "Speed")
+            .with_service(CSC_SERVICE, vec![(CSC_MEASUREMENT, CharPropFlags::NOTIFY, vec![])])
+    );
+    let adapter = FakeAdapter::new(vec![Arc::clone(&sensor)]);
+    let mut known = known_strap();
+    known.adaptor_id = None;
+    // 26 x 2.1 mountain bike wheel
+    known.wheel_circumference = Some(2.068);
+    let data = program_data(&[], vec![known]);
+    let mut receiver = get_receiver();
+    let manager = start_manager(vec![adapter], &data);
+
+    wait_until(|| sensor.subscribed(CSC_MEASUREMENT)).await;
+    sensor.notify(CSC_MEASUREMENT, vec![0b1, 0x0A, 0x00, 0x00, 0x00, 0x00, 0x04]);
+    next_data(&mut receiver).await;
+    // 4 wheel revolutions within 2 s
+    sensor.notify(CSC_MEASUREMENT, vec![0b1, 0x0E, 0x00, 0x00, 0x00, 0x00, 0x0C]);
+    let cycling = next_data(&mut receiver).await.cycling.expect("cycling data is missing");
+    assert!(cycling.speed.is_some_and(|s| (s - 2.0 * 2.068).abs() < 0.001));
+    assert!(cycling.distance.is_some_and(|d| (d - 14.0 * 2.068).abs() < 0.001));
+    // kept, when the profile of the device is updated
+    assert_eq!(manager.connected_hrm().await.and_then(|h| h.wheel_circumference), Some(2.068));
+    assert_eq!(data.merged_config.read().await.program_config.hrm_list.first().and_then(|h| h.wheel_circumference), Some(2.068));
+}
+
+const PLX_SERVICE: Uuid = uuid_from_u16(0x1822);
+const PLX_SPOT_CHECK: Uuid = uuid_from_u16(0x2a5e);
+const PLX_CONTINUOUS: Uuid = uuid_from_u16(0x2a5f);
+
+#[test]
+fn decodes_sfloat_including_special_values() {
+    let sfloat = |raw: u16| read_sfloat(&raw.to_le_bytes(), 0).expect("value too short");
+    assert!((sfloat(0xF3CF) - 97.5).abs() < 0.001);
+    assert!((sfloat(0x0048) - 72.0).abs() < f32::EPSILON);
+    assert!((sfloat(0x0FFB) + 5.0).abs() < f32::EPSILON);
+    assert!((sfloat(0x2003) - 300.0).abs() < f32::EPSILON);
+    assert!(sfloat(0x07FF).is_nan());
+    assert!(sfloat(0x0800).is_nan());
+    assert!(sfloat(0x0801).is_nan());
+    assert!(sfloat(0x07FE).is_infinite() && sfloat(0x07FE).is_sign_positive());
+    assert!(sfloat(0x0802).is_infinite() && sfloat(0x0802).is_sign_negative());
+    // special values only exist with an exponent of 0
+    assert!((sfloat(0xF7FF) - 204.7).abs() < 0.001);
+    assert!((sfloat(0x1802) + 20_460.0).abs() < f32::EPSILON);
+    assert!(read_sfloat(&[0x48], 0).is_err());
+}
+
+#[tokio::test(start_paused = true)]
+async fn publishes_spo2_and_pulse_rate_of_pulse_oximeter() {
+    let _serial = SERIAL.lock().await;
+    let oximeter = Arc::new(
+        FakePeripheral::new(STRAP_ADDRESS, "Oximeter")
+            .with_service(PLX_SERVICE, vec![
+                (PLX_SPOT_CHECK, CharPropFlags::INDICATE, vec![]),
+                (PLX_CONTINUOUS, CharPropFlags::NOTIFY, vec![]),
+            ])
+    );
+    let adapter = FakeAdapter::new(vec![Arc::clone(&oximeter)]);
+    let data = program_data(&["--accept-new-device", "--hrm-mac", "AA:BB:CC:DD:EE:01"], vec![]);
+    let mut receiver = get_receiver();
+    start_manager(vec![adapter], &data);
+
+    wait_until(|| oximeter.subscribed(PLX_SPOT_CHECK) && oximeter.subscribed(PLX_CONTINUOUS)).await;
+    // 97.5 % and 72 bpm
+    oximeter.notify(PLX_CONTINUOUS, vec![0x00, 0xCF, 0xF3, 0x48, 0x00]);
+    let hr = next_data(&mut receiver).await;
+    assert_eq!(hr.hr, 72);
+    assert!(hr.spo2.is_some_and(|s| (s - 97.5).abs() < 0.001));
+
+    // finger removed: SpO2 is NaN, pulse rate NRes
+    oximeter.notify(PLX_SPOT_CHECK, vec![0x00, 0xFF, 0x07, 0x00, 0x08]);
+    let removed = next_data(&mut receiver).await;
+    assert_eq!(removed.hr, 0);
+    assert_eq!(removed.spo2, None);
+    assert_eq!(data.merged_config.read().await.program_config.hrm_list.first().and_then(|h| h.adaptor_id), Some(5));
+}
+
+const PMD_SERVICE: Uuid = Uuid::from_u128(0xfb005c80_02e7_f387_1cad_8acd2d8df0c8);
+
+/// ECG frame with the samples -30, 512 and 1 µV; the last one was taken at 1 s
+const ECG_FRAME: [u8; 19] = [0x00, 0x00, 0xCA, 0x9A, 0x3B, 0x00, 0x00, 0x00, 0x00, 0x00, 0xE2, 0xFF, 0xFF, 0x00, 0x02, 0x00, 0x01, 0x00, 0x00];
+
+/// Delta compressed accelerometer frame: reference sample (-100, 200, 1000) mG, followed by one block of
+/// two samples with 4 bit deltas (1, -1, 2) and (-8, 7, 0)
+const COMPRESSED_ACC_FRAME: [u8; 21] = [
+    0x02, 0x00, 0xCA, 0x9A, 0x3B, 0x00, 0x00, 0x00, 0x00, 0x81,
+    0x9C, 0xFF, 0xC8, 0x00, 0xE8, 0x03,
+    0x04, 0x02, 0xF1, 0x82, 0x07,
+];
+
+/// Settings the accelerometer offers: sample rates 52 and 200 Hz, 16 bit resolution, ranges 2, 4 and 8 G
+const ACC_SETTINGS: [u8; 23] = [
+    0xF0, 0x01, 0x02, 0x00, 0x00,
+    0x00, 0x02, 0x34, 0x00, 0xC8, 0x00,
+    0x01, 0x01, 0x10, 0x00,
+    0x02, 0x03, 0x02, 0x00, 0x04, 0x00, 0x08, 0x00,
+];
+
+#[test]
+fn decodes_uncompressed_pmd_frames() {
+    let ecg = decode_pmd_frame(&ECG_FRAME, 14).expect("could not decode ECG frame");
+    assert_eq!(ecg.measurement, RawMeasurement::Ecg);
+    assert_eq!(ecg.sensor_timestamp, 1_000_000_000);
+    assert_eq!(ecg.samples, vec![vec![-30], vec![512], vec![1]]);
+
+    let mut acc_frame = COMPRESSED_ACC_FRAME[..10].to_vec();
+    acc_frame[9] = 0x01;
+    acc_frame.extend([0xFC, 0xFF, 0x08, 0x00, 0xE8, 0x03]);
+    let acc = decode_pmd_frame(&acc_frame, 16).expect("could not decode accelerometer frame");
+    assert_eq!(acc.measurement, RawMeasurement::Acc);
+    assert_eq!(acc.samples, vec![vec![-4, 8, 1000]]);
+
+    // incomplete sample
+    assert!(decode_pmd_frame(&ECG_FRAME[..18], 14).is_err());
+    // unknown measurement type
+    assert!(decode_pmd_frame(&[0x09; 12], 14).is_err());
+}
+
+#[test]
+fn decodes_delta_compressed_pmd_frames() {
+    let acc = decode_pmd_frame(&COMPRESSED_ACC_FRAME, 16).expect("could not decode compressed frame");
+    assert_eq!(acc.measurement, RawMeasurement::Acc);
+    assert_eq!(acc.samples, vec![vec![-100, 200, 1000], vec![-99, 199, 1002], vec![-107, 206, 1002]]);
+
+    // delta block is cut off
+    assert!(decode_pmd_frame(&COMPRESSED_ACC_FRAME[..20], 16).is_err());
+    // resolutions, which do not fit a sample
+    assert!(decode_pmd_frame(&COMPRESSED_ACC_FRAME, 0).is_err());
+    assert!(decode_pmd_frame(&COMPRESSED_ACC_FRAME, 40).is_err());
+    // deltas wider than a sample
+    let mut malformed = COMPRESSED_ACC_FRAME[..16].to_vec();
+    malformed.extend([40, 0x01]);
+    malformed.extend([0xFF; 15]);
+    assert!(decode_pmd_frame(&malformed, 16).is_err());
+}
+
+#[test]
+fn negotiates_pmd_stream_settings() {
+    let settings = negotiate_settings(RawMeasurement::Acc, &ACC_SETTINGS).expect("could not parse settings");
+    assert_eq!(settings.values, vec![(0, 200), (1, 16), (2, 8)]);
+    assert_eq!(start_command(RawMeasurement::Acc, &settings), vec![0x02, 0x02, 0, 1, 0xC8, 0, 1, 1, 0x10, 0, 2, 1, 8, 0]);
+
+    let mut refused = ACC_SETTINGS.to_vec();
+    refused[3] = 0x05;
+    assert!(negotiate_settings(RawMeasurement::Acc, &refused).is_err());
+}
+
+#[tokio::test(start_paused = true)]
+async fn streams_raw_samples_of_polar_strap() {
+    let _serial = SERIAL.lock().await;
+    let strap = Arc::new(
+        FakePeripheral::new(STRAP_ADDRESS, "Polar H10")
+            .with_service(HR_SERVICE, vec![(HR_MEASUREMENT, CharPropFlags::NOTIFY, vec![])])
+            .with_service(PMD_SERVICE, vec![
+                // ECG and accelerometer supported
+                (PMD_CONTROL_POINT, CharPropFlags::READ | CharPropFlags::WRITE | CharPropFlags::INDICATE, vec![0x0F, 0b101]),
+                (PMD_DATA, CharPropFlags::NOTIFY, vec![]),
+            ])
+            .with_responder(PMD_CONTROL_POINT, |command| match command {
+                [0x01, 0x00] => Some(vec![0xF0, 0x01, 0x00, 0x00, 0x00, 0x00, 0x01, 0x82, 0x00, 0x01, 0x01, 0x0E, 0x00]),
+                [0x01, 0x02] => Some(ACC_SETTINGS.to_vec()),
+                [0x02, measurement, ..] => Some(vec![0xF0, 0x02, *measurement, 0x00, 0x00]),
+                _ => None,
+            })
+    );
+    let adapter = FakeAdapter::new(vec![Arc::clone(&strap)]);
+    let data = program_data(&["--accept-new-device", "--hrm-mac", "AA:BB:CC:DD:EE:01"], vec![]);
+    let mut receiver = get_receiver();
+    let mut raw_receiver = get_raw_receiver();
+    start_manager(vec![adapter], &data);
+
+    wait_until(|| strap.subscribed(HR_MEASUREMENT)).await;
+    let commands = strap.written().into_iter().filter(|(uuid, _)| *uuid == PMD_CONTROL_POINT).map(|(_, c)| c).collect::<Vec<_>>();
+    assert!(commands.contains(&vec![0x02, 0x00, 0x00, 0x01, 0x82, 0x00, 0x01, 0x01, 0x0E, 0x00]));
+    assert!(commands.contains(&vec![0x02, 0x02, 0, 1, 0xC8, 0, 1, 1, 0x10, 0, 2, 1, 8, 0]));
+
+    strap.notify(PMD_DATA, ECG_FRAME.to_vec());
+    let raw = timeout(Duration::from_mins(1), raw_receiver.recv()).await.expect("no raw samples in time").expect("channel closed");
+    assert_eq!(raw.device, Some(MacAddress::new(STRAP_ADDRESS)));
+    assert_eq!(raw.measurement, RawMeasurement::Ecg);
+    assert_eq!(raw.sample_rate, 130);
+    assert_eq!(raw.samples, vec![vec![-30], vec![512], vec![1]]);
+
+    // raw samples do not publish the heart rate state
+    strap.notify(HR_MEASUREMENT, vec![0, 72]);
+    assert_eq!(next_data(&mut receiver).await.hr, 72);
+    assert_eq!(data.merged_config.read().await.program_config.hrm_list.first().and_then(|h| h.adaptor_id), Some(6));
+}
+
+#[tokio::test(start_paused = true)]
+async fn restarts_raw_streams_after_reconnect() {
+    let _serial = SERIAL.lock().await;
+    let strap = Arc::new(
+        FakePeripheral::new(STRAP_ADDRESS, "Polar H10")
+            .with_service(HR_SERVICE, vec![(HR_MEASUREMENT, CharPropFlags::NOTIFY, vec![])])
+            .with_service(PMD_SERVICE, vec![
+                // only ECG supported
+                (PMD_CONTROL_POINT, CharPropFlags::READ | CharPropFlags::WRITE | CharPropFlags::INDICATE, vec![0x0F, 0b1]),
+                (PMD_DATA, CharPropFlags::NOTIFY, vec![]),
+            ])
+            .with_responder(PMD_CONTROL_POINT, |command| match command {
+                [0x01, 0x00] => Some(vec![0xF0, 0x01, 0x00, 0x00, 0x00, 0x00, 0x01, 0x82, 0x00, 0x01, 0x01, 0x0E, 0x00]),
+                [0x02, measurement, ..] => Some(vec![0xF0, 0x02, *measurement, 0x00, 0x00]),
+                _ => None,
+            })
+    );
+    let starts = || strap.written().into_iter().filter(|(uuid, c)| *uuid == PMD_CONTROL_POINT && c.first() == Some(&0x02)).count();
+    let adapter = FakeAdapter::new(vec![Arc::clone(&strap)]);
+    let data = program_data(&["--accept-new-device", "--hrm-mac", "AA:BB:CC:DD:EE:01"], vec![]);
+    let mut raw_receiver = get_raw_receiver();
+    start_manager(vec![Arc::clone(&adapter)], &data);
+
+    wait_until(|| strap.subscribed(HR_MEASUREMENT) && starts() == 1).await;
+    strap.drop_connection();
+    wait_until(|| !strap.connected()).await;
+    wait_until(|| strap.connected()).await;
+
+    // the stream is started again on the same connection, without a new scan
+    wait_until(|| starts() == 2).await;
+    assert_eq!(adapter.scans.load(Ordering::Acquire), 1);
+    strap.notify(PMD_DATA, ECG_FRAME.to_vec());
+    let raw = timeout(Duration::from_mins(1), raw_receiver.recv()).await.expect("no raw samples in time").expect("channel closed");
+    assert_eq!(raw.measurement, RawMeasurement::Ecg);
+    assert_eq!(raw.samples, vec![vec![-30], vec![512], vec![1]]);
+}
+
+#[tokio::test(start_paused = true)]
+async fn prefers_heart_rate_adaptor_for_device_with_several_services() {
+    let _serial = SERIAL.lock().await;
+    let device = Arc::new(
+        FakePeripheral::new(STRAP_ADDRESS, "Bike Computer")
+            .with_service(CYCLING_POWER_SERVICE, vec![(CYCLING_POWER_MEASUREMENT, CharPropFlags::NOTIFY, vec![])])
+            .with_service(HR_SERVICE, vec![(HR_MEASUREMENT, CharPropFlags::NOTIFY, vec![])])
+    );
+    let adapter = FakeAdapter::new(vec![Arc::clone(&device)]);
+    let data = program_data(&["--accept-new-device", "--hrm-mac", "AA:BB:CC:DD:EE:01"], vec![]);
+    let mut receiver = get_receiver();
+    start_manager(vec![adapter], &data);
+
+    wait_until(|| device.subscribed(HR_MEASUREMENT)).await;
+    assert!(!device.subscribed(CYCLING_POWER_MEASUREMENT));
+    device.notify(HR_MEASUREMENT, vec![0, 130]);
+    assert_eq!(next_data(&mut receiver).await.hr, 130);
+    assert_eq!(data.merged_config.read().await.program_config.hrm_list.first().and_then(|h| h.adaptor_id), Some(1));
+}
+
+#[tokio::test(start_paused = true)]
+async fn prefers_matching_declarative_adaptor_over_standard_service() {
+    let _serial = SERIAL.lock().await;
+    let vendor_data = uuid_from_u16(0xfff1);
+    let device = Arc::new(
+        FakePeripheral::new(STRAP_ADDRESS, "Vendor HR 3000")
+            .with_service(HR_SERVICE, vec![(HR_MEASUREMENT, CharPropFlags::NOTIFY, vec![])])
+            .with_service(uuid_from_u16(0xfff0), vec![(vendor_data, CharPropFlags::NOTIFY, vec![])])
+    );
+    let adapter = FakeAdapter::new(vec![Arc::clone(&device)]);
+    let data = program_data(&["--accept-new-device", "--hrm-mac", "AA:BB:CC:DD:EE:01"], vec![]);
+    data.merged_config.write().await.program_config.adaptors = serde_json::from_str(r#"[{
+        "adaptor_id": 100,
+        "match": {"name_regex": "^Vendor HR"},
+        "heart_rate": {"characteristic": "0000fff1-0000-1000-8000-00805f9b34fb", "offset": 0, "width": 1}
+    }]"#).expect("invalid definition");
+    let mut receiver = get_receiver();
+    start_manager(vec![adapter], &data);
+
+    wait_until(|| device.subscribed(vendor_data)).await;
+    assert!(!device.subscribed(HR_MEASUREMENT));
+    device.notify(vendor_data, vec![95]);
+    assert_eq!(next_data(&mut receiver).await.hr, 95);
+    assert_eq!(data.merged_config.read().await.program_config.hrm_list.first().and_then(|h| h.adaptor_id), Some(100));
+}
+
+#[test]
+fn converts_hex_strings() {
+    assert_eq!(Hex::parse("0a ff00").ok(), Some(Hex(vec![0x0A, 0xFF, 0x00])));
+    assert_eq!(Hex(vec![0x0A, 0xFF, 0x00]).to_string(), "0aff00");
+    assert!(Hex::parse("abc").is_err());
+    assert!(Hex::parse("zz").is_err());
+}
+
+#[tokio::test(start_paused = true)]
+async fn captures_debug_session_and_loads_it_as_device() {
+    let _serial = SERIAL.lock().await;
+    let path = std::env::temp_dir().join(format!("hrm-capture-{}.jsonl", std::process::id()));
+    let path_arg = path.to_string_lossy().into_owned();
+    let strap = strap();
+    let debug_data = program_data(&["--debug-device", "--capture", &path_arg, "--accept-new-device", "--hrm-mac", "AA:BB:CC:DD:EE:01"], vec![]);
+    start_manager(vec![FakeAdapter::new(vec![Arc::clone(&strap)])], &debug_data);
+
+    wait_until(|| strap.subscribed(HR_MEASUREMENT) && strap.subscribed(BATTERY_LEVEL)).await;
+    strap.notify(HR_MEASUREMENT, vec![0, 72]);
+    strap.notify(BATTERY_LEVEL, vec![79]);
+    wait_until(|| std::fs::read_to_string(&path).is_ok_and(|c| c.lines().count() == 3)).await;
+
+    let capture = Capture::load(&path).expect("capture could not be loaded");
+    let _ = std::fs::remove_file(&path);
+    assert_eq!(capture.header.device.name, "Strap");
+    assert_eq!(capture.header.device.address, "AA:BB:CC:DD:EE:01");
+    let manufacturer = capture.header.services.iter()
+        .flat_map(|s| &s.characteristics)
+        .find(|c| c.uuid == MANUFACTURER_NAME)
+        .expect("manufacturer name is missing");
+    assert_eq!(manufacturer.value, Some(Hex(b"ACME".to_vec())));
+    assert_eq!(manufacturer.properties, vec!["READ"]);
+    assert_eq!(capture.notifications.iter().map(|n| (n.uuid, n.value.clone())).collect::<Vec<_>>(), vec![
+        (HR_MEASUREMENT, Hex(vec![0, 72])),
+        (BATTERY_LEVEL, Hex(vec![79])),
+    ]);
+
+    // the captured device behaves like the original one
+    let replayed = Arc::new(FakePeripheral::from_capture(&capture.header).expect("capture could not be converted"));
+    let data = program_data(&["--accept-new-device", "--hrm-mac", "AA:BB:CC:DD:EE:01"], vec![]);
+    let mut receiver = get_receiver();
+    start_manager(vec![FakeAdapter::new(vec![Arc::clone(&replayed)])], &data);
+    wait_until(|| replayed.subscribed(HR_MEASUREMENT)).await;
+    for notification in &capture.notifications {
+        replayed.notify(notification.uuid, notification.value.0.clone());
+    }
+    let hr = next_data(&mut receiver).await;
+    assert_eq!(hr.hr, 72);
+    assert_eq!(hr.battery, Some(80));
+    assert_eq!(next_data(&mut receiver).await.battery, Some(79));
+}
+
+#[tokio::test(start_paused = true)]
+async fn captures_each_session_of_reconnected_debug_device() {
+    let _serial = SERIAL.lock().await;
+    let path = std::env::temp_dir().join(format!("hrm-sessions-{}.jsonl", std::process::id()));
+    let second_path = session_path(&path, 2);
+    let path_arg = path.to_string_lossy().into_owned();
+    let strap = strap();
+    let adapter = FakeAdapter::new(vec![Arc::clone(&strap)]);
+    let data = program_data(&["--debug-device", "--capture", &path_arg, "--accept-new-device", "--hrm-mac", "AA:BB:CC:DD:EE:01"], vec![]);
+    start_manager(vec![Arc::clone(&adapter)], &data);
+
+    wait_until(|| strap.subscribed(HR_MEASUREMENT)).await;
+    strap.notify(HR_MEASUREMENT, vec![0, 72]);
+    wait_until(|| std::fs::read_to_string(&path).is_ok_and(|c| c.lines().count() == 2)).await;
+
+    // the connection is lost for good, so the device is found and connected again
+    strap.set_connectable(false);
+    strap.drop_connection();
+    wait_until(|| adapter.scans.load(Ordering::Acquire) >= 2).await;
+    strap.set_connectable(true);
+    wait_until(|| strap.connected() && strap.subscribed(HR_MEASUREMENT)).await;
+    strap.notify(HR_MEASUREMENT, vec![0, 73]);
+    wait_until(|| std::fs::read_to_string(&second_path).is_ok_and(|c| c.lines().count() == 2)).await;
+
+    let first = Capture::load(&path).expect("first capture could not be loaded");
+    let second = Capture::load(&second_path).expect("second capture could not be loaded");
+    let _ = std::fs::remove_file(&path);
+    let _ = std::fs::remove_file(&second_path);
+    assert_eq!(first.notifications.iter().map(|n| n.value.clone()).collect::<Vec<_>>(), vec![Hex(vec![0, 72])]);
+    assert_eq!(second.notifications.iter().map(|n| n.value.clone()).collect::<Vec<_>>(), vec![Hex(vec![0, 73])]);
+}
+
+#[test]
+fn parses_explorer_commands() {
+    assert_eq!("read 2a37".parse(), Ok(ExplorerCommand::Read(HR_MEASUREMENT)));
+    assert_eq!(
+        "write_nr 00002a39-0000-1000-8000-00805f9b34fb 01 ff".parse(),
+        Ok(ExplorerCommand::Write { uuid: HR_CONTROL_POINT, value: vec![0x01, 0xFF], with_response: false }),
+    );
+    assert_eq!("filter 2a37 2a19".parse(), Ok(ExplorerCommand::Filter([HR_MEASUREMENT, BATTERY_LEVEL].into())));
+    assert_eq!("filter".parse(), Ok(ExplorerCommand::Filter([].into())));
+    assert!("write 2a39".parse::<ExplorerCommand>().is_err());
+    assert!("read".parse::<ExplorerCommand>().is_err());
+    assert!("read 2a37 2a38".parse::<ExplorerCommand>().is_err());
+    assert!("unknown".parse::<ExplorerCommand>().is_err());
+}
+
+#[tokio::test]
+async fn explores_connected_device() {
+    let strap = Arc::new(
+        FakePeripheral::new(STRAP_ADDRESS, "Strap")
+            .with_service(HR_SERVICE, vec![
+                (HR_MEASUREMENT, CharPropFlags::NOTIFY, vec![]),
+                (HR_CONTROL_POINT, CharPropFlags::WRITE, vec![]),
+            ])
+            .with_service(BATTERY_SERVICE, vec![(BATTERY_LEVEL, CharPropFlags::READ | CharPropFlags::NOTIFY, vec![80])])
+    );
+    strap.connect().await.expect("connecting failed");
+    strap.discover_services().await.expect("discovering services failed");
+    let explorer = Explorer::new(Arc::clone(&strap) as _);
+
+    let services = explorer.execute(ExplorerCommand::Services).await.expect("listing services failed");
+    assert!(services.contains("00002a39-0000-1000-8000-00805f9b34fb: WRITE"));
+    assert_eq!(explorer.execute(ExplorerCommand::Read(BATTERY_LEVEL)).await.ok(), Some(format!("{BATTERY_LEVEL}: 50")));
+    assert!(explorer.execute(ExplorerCommand::Read(HR_MEASUREMENT)).await.is_err());
+
+    explorer.execute(ExplorerCommand::Write { uuid: HR_CONTROL_POINT, value: vec![0x01], with_response: true })
+        .await
+        .expect("writing failed");
+    assert_eq!(strap.written(), vec![(HR_CONTROL_POINT, vec![0x01])]);
+    assert!(explorer.execute(ExplorerCommand::Write { uuid: HR_CONTROL_POINT, value: vec![0x01], with_response: false }).await.is_err());
+
+    explorer.execute(ExplorerCommand::Subscribe(HR_MEASUREMENT)).await.expect("subscribing failed");
+    assert!(strap.subscribed(HR_MEASUREMENT));
+    explorer.execute(ExplorerCommand::Unsubscribe(HR_MEASUREMENT)).await.expect("unsubscribing failed");
+    assert!(!strap.subscribed(HR_MEASUREMENT));
+
+    explorer.execute(ExplorerCommand::Filter([BATTERY_LEVEL].into())).await.expect("filtering failed");
+    assert!(explorer.shows(BATTERY_LEVEL).await);
+    assert!(!explorer.shows(HR_MEASUREMENT).await);
+    explorer.execute(ExplorerCommand::Filter([].into())).await.expect("filtering failed");
+    assert!(explorer.shows(HR_MEASUREMENT).await);
+}
+
+#[tokio::test(start_paused = true)]
+async fn connects_to_multiple_devices_and_tags_their_data() {
+    let _serial = SERIAL.lock().await;
+    let first = strap();
+    let second = Arc::new(
+        FakePeripheral::new([0xAA, 0xBB, 0xCC, 0xDD, 0xEE, 0x02], "Second Strap")
+            .with_service(HR_SERVICE, vec![(HR_MEASUREMENT, CharPropFlags::NOTIFY, vec![])])
+    );
+    let adapter = FakeAdapter::new(vec![Arc::clone(&first), Arc::clone(&second)]);
+    let data = program_data(&["--device", "AA:BB:CC:DD:EE:01", "--device", "AA:BB:CC:DD:EE:02"], vec![known_strap()]);
+    let mut receiver = get_receiver();
+    let manager = start_manager(vec![adapter], &data);
+
+    wait_until(|| first.subscribed(HR_MEASUREMENT) && second.subscribed(HR_MEASUREMENT)).await;
+    first.notify(HR_MEASUREMENT, vec![0, 70]);
+    second.notify(HR_MEASUREMENT, vec![0, 140]);
+    let mut updates = vec![];
+    for _ in 0..2 {
+        let update = timeout(Duration::from_mins(1), receiver.recv()).await.expect("no data in time").expect("channel closed");
+        let Some(HrmState::Ok(hr)) = update.hr_state else {
+            panic!("expected data");
+        };
+        updates.push((update.device.map(|d| d.to_string()), hr.hr));
+    }
+    updates.sort();
+    assert_eq!(updates, vec![
+        (Some("AA:BB:CC:DD:EE:01".to_owned()), 70),
+        (Some("AA:BB:CC:DD:EE:02".to_owned()), 140),
+    ]);
+
+    // the unknown device was paired, because it was configured explicitly
+    let hrm_list = data.merged_config.read().await.program_config.hrm_list.iter().map(|h| h.name.clone()).collect::<Vec<_>>();
+    assert_eq!(hrm_list, vec!["Strap", "Second Strap"]);
+    assert_eq!(manager.connected_hrms().await.len(), 2);
+
+    // losing one device does not affect the other one
+    second.set_connectable(false);
+    second.drop_connection();
+    loop {
+        let update = timeout(Duration::from_mins(1), receiver.recv()).await.expect("no data in time").expect("channel closed");
+        if let Some(HrmState::Disconnected) = update.hr_state {
+            assert_eq!(update.device.map(|d| d.to_string()), Some("AA:BB:CC:DD:EE:02".to_owned()));
+            break;
+        }
+    }
+    first.notify(HR_MEASUREMENT, vec![0, 71]);
+    assert_eq!(next_data(&mut receiver).await.hr, 71);
+}
+
+#[tokio::test(start_paused = true)]
+async fn resets_energy_expended_of_devices_supporting_it() {
+    let _serial = SERIAL.lock().await;
+    let first = strap();
+    // no heart rate control point
+    let second = Arc::new(
+        FakePeripheral::new([0xAA, 0xBB, 0xCC, 0xDD, 0xEE, 0x02], "Second Strap")
+            .with_service(HR_SERVICE, vec![(HR_MEASUREMENT, CharPropFlags::NOTIFY, vec![])])
+    );
+    let adapter = FakeAdapter::new(vec![Arc::clone(&first), Arc::clone(&second)]);
+    let data = program_data(&["--device", "AA:BB:CC:DD:EE:01", "--device", "AA:BB:CC:DD:EE:02"], vec![known_strap()]);
+    let manager = start_manager(vec![adapter], &data);
+    wait_until(|| first.subscribed(HR_MEASUREMENT) && second.subscribed(HR_MEASUREMENT)).await;
+
+    // the device without support is skipped
+    manager.reset_energy_expended().await.expect("reset failed");
+    assert_eq!(first.written(), vec![(HR_CONTROL_POINT, vec![0x01])]);
+
+    manager.reset_device_energy_expended(MacAddress::new(STRAP_ADDRESS)).await.expect("reset failed");
+    assert_eq!(first.written().len(), 2);
+    assert!(manager.reset_device_energy_expended("AA:BB:CC:DD:EE:02".parse().expect("invalid mac")).await.is_err());
+    assert!(manager.reset_device_energy_expended("AA:BB:CC:DD:EE:03".parse().expect("invalid mac")).await.is_err());
+    assert!(second.written().is_empty());
+}
+
+#[tokio::test(start_paused = true)]
+async fn slots_take_turns_scanning_with_shared_adapter() {
+    let _serial = SERIAL.lock().await;
+    let adapter = FakeAdapter::new(vec![]);
+    let data = program_data(&["--device", "AA:BB:CC:DD:EE:01", "--device", "AA:BB:CC:DD:EE:02"], vec![]);
+    let manager = start_manager(vec![Arc::clone(&adapter)], &data);
+
+    // neither device is in range, so both slots keep scanning
+    wait_until(|| adapter.stopped_scans.load(Ordering::Acquire) >= 4).await;
+    assert_eq!(adapter.overlapping_scans.load(Ordering::Acquire), 0);
+    // a scan requested remotely waits for its turn as well
+    manager.scan(&data).await.expect("scan failed");
+    assert_eq!(adapter.overlapping_scans.load(Ordering::Acquire), 0);
+}
+
+const FITNESS_MACHINE_SERVICE: Uuid = uuid_from_u16(0x1826);
+const TREADMILL_DATA: Uuid = uuid_from_u16(0x2acd);
+
+#[test]
+fn decodes_fitness_machine_data() {
+    let approx = |value: Option<f32>, expected: f32| value.is_some_and(|v| (v - expected).abs() < 0.001);
+    let mut hr = 0;
+
+    // 10.8 km/h, 1234 m, 2.5 % incline, 140 bpm, 600 s, 250 W
+    let mut treadmill = MachineData::default();
+    parse_treadmill_data(&[
+        0x0C, 0x15, 0x38, 0x04, 0xD2, 0x04, 0x00, 0x19, 0x00, 0x00, 0x00, 140, 0x58, 0x02, 0x00, 0x00, 0xFA, 0x00,
+    ], &mut treadmill, &mut hr).expect("invalid treadmill data");
+    assert_eq!(treadmill.kind, Some(MachineKind::Treadmill));
+    assert!(approx(treadmill.speed, 3.0));
+    assert!(approx(treadmill.incline, 2.5));
+    assert_eq!((treadmill.distance, treadmill.elapsed_time, treadmill.power, hr), (Some(1234), Some(600), Some(250), 140));
+
+    // "more data" packet with the energy only; the speed of the previous packet is kept
+    parse_treadmill_data(&[0x81, 0x00, 50, 0x00, 0x00, 0x00, 0x00], &mut treadmill, &mut hr).expect("invalid treadmill data");
+    assert_eq!(treadmill.energy, Some(50));
+    assert!(approx(treadmill.speed, 3.0));
+
+    // 30 km/h, 90 rpm, 200 W, 120 bpm
+    let mut bike = MachineData::default();
+    parse_indoor_bike_data(&[0x44, 0x02, 0xB8, 0x0B, 0xB4, 0x00, 0xC8, 0x00, 120], &mut bike, &mut hr).expect("invalid bike data");
+    assert_eq!(bike.kind, Some(MachineKind::IndoorBike));
+    assert!(approx(bike.speed, 30.0 / 3.6));
+    assert!(approx(bike.cadence, 90.0));
+    assert_eq!((bike.power, hr), (Some(200), 120));
+
+    // 24 strokes per minute, 100 strokes, 500 m, 2:05 per 500 m, 180 W
+    let mut rower = MachineData::default();
+    parse_rower_data(&[0x2C, 0x00, 48, 0x64, 0x00, 0xF4, 0x01, 0x00, 0x7D, 0x00, 0xB4, 0x00], &mut rower, &mut hr).expect("invalid rower data");
+    assert_eq!(rower.kind, Some(MachineKind::Rower));
+    assert!(approx(rower.stroke_rate, 24.0));
+    assert!(approx(rower.speed, 4.0));
+    assert_eq!((rower.stroke_count, rower.distance, rower.power), (Some(100), Some(500), Some(180)));
+
+    // total distance announced, but missing
+    assert!(parse_treadmill_data(&[0x05, 0x00, 0xD2], &mut MachineData::default(), &mut hr).is_err());
+}
+
+#[tokio::test(start_paused = true)]
+async fn publishes_machine_data_and_heart_rate_of_treadmill() {
+    let _serial = SERIAL.lock().await;
+    let treadmill = Arc::new(
+        FakePeripheral::new(STRAP_ADDRESS, "Treadmill")
+            .with_service(FITNESS_MACHINE_SERVICE, vec![(TREADMILL_DATA, CharPropFlags::NOTIFY, vec![])])
+            .with_service(HR_SERVICE, vec![(HR_MEASUREMENT, CharPropFlags::NOTIFY, vec![])])
+    );
+    let adapter = FakeAdapter::new(vec![Arc::clone(&treadmill)]);
+    let data = program_data(&["--accept-new-device", "--hrm-mac", "AA:BB:CC:DD:EE:01"], vec![]);
+    let mut receiver = get_receiver();
+    start_manager(vec![adapter], &data);
+
+    wait_until(|| treadmill.subscribed(TREADMILL_DATA) && treadmill.subscribed(HR_MEASUREMENT)).await;
+    // 7.2 km/h and 1 % incline
+    treadmill.notify(TREADMILL_DATA, vec![0x08, 0x00, 0xD0, 0x02, 0x0A, 0x00, 0x00, 0x00]);
+    let machine = next_data(&mut receiver).await.machine.expect("machine data is missing");
+    assert!(machine.speed.is_some_and(|s| (s - 2.0).abs() < 0.001));
+    assert!(machine.incline.is_some_and(|i| (i - 1.0).abs() < 0.001));
+
+    // the heart rate of the chest strap, received by the treadmill
+    treadmill.notify(HR_MEASUREMENT, vec![0, 135]);
+    let hr = next_data(&mut receiver).await;
+    assert_eq!(hr.hr, 135);
+    assert_eq!(hr.machine.and_then(|m| m.kind), Some(MachineKind::Treadmill));
+    assert_eq!(data.merged_config.read().await.program_config.hrm_list.first().and_then(|h| h.adaptor_id), Some(7));
+}
+
+#[test]
+fn decodes_heart_rate_from_advertisements() {
+    let decode = |change: fn(&mut PeripheralProperties)| {
+        let mut properties = PeripheralProperties::default();
+        change(&mut properties);
+        decode_advertisement(&properties).map(|(decoder, data)| (decoder, data.hr, data.contact_ok))
+    };
+    assert_eq!(decode(|p| { p.service_data.insert(HR_SERVICE, vec![0b110, 72]); }), Some(("heart_rate_service", 72, Some(true))));
+    assert_eq!(decode(|p| { p.manufacturer_data.insert(0x0157, vec![0x02, 0x00, 0x00, 95]); }), Some(("huami", 95, None)));
+    // heart rate broadcast enabled, but not measured yet
+    assert_eq!(decode(|p| { p.manufacturer_data.insert(0x0157, vec![0x02, 0x00, 0x00, 0xFF]); }), None);
+    assert_eq!(decode(|p| { p.manufacturer_data.insert(0x006B, vec![0x01, 0x02, 0x03, 0x04]); }), None);
+    assert_eq!(decode(|p| { p.service_data.insert(HR_SERVICE, vec![]); }), None);
+}
+
+#[tokio::test(start_paused = true)]
+async fn publishes_heart_rate_from_advertisements_without_connecting() {
+    let _serial = SERIAL.lock().await;
+    let strap = Arc::new(
+        FakePeripheral::new(STRAP_ADDRESS, "Broadcast Strap")
+            .with_properties(|p| { p.service_data.insert(HR_SERVICE, vec![0, 70]); })
+    );
+    let watch = Arc::new(
+        FakePeripheral::new([0xAA, 0xBB, 0xCC, 0xDD, 0xEE, 0x02], "Watch")
+            .with_properties(|p| { p.manufacturer_data.insert(0x0157, vec![0x02, 0x00, 0x00, 110]); })
+    );
+    let other = Arc::new(FakePeripheral::new([0xAA, 0xBB, 0xCC, 0xDD, 0xEE, 0x03], "Headphones"));
+    let adapter = FakeAdapter::new(vec![Arc::clone(&strap), Arc::clone(&watch), Arc::clone(&other)]);
+    let data = program_data(&["--advertisements"], vec![]);
+    let mut receiver = get_receiver();
+    let manager: &'static HrManager = Box::leak(Box::new(HrManager::new(Arc::new(FakeTransport::new(vec![adapter])))));
+    tokio::spawn(manager.watch_advertisements(Arc::clone(&data)));
+
+    let mut updates = vec![];
+    for _ in 0..2 {
+        let update = timeout(Duration::from_mins(1), receiver.recv()).await.expect("no data in time").expect("channel closed");
+        let Some(HrmState::Ok(hr)) = update.hr_state else {
+            panic!("expected data");
+        };
+        updates.push((update.device.map(|d| d.to_string()), hr.hr));
+    }
+    updates.sort();
+    assert_eq!(updates, vec![
+        (Some("AA:BB:CC:DD:EE:01".to_owned()), 70),
+        (Some("AA:BB:CC:DD:EE:02".to_owned()), 110),
+    ]);
+
+    // only new advertisements are published; the watch stops advertising
+    strap.advertise(|p| { p.service_data.insert(HR_SERVICE, vec![0, 72]); });
+    let advertised = timeout(Duration::from_mins(1), receiver.recv()).await.expect("no data in time").expect("channel closed");
+    assert_eq!(advertised.device.map(|d| d.to_string()), Some("AA:BB:CC:DD:EE:01".to_owned()));
+    assert!(matches!(advertised.hr_state, Some(HrmState::Ok(HrData { hr: 72, .. }))));
+    for rssi in -70..-60 {
+        sleep(Duration::from_secs(1)).await;
+        strap.advertise(|p| p.rssi = Some(rssi));
+    }
+    loop {
+        let update = timeout(Duration::from_mins(1), receiver.recv()).await.expect("no data in time").expect("channel closed");
+        if let Some(HrmState::Disconnected) = update.hr_state {
+            assert_eq!(update.device.map(|d| d.to_string()), Some("AA:BB:CC:DD:EE:02".to_owned()));
+            break;
+        }
+        assert_eq!(update.device.map(|d| d.to_string()), Some("AA:BB:CC:DD:EE:01".to_owned()));
+    }
+    assert!(!strap.connected() && !watch.connected() && !other.connected());
+}
+
+#[tokio::test(start_paused = true)]
+async fn keeps_device_repeating_its_advertisement() {
+    let _serial = SERIAL.lock().await;
+    let strap = Arc::new(
+        FakePeripheral::new(STRAP_ADDRESS, "Broadcast Strap")
+            .with_properties(|p| { p.service_data.insert(HR_SERVICE, vec![0, 70]); })
+    );
+    let adapter = FakeAdapter::new(vec![Arc::clone(&strap)]);
+    let data = program_data(&["--advertisements"], vec![]);
+    let mut receiver = get_receiver();
+    let manager: &'static HrManager = Box::leak(Box::new(HrManager::new(Arc::new(FakeTransport::new(vec![adapter])))));
+    tokio::spawn(manager.watch_advertisements(Arc::clone(&data)));
+    assert_eq!(next_data(&mut receiver).await.hr, 70);
+
+    // the same advertisement is received again and again for longer than the timeout
+    let start = Instant::now();
+    while start.elapsed() < Duration::from_secs(30) {
+        sleep(Duration::from_secs(1)).await;
+        strap.advertise(|_| {});
+        let update = timeout(Duration::from_secs(5), receiver.recv()).await.expect("no data in time").expect("channel closed");
+        assert!(matches!(update.hr_state, Some(HrmState::Ok(HrData { hr: 70, .. }))));
+    }
+
+    // the strap is gone
+    assert!(matches!(next_state(&mut receiver).await, HrmState::Disconnected));
+    assert!(start.elapsed() >= Duration::from_secs(40));
+}
+
+/// Creates a found device with the signal strength `rssi`, like a search does.
+fn found(address: [u8; 6], name: &str, rssi: i16, is_known: bool) -> FoundDevice {
+    FoundDevice {
+        name: name.to_owned(),
+        addr: BDAddr::from(address),
+        peripheral: Arc::new(FakePeripheral::new(address, name)),
+        is_known,
+        filtered: false,
+        properties: PeripheralProperties {
+            address: BDAddr::from(address),
+            local_name: Some(name.to_owned()),
+            rssi: Some(rssi),
+            services: vec![HR_SERVICE],
+            ..PeripheralProperties::default()
+        },
+        reconnect: ReconnectStrategy::default(),
+        stats: Arc::default(),
+        interrupt: CancellationToken::new(),
+        reconnected: Arc::default(),
+        wheel_circumference: None,
+    }
+}
+
+#[test]
+fn selects_device_by_policy() {
+    let chosen = |devices: &[FoundDevice], policy: &SelectionPolicy, known: &[MacAddress]| {
+        match decide(devices, false, Some(policy), known).expect("invalid policy").0 {
+            Decision::Chosen(index) => devices.get(index).map(|d| d.name.clone()),
+            Decision::Fallback(_) => None,
+        }
+    };
+    let known_strap = MacAddress::new([0xAA, 0xBB, 0xCC, 0xDD, 0xEE, 0x03]);
+    let devices = vec![
+        found([0xAA, 0xBB, 0xCC, 0xDD, 0xEE, 0x03], "Polar H9", -85, true),
+        found([0xAA, 0xBB, 0xCC, 0xDD, 0xEE, 0x01], "Polar H10", -70, false),
+        found([0xAA, 0xBB, 0xCC, 0xDD, 0xEE, 0x02], "Headphones", -40, false),
+    ];
+
+    // known devices first
+    let policy = SelectionPolicy::default();
+    assert_eq!(chosen(&devices, &policy, &[known_strap]), Some("Polar H9".to_owned()));
+
+    // strongest signal wins
+    let strongest = SelectionPolicy {
+        ranking: vec![Ranking::StrongestSignal],
+        ..SelectionPolicy::default()
+    };
+    assert_eq!(chosen(&devices, &strongest, &[known_strap]), Some("Headphones".to_owned()));
+
+    // rules reject the devices before ranking them
+    let polar = SelectionPolicy {
+        name_regex: Some("^Polar".to_owned()),
+        services: vec![HR_SERVICE],
+        min_rssi: Some(-80),
+        ..SelectionPolicy::default()
+    };
+    let (_, reasons) = decide(&devices, false, Some(&polar), &[known_strap]).expect("invalid policy");
+    assert_eq!(reasons, vec![
+        "Polar H9 (AA:BB:CC:DD:EE:03) is rejected: the signal (-85 dBm) is weaker than -80 dBm",
+        "Headphones (AA:BB:CC:DD:EE:02) is rejected: the name does not match \"^Polar\"",
+        "Polar H10 (AA:BB:CC:DD:EE:01) is ranked 1 (unknown, -70 dBm)",
+    ]);
+    assert_eq!(chosen(&devices, &polar, &[known_strap]), Some("Polar H10".to_owned()));
+
+    // fallback, if no device fulfills the rules
+    let wahoo = SelectionPolicy {
+        name_regex: Some("^TICKR".to_owned()),
+        fallback: Fallback::Fail,
+        ..SelectionPolicy::default()
+    };
+    assert!(matches!(decide(&devices, false, Some(&wahoo), &[]), Ok((Decision::Fallback(Fallback::Fail), _))));
+
+    // without a policy, only known devices are chosen automatically
+    assert!(matches!(decide(&devices, false, None, &[known_strap]), Ok((Decision::Chosen(0), _))));
+    assert!(matches!(decide(&devices[1..], false, None, &[]), Ok((Decision::Fallback(Fallback::Wait), _))));
+}
+
+#[tokio::test(start_paused = true)]
+async fn connects_to_device_chosen_by_policy_without_asking() {
+    let _serial = SERIAL.lock().await;
+    let far = Arc::new(
+        FakePeripheral::new([0xAA, 0xBB, 0xCC, 0xDD, 0xEE, 0x02], "Far Strap")
+            .with_service(HR_SERVICE, vec![(HR_MEASUREMENT, CharPropFlags::NOTIFY, vec![])])
+            .with_properties(|p| p.rssi = Some(-90))
+    );
+    let near = strap();
+    let adapter = FakeAdapter::new(vec![Arc::clone(&far), Arc::clone(&near)]);
+    let data = program_data(&[], vec![]);
+    data.merged_config.write().await.program_config.selection = Some(SelectionPolicy {
+        ranking: vec![Ranking::StrongestSignal],
+        ..SelectionPolicy::default()
+    });
+    let mut receiver = get_receiver();
+    start_manager(vec![adapter], &data);
+
+    wait_until(|| near.subscribed(HR_MEASUREMENT)).await;
+    assert!(!far.connected());
+    near.notify(HR_MEASUREMENT, vec![0, 66]);
+    assert_eq!(next_data(&mut receiver).await.hr, 66);
+    // the chosen device is paired
+    assert_eq!(data.merged_config.read().await.program_config.hrm_list.first().map(|h| h.name.clone()), Some("Strap".to_owned()));
+}
+
+#[test]
+fn backs_off_exponentially_with_jitter() {
+    let strategy = ReconnectStrategy {
+        initial_backoff_ms: 500,
+        max_backoff_ms: 5000,
+        jitter: 0.2,
+        ..ReconnectStrategy::default()
+    };
+    let backoffs = (1..=6).map(|failures| strategy.backoff_with(failures, 0.5).as_millis()).collect::<Vec<_>>();
+    assert_eq!(backoffs, vec![500, 1000, 2000, 4000, 5000, 5000]);
+    assert_eq!(strategy.backoff_with(2, 0.0), Duration::from_millis(800));
+    assert_eq!(strategy.backoff_with(2, 1.0), Duration::from_millis(1200));
+    assert_eq!(strategy.backoff_with(u32::MAX, 0.5), Duration::from_secs(5));
+    for failures in 1..100 {
+        let backoff = strategy.backoff(failures);
+        assert!(backoff <= Duration::from_secs(6), "{backoff:?} is too long");
+    }
+
+    assert!(strategy.check().is_ok());
+    assert!(ReconnectStrategy { jitter: 1.5, ..strategy }.check().is_err());
+    assert!(ReconnectStrategy { poll_interval_ms: 0, ..strategy }.check().is_err());
+    assert!(ReconnectStrategy { initial_backoff_ms: 6000, ..strategy }.check().is_err());
+}
+
+#[tokio::test(start_paused = true)]
+async fn counts_reconnects_and_gives_up_lost_device() {
+    let _serial = SERIAL.lock().await;
+    let strap = strap();
+    let adapter = FakeAdapter::new(vec![Arc::clone(&strap)]);
+    let data = program_data(&["--pin-device"], vec![known_strap()]);
+    data.merged_config.write().await.reconnect = ReconnectStrategy {
+        max_attempts: 3,
+        give_up_after: Some(2),
+        give_up: GiveUp::Stop,
+        ..ReconnectStrategy::default()
+    };
+    let manager = start_manager(vec![Arc::clone(&adapter)], &data);
+    let stats = || async { manager.reconnect_stats().await.into_iter().next().expect("no stats").1 };
+
+    // restored without scanning
+    wait_until(|| strap.subscribed(HR_MEASUREMENT)).await;
+    strap.drop_connection();
+    wait_until(|| !strap.connected()).await;
+    wait_until(|| strap.connected()).await;
+    sleep(Duration::from_secs(1)).await;
+    let restored = stats().await;
+    assert_eq!((restored.disconnects, restored.reconnects, restored.failed_attempts), (1, 1, 0));
+    assert!(restored.last_reconnect_latency.is_some_and(|l| l <= 1000));
+    assert_eq!(restored.disconnected_since, None);
+    assert_eq!(adapter.scans.load(Ordering::Acquire), 1);
+
+    // 3 attempts, then 2 failed scans
+    strap.set_connectable(false);
+    strap.drop_connection();
+    timeout(Duration::from_mins(5), async {
+        while !stats().await.given_up {
+            sleep(Duration::from_secs(1)).await;
+        }
+    }).await.expect("device was not given up in time");
+    let lost = stats().await;
+    assert_eq!((lost.disconnects, lost.reconnects, lost.failed_attempts), (2, 1, 5));
+    assert!(lost.disconnected_since.is_some());
+    assert_eq!(adapter.scans.load(Ordering::Acquire), 3);
+
+    // the slot does not scan anymore
+    strap.set_connectable(true);
+    sleep(Duration::from_mins(1)).await;
+    assert_eq!(adapter.scans.load(Ordering::Acquire), 3);
+    assert!(!strap.connected());
+    assert!(stats().await.downtime >= 60_000);
+}
+
+#[tokio::test(start_paused = true)]
+async fn scans_for_supported_services_until_known_device_is_found() {
+    let _serial = SERIAL.lock().await;
+    let speaker = Arc::new(FakePeripheral::new([0xAA, 0xBB, 0xCC, 0xDD, 0xEE, 0x02], "Speaker"));
+    let strap = strap();
+    let adapter = FakeAdapter::new(vec![Arc::clone(&speaker)]);
+    let data = program_data(&["--scan-duration", "10"], vec![known_strap()]);
+    let start = Instant::now();
+    start_manager(vec![Arc::clone(&adapter)], &data);
+
+    // the speaker does not advertise a supported service, so the scans find nothing
+    wait_until(|| adapter.stopped_scans.load(Ordering::Acquire) >= 1).await;
+    assert!(start.elapsed() >= Duration::from_secs(10));
+    let filter = adapter.filter();
+    assert!(filter.contains(&HR_SERVICE) && filter.contains(&RSC_SERVICE));
+
+    // the scan ends early, when the known strap appears
+    wait_until(|| adapter.scans.load(Ordering::Acquire) >= 2).await;
+    let scan_started = Instant::now();
+    sleep(Duration::from_secs(3)).await;
+    adapter.add_peripheral(Arc::clone(&strap));
+    wait_until(|| strap.subscribed(HR_MEASUREMENT)).await;
+    assert!(scan_started.elapsed() < Duration::from_secs(4));
+    assert_eq!(adapter.scans.load(Ordering::Acquire), adapter.stopped_scans.load(Ordering::Acquire));
+    assert!(!speaker.connected());
+
+    // definitions without services may match any device
+    assert!(supported_services(&[]).is_some_and(|s| s.contains(&HR_SERVICE)));
+    let any_device: Vec<_> = serde_json::from_str(r#"[{"adaptor_id": 100, "heart_rate": {"characteristic": "0000fff1-0000-1000-8000-00805f9b34fb"}}]"#)
+        .expect("invalid definition");
+    assert_eq!(supported_services(&any_device), None);
+}
+
+#[tokio::test(start_paused = true)]
+async fn finds_all_devices_with_unfiltered_scan() {
+    let _serial = SERIAL.lock().await;
+    let speaker = Arc::new(FakePeripheral::new([0xAA, 0xBB, 0xCC, 0xDD, 0xEE, 0x02], "Speaker"));
+    let adapter = FakeAdapter::new(vec![Arc::clone(&speaker)]);
+    let data = program_data(&["--unfiltered-scan"], vec![]);
+    let manager = HrManager::new(Arc::new(FakeTransport::new(vec![Arc::clone(&adapter)])));
+
+    let devices = manager.search(&data, None, true).await.expect("scan failed");
+    assert_eq!(devices.iter().map(|d| d.name.as_str()).collect::<Vec<_>>(), vec!["Speaker"]);
+    assert!(adapter.filter().is_empty());
+}
+
+#[tokio::test(start_paused = true)]
+async fn scans_only_with_chosen_adapter() {
+    let _serial = SERIAL.lock().await;
+    let strap = strap();
+    let built_in = FakeAdapter::named("hci0 (pci:v8086p0AAA)", vec![Arc::clone(&strap)]);
+    let dongle = FakeAdapter::named("hci1 (usb:v0A12p0001)", vec![Arc::clone(&strap)]);
+    let data = program_data(&["--adapter", "USB"], vec![known_strap()]);
+    start_manager(vec![Arc::clone(&built_in), Arc::clone(&dongle)], &data);
+
+    wait_until(|| strap.subscribed(HR_MEASUREMENT)).await;
+    assert_eq!(built_in.scans.load(Ordering::Acquire), 0);
+    assert_eq!(dongle.scans.load(Ordering::Acquire), 1);
+
+    // by index
+    let manager = HrManager::new(Arc::new(FakeTransport::new(vec![Arc::clone(&built_in), Arc::clone(&dongle)])));
+    let by_index = program_data(&["--adapter", "1"], vec![known_strap()]);
+    manager.search(&by_index, None, true).await.expect("scan failed");
+    assert_eq!(built_in.scans.load(Ordering::Acquire), 1);
+    assert_eq!(dongle.scans.load(Ordering::Acquire), 1);
+
+    for selector in ["3", "0", "hci2"] {
+        let unknown = program_data(&["--adapter", selector], vec![known_strap()]);
+        assert!(manager.search(&unknown, None, true).await.is_err(), "{selector} should not match");
+        assert!(manager.check_adapter(selector).await.is_err(), "{selector} should not match");
+    }
+    assert!(manager.check_adapter("usb").await.is_ok());
+}
+
+#[tokio::test(start_paused = true)]
+async fn controls_device_chosen_remotely() {
+    let _serial = SERIAL.lock().await;
+    let speaker = Arc::new(FakePeripheral::new([0xAA, 0xBB, 0xCC, 0xDD, 0xEE, 0x02], "Speaker"));
+    let strap = strap();
+    let adapter = FakeAdapter::new(vec![Arc::clone(&speaker), Arc::clone(&strap)]);
+    // nothing is known, so the slot waits for the user to choose
+    let data = program_data(&["--unfiltered-scan"], vec![]);
+    let mut receiver = get_receiver();
+    let manager = start_manager(vec![Arc::clone(&adapter)], &data);
+    wait_until(|| adapter.scans.load(Ordering::Acquire) >= 1).await;
+    let strap_mac = MacAddress::new(STRAP_ADDRESS);
+
+    let scanned = manager.scan(&data).await.expect("scan failed");
+    assert_eq!(scanned, manager.scanned_devices().await);
+    let [first, second] = scanned.as_slice() else {
+        panic!("expected exactly two devices");
+    };
+    assert_eq!((first.name.as_str(), first.adaptor, first.known), ("Speaker", None, false));
+    assert_eq!((second.name.as_str(), second.mac, second.adaptor, second.known), ("Strap", strap_mac, Some(1), false));
+    assert!(manager.disconnect(strap_mac).await.is_err());
+
+    // connected and paired, although it is not known
+    manager.connect(strap_mac).await.expect("connect failed");
+    wait_until(|| strap.subscribed(HR_MEASUREMENT)).await;
+    assert!(data.merged_config.read().await.program_config.hrm_list.iter().any(|h| h.mac == strap_mac));
+    assert!(!speaker.connected());
+
+    // stays disconnected without scanning
+    manager.disconnect(strap_mac).await.expect("disconnect failed");
+    assert!(matches!(next_state(&mut receiver).await, HrmState::Disconnected));
+    wait_until(|| !strap.connected()).await;
+    let scans = adapter.scans.load(Ordering::Acquire);
+    sleep(Duration::from_mins(1)).await;
+    assert_eq!(adapter.scans.load(Ordering::Acquire), scans);
+    assert!(!strap.connected());
+    assert_eq!(manager.reconnect_stats().await.into_iter().next().map(|(_, s)| s.disconnects), Some(0));
+
+    manager.reconnect(strap_mac).await.expect("reconnect failed");
+    wait_until(|| strap.connected() && strap.subscribed(HR_MEASUREMENT)).await;
+    strap.notify(HR_MEASUREMENT, vec![0, 70]);
+    assert_eq!(next_data(&mut receiver).await.hr, 70);
+
+    // forgotten and disconnected; the slot waits for the user again
+    manager.forget(strap_mac, &data).await.expect("forget failed");
+    wait_until(|| !strap.connected()).await;
+    assert!(data.merged_config.read().await.program_config.hrm_list.is_empty());
+    assert!(manager.connected_hrm().await.is_none());
+    sleep(Duration::from_mins(1)).await;
+    assert!(!strap.connected() && !speaker.connected());
+    assert!(manager.forget(strap_mac, &data).await.is_err());
+}
+
+#[tokio::test(start_paused = true)]
+async fn stays_disconnected_when_disconnected_during_rescan() {
+    let _serial = SERIAL.lock().await;
+    let speaker = Arc::new(FakePeripheral::new([0xAA, 0xBB, 0xCC, 0xDD, 0xEE, 0x02], "Speaker"));
+    let strap = strap();
+    let adapter = FakeAdapter::new(vec![Arc::clone(&speaker)]);
+    let data = program_data(&["--unfiltered-scan"], vec![]);
+    let manager = start_manager(vec![Arc::clone(&adapter)], &data);
+    wait_until(|| adapter.scans.load(Ordering::Acquire) >= 1).await;
+    let strap_mac = MacAddress::new(STRAP_ADDRESS);
+
+    // the requested strap is not in range, so the slot rescans
+    manager.connect(strap_mac).await.expect("connect failed");
+    let scans = adapter.scans.load(Ordering::Acquire);
+    wait_until(|| adapter.scans.load(Ordering::Acquire) >= scans + 2).await;
+
+    // it appears and is disconnected during the same scan
+    adapter.add_peripheral(Arc::clone(&strap));
+    manager.disconnect(strap_mac).await.expect("disconnect failed");
+    sleep(Duration::from_mins(1)).await;
+    assert!(!strap.connected());
+
+    manager.reconnect(strap_mac).await.expect("reconnect failed");
+    wait_until(|| strap.connected() && strap.subscribed(HR_MEASUREMENT)).await;
+}
+
+#[tokio::test(start_paused = true)]
+async fn forgets_requested_device_without_dropping_current_one() {
+    let _serial = SERIAL.lock().await;
+    let strap = strap();
+    let adapter = FakeAdapter::new(vec![Arc::clone(&strap)]);
+    let data = program_data(&[], vec![known_strap()]);
+    let manager = start_manager(vec![adapter], &data);
+    wait_until(|| strap.subscribed(HR_MEASUREMENT)).await;
+
+    // a device out of range is requested instead of the strap, then forgotten
+    let absent = MacAddress::new([0xAA, 0xBB, 0xCC, 0xDD, 0xEE, 0x03]);
+    manager.connect(absent).await.expect("connect failed");
+    wait_until(|| !strap.connected()).await;
+    manager.forget(absent, &data).await.expect("forget failed");
+    assert_eq!(manager.connected_hrm().await.map(|h| h.mac), Some(MacAddress::new(STRAP_ADDRESS)));
+    assert_eq!(data.merged_config.read().await.program_config.hrm_list.len(), 1);
+
+    // the known strap is chosen again
+    wait_until(|| strap.connected() && strap.subscribed(HR_MEASUREMENT)).await;
+}
@@ -0,0 +1,381 @@
+//! In-memory [`Transport`] to script peripherals in tests.
+//!
+//! A [`FakePeripheral`] is described by its advertisement, its services and the values of its characteristics.
+//! Tests can send notifications, drop the connection and refuse reconnections at any time.
+//! Control points can answer written commands with a responder.\
+//! Devices can also be created from a capture written with `debug-device`.
+
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use btleplug::api::{AddressType, BDAddr, CharPropFlags, Characteristic, Descriptor, PeripheralProperties, ScanFilter, Service, ValueNotification, WriteType};
+use futures::stream::{self, select_all};
+use tokio::sync::broadcast::{channel, Sender};
+use uuid::Uuid;
+
+use crate::adaptors::capture::CaptureHeader;
+use crate::adaptors::transport::{AdvertisementStream, BleAdapter, BlePeripheral, NotificationStream, Transport};
+
+/// Locks a mutex, ignoring poisoning; a panicking test must not break the others.
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(std::sync::PoisonError::into_inner)
+}
+
+/// Answers a value written to a characteristic; the answer is sent as notification of the same characteristic.
+type Responder = Box<dyn Fn(&[u8]) -> Option<Vec<u8>> + Send + Sync>;
+
+/// Transport with a fixed list of adapters.
+pub struct FakeTransport {
+    adapters: Vec<Arc<FakeAdapter>>,
+}
+
+impl FakeTransport {
+    pub fn new(adapters: Vec<Arc<FakeAdapter>>) -> Self {
+        Self { adapters }
+    }
+}
+
+#[async_trait]
+impl Transport for FakeTransport {
+    async fn adapters(&self) -> Result<Vec<Arc<dyn BleAdapter>>> {
+        Ok(self.adapters.iter().map(|a| Arc::clone(a) as _).collect())
+    }
+}
+
+/// Adapter "finding" all peripherals added to it, once a scan was started.
+///
+/// Like a real adapter, only peripherals advertising one of the services of the scan filter (if any) are found.
+#[derive(Default)]
+pub struct FakeAdapter {
+    /// Returned as description of the adapter
+    info: String,
+    peripherals: Mutex<Vec<Arc<FakePeripheral>>>,
+    /// Number of scans started
+    pub scans: AtomicUsize,
+    /// Number of scans stopped
+    pub stopped_scans: AtomicUsize,
+    /// Number of scans started, while another one was still running
+    pub overlapping_scans: AtomicUsize,
+    /// Services of the last scan filter
+    filter: Mutex<Vec<Uuid>>,
+}
+
+impl FakeAdapter {
+    pub fn new(peripherals: Vec<Arc<FakePeripheral>>) -> Arc<Self> {
+        Self::named("hci0 (fake)", peripherals)
+    }
+
+    /// Creates an adapter with the description `info`.
+    pub fn named(info: &str, peripherals: Vec<Arc<FakePeripheral>>) -> Arc<Self> {
+        Arc::new(Self {
+            info: info.to_owned(),
+            peripherals: Mutex::new(peripherals),
+            ..Self::default()
+        })
+    }
+
+    /// Makes a peripheral visible for the next scans.
+    pub fn add_peripheral(&self, peripheral: Arc<FakePeripheral>) {
+        lock(&self.peripherals).push(peripheral);
+    }
+
+    /// Returns the services of the last scan filter.
+    pub fn filter(&self) -> Vec<Uuid> {
+        lock(&self.filter).clone()
+    }
+}
+
+#[async_trait]
+impl BleAdapter for FakeAdapter {
+    async fn info(&self) -> Result<String> {
+        Ok(self.info.clone())
+    }
+
+    async fn start_scan(&self, filter: ScanFilter) -> Result<()> {
+        *lock(&self.filter) = filter.services;
+        if self.scans.fetch_add(1, Ordering::AcqRel) > self.stopped_scans.load(Ordering::Acquire) {
+            self.overlapping_scans.fetch_add(1, Ordering::AcqRel);
+        }
+        Ok(())
+    }
+
+    async fn stop_scan(&self) -> Result<()> {
+        self.stopped_scans.fetch_add(1, Ordering::AcqRel);
+        Ok(())
+    }
+
+    async fn peripherals(&self) -> Result<Vec<Arc<dyn BlePeripheral>>> {
+        if self.scans.load(Ordering::Acquire) == 0 {
+            return Ok(vec![]);
+        }
+        let filter = self.filter();
+        Ok(
+            lock(&self.peripherals)
+                .iter()
+                .filter(|p| filter.is_empty() || lock(&p.properties).services.iter().any(|s| filter.contains(s)))
+                .map(|p| Arc::clone(p) as _)
+                .collect()
+        )
+    }
+
+    async fn advertisements(&self) -> Result<AdvertisementStream> {
+        let streams = lock(&self.peripherals)
+            .iter()
+            .map(|p| Box::pin(stream::unfold(p.advertisements.subscribe(), |mut receiver| async move {
+                receiver.recv().await.ok().map(|a| (a, receiver))
+            })))
+            .collect::<Vec<_>>();
+        Ok(Box::pin(select_all(streams)))
+    }
+}
+
+/// Scriptable peripheral.
+pub struct FakePeripheral {
+    properties: Mutex<PeripheralProperties>,
+    services: BTreeSet<Service>,
+    values: Mutex<HashMap<Uuid, Vec<u8>>>,
+    connected: AtomicBool,
+    connectable: AtomicBool,
+    discovered: AtomicBool,
+    subscriptions: Mutex<HashSet<Uuid>>,
+    written: Mutex<Vec<(Uuid, Vec<u8>)>>,
+    responders: HashMap<Uuid, Responder>,
+    notifications: Sender<ValueNotification>,
+    /// Sends the address for every advertisement received
+    advertisements: Sender<BDAddr>,
+}
+
+impl FakePeripheral {
+    /// Creates a connectable peripheral advertising `name` without any services.
+    pub fn new(address: [u8; 6], name: &str) -> Self {
+        Self {
+            properties: Mutex::new(PeripheralProperties {
+                address: BDAddr::from(address),
+                local_name: Some(name.to_owned()),
+                rssi: Some(-60),
+                ..PeripheralProperties::default()
+            }),
+            services: BTreeSet::new(),
+            values: Mutex::default(),
+            connected: AtomicBool::new(false),
+            connectable: AtomicBool::new(true),
+            discovered: AtomicBool::new(false),
+            subscriptions: Mutex::default(),
+            written: Mutex::default(),
+            responders: HashMap::new(),
+            notifications: channel(64).0,
+            advertisements: channel(64).0,
+        }
+    }
+
+    /// Creates a peripheral with the advertisement, services and initial values of a capture.
+    ///
+    /// The captured notifications are not sent; use [`FakePeripheral::notify`] to replay them.
+    pub fn from_capture(header: &CaptureHeader) -> Result<Self> {
+        let device = &header.device;
+        let address = device.address.parse::<BDAddr>()?;
+        let mut peripheral = Self::new(address.into_inner(), &device.name).with_properties(|p| {
+            p.address_type = device.address_type.as_deref().and_then(AddressType::from_str);
+            p.tx_power_level = device.tx_power_level;
+            p.rssi = device.rssi;
+            p.class = device.class;
+            p.manufacturer_data = device.manufacturer_data.iter().map(|(k, v)| (*k, v.0.clone())).collect();
+            p.service_data = device.service_data.iter().map(|(k, v)| (*k, v.0.clone())).collect();
+        });
+        for service in &header.services {
+            let mut characteristics = BTreeSet::new();
+            for char in &service.characteristics {
+                characteristics.insert(Characteristic {
+                    uuid: char.uuid,
+                    service_uuid: service.uuid,
+                    properties: char.properties.iter().filter_map(|name| CharPropFlags::from_name(name)).collect(),
+                    descriptors: char.descriptors.iter().map(|uuid| Descriptor {
+                        uuid: *uuid,
+                        service_uuid: service.uuid,
+                        characteristic_uuid: char.uuid,
+                    }).collect(),
+                });
+                if let Some(ref value) = char.value {
+                    lock(&peripheral.values).insert(char.uuid, value.0.clone());
+                }
+            }
+            peripheral.services.insert(Service {
+                uuid: service.uuid,
+                primary: service.primary,
+                characteristics,
+            });
+        }
+        lock(&peripheral.properties).services.clone_from(&device.services);
+        Ok(peripheral)
+    }
+
+    /// Adds a service with its characteristics and their initial values.
+    ///
+    /// The service is advertised and can be discovered after connecting.
+    #[must_use]
+    pub fn with_service(mut self, uuid: Uuid, characteristics: Vec<(Uuid, CharPropFlags, Vec<u8>)>) -> Self {
+        let mut service = Service {
+            uuid,
+            primary: true,
+            characteristics: BTreeSet::new(),
+        };
+        for (char_uuid, properties, value) in characteristics {
+            service.characteristics.insert(Characteristic {
+                uuid: char_uuid,
+                service_uuid: uuid,
+                properties,
+                descriptors: BTreeSet::new(),
+            });
+            lock(&self.values).insert(char_uuid, value);
+        }
+        lock(&self.properties).services.push(uuid);
+        self.services.insert(service);
+        self
+    }
+
+    /// Changes the advertisement of this peripheral.
+    #[must_use]
+    pub fn with_properties(self, change: impl FnOnce(&mut PeripheralProperties)) -> Self {
+        change(&mut lock(&self.properties));
+        self
+    }
+
+    /// Changes the advertised properties, as if a new advertisement was received.
+    ///
+    /// Without any change, the previous advertisement is repeated.
+    pub fn advertise(&self, change: impl FnOnce(&mut PeripheralProperties)) {
+        change(&mut lock(&self.properties));
+        let _ = self.advertisements.send(self.address());
+    }
+
+    /// Answers every value written to the characteristic `uuid` with the result of `responder`, if it is subscribed.
+    #[must_use]
+    pub fn with_responder(mut self, uuid: Uuid, responder: impl Fn(&[u8]) -> Option<Vec<u8>> + Send + Sync + 'static) -> Self {
+        self.responders.insert(uuid, Box::new(responder));
+        self
+    }
+
+    /// Sends a notification, if the characteristic is subscribed and the peripheral is connected.
+    pub fn notify(&self, uuid: Uuid, value: Vec<u8>) {
+        if self.connected.load(Ordering::Acquire) && lock(&self.subscriptions).contains(&uuid) {
+            let _ = self.notifications.send(ValueNotification { uuid, value });
+        }
+    }
+
+    /// Changes the value returned when reading a characteristic.
+    pub fn set_value(&self, uuid: Uuid, value: Vec<u8>) {
+        lock(&self.values).insert(uuid, value);
+    }
+
+    /// Drops the connection as if the device went out of range.
+    pub fn drop_connection(&self) {
+        self.connected.store(false, Ordering::Release);
+    }
+
+    /// Controls, if connection attempts succeed.
+    pub fn set_connectable(&self, connectable: bool) {
+        self.connectable.store(connectable, Ordering::Release);
+    }
+
+    pub fn connected(&self) -> bool {
+        self.connected.load(Ordering::Acquire)
+    }
+
+    pub fn subscribed(&self, uuid: Uuid) -> bool {
+        lock(&self.subscriptions).contains(&uuid)
+    }
+
+    /// Returns all values written to characteristics so far.
+    pub fn written(&self) -> Vec<(Uuid, Vec<u8>)> {
+        lock(&self.written).clone()
+    }
+
+    fn check_connected(&self) -> Result<()> {
+        if self.connected() {
+            Ok(())
+        } else {
+            Err(anyhow!("Peripheral {} is not connected", self.address()))
+        }
+    }
+}
+
+#[async_trait]
+impl BlePeripheral for FakePeripheral {
+    fn address(&self) -> BDAddr {
+        lock(&self.properties).address
+    }
+
+    async fn properties(&self) -> Result<Option<PeripheralProperties>> {
+        Ok(Some(lock(&self.properties).clone()))
+    }
+
+    fn services(&self) -> BTreeSet<Service> {
+        if self.discovered.load(Ordering::Acquire) {
+            self.services.clone()
+        } else {
+            BTreeSet::new()
+        }
+    }
+
+    async fn is_connected(&self) -> Result<bool> {
+        Ok(self.connected())
+    }
+
+    async fn connect(&self) -> Result<()> {
+        if !self.connectable.load(Ordering::Acquire) {
+            return Err(anyhow!("Peripheral {} refused the connection", self.address()));
+        }
+        self.connected.store(true, Ordering::Release);
+        Ok(())
+    }
+
+    async fn disconnect(&self) -> Result<()> {
+        self.connected.store(false, Ordering::Release);
+        lock(&self.subscriptions).clear();
+        Ok(())
+    }
+
+    async fn discover_services(&self) -> Result<()> {
+        self.check_connected()?;
+        self.discovered.store(true, Ordering::Release);
+        Ok(())
+    }
+
+    async fn read(&self, characteristic: &Characteristic) -> Result<Vec<u8>> {
+        self.check_connected()?;
+        lock(&self.values)
+            .get(&characteristic.uuid)
+            .cloned()
+            .ok_or(anyhow!("Characteristic {} does not exist", characteristic.uuid))
+    }
+
+    async fn write(&self, characteristic: &Characteristic, data: &[u8], _write_type: WriteType) -> Result<()> {
+        self.check_connected()?;
+        lock(&self.written).push((characteristic.uuid, data.to_vec()));
+        if let Some(response) = self.responders.get(&characteristic.uuid).and_then(|r| r(data)) {
+            self.notify(characteristic.uuid, response);
+        }
+        Ok(())
+    }
+
+    async fn subscribe(&self, characteristic: &Characteristic) -> Result<()> {
+        self.check_connected()?;
+        lock(&self.subscriptions).insert(characteristic.uuid);
+        Ok(())
+    }
+
+    async fn unsubscribe(&self, characteristic: &Characteristic) -> Result<()> {
+        lock(&self.subscriptions).remove(&characteristic.uuid);
+        Ok(())
+    }
+
+    async fn notifications(&self) -> Result<NotificationStream> {
+        let receiver = self.notifications.subscribe();
+        Ok(Box::pin(stream::unfold(receiver, |mut receiver| async move {
+            receiver.recv().await.ok().map(|n| (n, receiver))
+        })))
+    }
+}
@@ -0,0 +1,95 @@
+//! Abstraction of the Bluetooth Low Energy layer
+//!
+//! [`HrManager`](crate::adaptors::hrm::HrManager) and all adaptors only talk to a [`Transport`] and its
+//! [`BleAdapter`]s and [`BlePeripheral`]s.\
+//! [`platform::PlatformTransport`] uses the Bluetooth stack of the OS via [`btleplug`];
+//! the fake transport (only available in tests) allows scripting devices in memory.
+
+use std::collections::BTreeSet;
+use std::pin::Pin;
+use std::sync::Arc;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use btleplug::api::{BDAddr, Characteristic, PeripheralProperties, ScanFilter, Service, ValueNotification, WriteType};
+use futures::Stream;
+
+pub mod platform;
+#[cfg(test)]
+pub mod fake;
+
+/// Stream of notifications of all subscribed characteristics of a peripheral.
+pub type NotificationStream = Pin<Box<dyn Stream<Item=ValueNotification> + Send>>;
+
+/// Stream of the addresses of peripherals, whose advertisement was received.
+pub type AdvertisementStream = Pin<Box<dyn Stream<Item=BDAddr> + Send>>;
+
+/// Entry point to the Bluetooth stack.
+#[async_trait]
+pub trait Transport: Send + Sync {
+    /// Returns all Bluetooth adapters available.
+    async fn adapters(&self) -> Result<Vec<Arc<dyn BleAdapter>>>;
+}
+
+/// A Bluetooth adapter, which can scan for peripherals.
+#[async_trait]
+pub trait BleAdapter: Send + Sync {
+    /// Returns a description of the adapter, starting with its name (like "hci0" on Linux).
+    async fn info(&self) -> Result<String>;
+
+    /// Starts scanning for peripherals matching the filter.
+    async fn start_scan(&self, filter: ScanFilter) -> Result<()>;
+
+    /// Stops the scan started by [`BleAdapter::start_scan`].
+    async fn stop_scan(&self) -> Result<()>;
+
+    /// Returns all peripherals found by this adapter so far.
+    async fn peripherals(&self) -> Result<Vec<Arc<dyn BlePeripheral>>>;
+
+    /// Returns a stream, which yields a peripheral every time one of its advertisements is received while scanning.
+    ///
+    /// Unlike [`BlePeripheral::properties`], this also reveals advertisements repeating the previous one.
+    async fn advertisements(&self) -> Result<AdvertisementStream>;
+}
+
+/// A peripheral found during a scan.
+///
+/// Mirrors [`btleplug::api::Peripheral`], but can be implemented outside of btleplug.
+#[async_trait]
+pub trait BlePeripheral: Send + Sync {
+    /// Returns the mac address of the peripheral.
+    fn address(&self) -> BDAddr;
+
+    /// Returns the properties advertised by the peripheral, if any were received.
+    async fn properties(&self) -> Result<Option<PeripheralProperties>>;
+
+    /// Returns the services discovered by [`BlePeripheral::discover_services`].
+    fn services(&self) -> BTreeSet<Service>;
+
+    /// Returns the characteristics of all services discovered by [`BlePeripheral::discover_services`].
+    fn characteristics(&self) -> BTreeSet<Characteristic> {
+        self.services()
+            .iter()
+            .flat_map(|s| s.characteristics.clone())
+            .collect()
+    }
+
+    async fn is_connected(&self) -> Result<bool>;
+
+    async fn connect(&self) -> Result<()>;
+
+    async fn disconnect(&self) -> Result<()>;
+
+    async fn discover_services(&self) -> Result<()>;
+
+    async fn read(&self, characteristic: &Characteristic) -> Result<Vec<u8>>;
+
+    async fn write(&self, characteristic: &Characteristic, data: &[u8], write_type: WriteType) -> Result<()>;
+
+    async fn subscribe(&self, characteristic: &Characteristic) -> Result<()>;
+
+    async fn unsubscribe(&self, characteristic: &Characteristic) -> Result<()>;
+
+    /// Returns a stream of notifications for all subscribed characteristics.
+    async fn notifications(&self) -> Result<NotificationStream>;
+}
@@ -0,0 +1,135 @@
+//! [`Transport`] implementation using the Bluetooth stack of the OS via [`btleplug`].
+
+use std::collections::BTreeSet;
+use std::sync::Arc;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use btleplug::api::{BDAddr, Central, CentralEvent, Characteristic, Manager as _, Peripheral as _, PeripheralProperties, ScanFilter, Service, WriteType};
+use btleplug::platform::{Adapter, Manager, Peripheral};
+use futures::StreamExt;
+
+use crate::adaptors::transport::{AdvertisementStream, BleAdapter, BlePeripheral, NotificationStream, Transport};
+
+/// Uses the Bluetooth stack of the OS.
+pub struct PlatformTransport;
+
+#[async_trait]
+impl Transport for PlatformTransport {
+    async fn adapters(&self) -> Result<Vec<Arc<dyn BleAdapter>>> {
+        let manager = Manager::new().await?;
+        Ok(
+            manager
+                .adapters()
+                .await?
+                .into_iter()
+                .map(|a| Arc::new(PlatformAdapter(a)) as _)
+                .collect()
+        )
+    }
+}
+
+struct PlatformAdapter(Adapter);
+
+#[async_trait]
+impl BleAdapter for PlatformAdapter {
+    async fn info(&self) -> Result<String> {
+        Ok(self.0.adapter_info().await?)
+    }
+
+    async fn start_scan(&self, filter: ScanFilter) -> Result<()> {
+        Ok(self.0.start_scan(filter).await?)
+    }
+
+    async fn stop_scan(&self) -> Result<()> {
+        Ok(self.0.stop_scan().await?)
+    }
+
+    async fn peripherals(&self) -> Result<Vec<Arc<dyn BlePeripheral>>> {
+        Ok(
+            self.0
+                .peripherals()
+                .await?
+                .into_iter()
+                .map(|p| Arc::new(PlatformPeripheral(p)) as _)
+                .collect()
+        )
+    }
+
+    async fn advertisements(&self) -> Result<AdvertisementStream> {
+        let adapter = self.0.clone();
+        Ok(Box::pin(self.0.events().await?.filter_map(move |event| {
+            let adapter = adapter.clone();
+            async move {
+                let (
+                    CentralEvent::DeviceDiscovered(id)
+                    | CentralEvent::DeviceUpdated(id)
+                    | CentralEvent::ManufacturerDataAdvertisement { id, .. }
+                    | CentralEvent::ServiceDataAdvertisement { id, .. }
+                    | CentralEvent::ServicesAdvertisement { id, .. }
+                ) = event else {
+                    // connection changes
+                    return None;
+                };
+                adapter.peripheral(&id).await.ok().map(|p| p.address())
+            }
+        })))
+    }
+}
+
+struct PlatformPeripheral(Peripheral);
+
+#[async_trait]
+impl BlePeripheral for PlatformPeripheral {
+    fn address(&self) -> BDAddr {
+        self.0.address()
+    }
+
+    async fn properties(&self) -> Result<Option<PeripheralProperties>> {
+        Ok(self.0.properties().await?)
+    }
+
+    fn services(&self) -> BTreeSet<Service> {
+        self.0.services()
+    }
+
+    fn characteristics(&self) -> BTreeSet<Characteristic> {
+        self.0.characteristics()
+    }
+
+    async fn is_connected(&self) -> Result<bool> {
+        Ok(self.0.is_connected().await?)
+    }
+
+    async fn connect(&self) -> Result<()> {
+        Ok(self.0.connect().await?)
+    }
+
+    async fn disconnect(&self) -> Result<()> {
+        Ok(self.0.disconnect().await?)
+    }
+
+    async fn discover_services(&self) -> Result<()> {
+        Ok(self.0.discover_services().await?)
+    }
+
+    async fn read(&self, characteristic: &Characteristic) -> Result<Vec<u8>> {
+        Ok(self.0.read(characteristic).await?)
+    }
+
+    async fn write(&self, characteristic: &Characteristic, data: &[u8], write_type: WriteType) -> Result<()> {
+        Ok(self.0.write(characteristic, data, write_type).await?)
+    }
+
+    async fn subscribe(&self, characteristic: &Characteristic) -> Result<()> {
+        Ok(self.0.subscribe(characteristic).await?)
+    }
+
+    async fn unsubscribe(&self, characteristic: &Characteristic) -> Result<()> {
+        Ok(self.0.unsubscribe(characteristic).await?)
+    }
+
+    async fn notifications(&self) -> Result<NotificationStream> {
+        Ok(self.0.notifications().await?)
+    }
+}
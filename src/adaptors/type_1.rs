@@ -1,23 +1,71 @@
-use std::mem;
 use std::sync::Arc;
-use std::time::Duration;
 use anyhow::anyhow;
 use async_trait::async_trait;
-use btleplug::api::{Characteristic, CharPropFlags, Peripheral};
-use chrono::Utc;
-use futures::StreamExt;
-use itertools::Itertools;
-use log::{debug, error, info, warn};
+use btleplug::api::{Characteristic, CharPropFlags, WriteType};
+use log::{debug, info, warn};
 use mac_address::MacAddress;
 use tokio::sync::RwLock;
-use tokio::time::{sleep, timeout};
 use uuid::Uuid;
-use crate::adaptors::{Adaptor, ChannelTransferObject, FoundDevice, HrData, HrmState, SENDER};
-use crate::config::Hrm;
+use crate::adaptors::{Adaptor, has_characteristic, MatchConfidence, FoundDevice, HrData, HrmState, read_battery, read_device_profile, run_notification_loop};
+use crate::adaptors::parse::{read_u16, read_u8};
+use crate::adaptors::type_2::parse_rsc_measurement;
+use crate::config::{DeviceProfile, Hrm};
+
+/// Parses a Heart Rate Measurement (0x2A37) value into `data`.
+///
+/// Layout: flags (u8), heart rate (u8 or u16), energy expended (u16, optional), RR intervals (u16 each, optional).
+pub(super) fn parse_heart_rate_measurement(value: &[u8], data: &mut HrData) -> anyhow::Result<()> {
+    let flags = *value.first().ok_or(anyhow!("Empty heart rate measurement"))?;
+    let mut offset = 1;
+
+    // heart rate format
+    if flags & 0b1 > 0 {
+        // HR is u16
+        data.hr = read_u16(value, offset)?;
+        offset += 2;
+    } else {
+        // HR is u8
+        data.hr = u16::from(read_u8(value, offset)?);
+        offset += 1;
+    }
+
+    // contact sensor supported
+    if flags & 0b100 > 0 {
+        // contact sensor is supported
+        data.contact_ok = Some(flags & 0b10 > 0);
+    } else {
+        // contact sensor is not supported
+        data.contact_ok = None;
+    }
+
+    // energy expended present (in kJ); devices send it only occasionally, so keep the last value otherwise
+    if flags & 0b1000 > 0 {
+        data.energy_expended = Some(read_u16(value, offset)?);
+        offset += 2;
+    }
+
+    // RR intervals present; resolution is 1/1024 seconds, converted to milliseconds
+    data.rr_intervals.clear();
+    if flags & 0b1_0000 > 0 {
+        while offset + 1 < value.len() {
+            let raw = u32::from(read_u16(value, offset)?);
+            #[allow(clippy::cast_possible_truncation)]
+            data.rr_intervals.push(((raw * 1000 + 512) / 1024) as u16);
+            offset += 2;
+        }
+    }
+    Ok(())
+}
+
+/// Heart Rate Control Point command to reset the energy expended value.
+const RESET_ENERGY_EXPENDED: u8 = 0x01;
 
 pub(super) struct Adaptor1 {
     found_device: FoundDevice,
     characteristics: Vec<Characteristic>,
+    /// Heart Rate Control Point (0x2A39), if the device exposes it
+    control_point: Option<Characteristic>,
+    profile: Option<DeviceProfile>,
     hrm_state: Arc<RwLock<HrmState>>,
     initial_battery: Option<u8>
 }
@@ -29,6 +77,8 @@ impl Adaptor for Adaptor1 {
             name: self.found_device.name.clone(),
             mac: MacAddress::from(self.found_device.addr.into_inner()),
             adaptor_id: Some(1),
+            profile: self.profile.clone(),
+            wheel_circumference: self.found_device.wheel_circumference,
         }
     }
 
@@ -40,133 +90,59 @@ impl Adaptor for Adaptor1 {
         let _ = self.found_device.peripheral.disconnect().await;
     }
 
+    fn supports_energy_reset(&self) -> bool {
+        self.control_point.is_some()
+    }
+
+    async fn reset_energy_expended(&self) -> anyhow::Result<()> {
+        let Some(ref control_point) = self.control_point else {
+            return Err(anyhow!("Device {} does not support resetting energy expended", self.found_device.name));
+        };
+        info!("Resetting energy expended...");
+        self.found_device.peripheral.write(control_point, &[RESET_ENERGY_EXPENDED], WriteType::WithResponse).await?;
+        Ok(())
+    }
+
     async fn heartbeat_loop(&self) -> anyhow::Result<()> {
-        let device = &self.found_device;
-        debug!("Subscribing to characteristics {:?}", self.characteristics.iter().map(|c| c.uuid).join(","));
-        for c in &self.characteristics {
-            device.peripheral.subscribe(c).await?;
-        }
-        let mut notification_stream = device.peripheral.notifications().await?;
-        info!("Device ready!");
-        let hrm_state = Arc::clone(&self.hrm_state);
-        let initial_battery = self.initial_battery.clone();
-        let handle = tokio::spawn(async move {
-            // Process while the BLE connection is not broken or stopped.
-            while let Some(received_data) = notification_stream.next().await {
-                debug!(
-                    "Received data from [{:?}]: {:?}",
-                    received_data.uuid, received_data.value[1]
-                );
-                let mut write = hrm_state.write().await;
-                let state = &mut *write;
-                if let HrmState::Disconnected = state {
-                    let _ = mem::replace(state, HrmState::Ok(
-                        HrData {
-                            hr: 0,
-                            contact_ok: None,
-                            battery: None,
+        run_notification_loop(
+            &self.found_device,
+            &self.characteristics,
+            &self.hrm_state,
+            HrData {
+                battery: self.initial_battery,
+                ..HrData::default()
+            },
+            |received_data, data| {
+                match received_data.uuid.as_u128() {
+                    0x00002a19_0000_1000_8000_00805f9b34fb => {
+                        if let Some(level) = received_data.value.first() {
+                            data.battery = Some(*level);
                         }
-                    ));
-                }
-                if let HrmState::Ok(ref mut data) = state {
-                    match received_data.uuid.as_u128() {
-                        0x0000180d_0000_1000_8000_00805f9b34fb => {
-                            data.battery = Some(received_data.value[0]);
-                        }
-                        0x00002a37_0000_1000_8000_00805f9b34fb => {
-                            // heart rate format
-                            if received_data.value[0] & 0b1 > 0 {
-                                // HR is u16
-                                data.hr = u16::from_le_bytes([received_data.value[1], received_data.value[2]]);
-                            } else { 
-                                // HR is u8
-                                data.hr = u16::from(received_data.value[1]);
-                            }
-                            
-                            // contact sensor supported
-                            if received_data.value[0] & 0b100 > 0 {
-                                // contact sensor is supported
-                                data.contact_ok = Some(received_data.value[0] & 0b10 > 0);
-                            } else {
-                                // contact sensor is not supported
-                                data.contact_ok = None;
-                            }
+                    }
+                    0x00002a37_0000_1000_8000_00805f9b34fb => {
+                        if let Err(err) = parse_heart_rate_measurement(&received_data.value, data) {
+                            warn!("Could not parse heart rate measurement {:?}: {err}", received_data.value);
                         }
-                        _ => {}
                     }
-                    if data.battery.is_none() { 
-                        data.battery = initial_battery;
+                    0x00002a53_0000_1000_8000_00805f9b34fb => {
+                        if let Err(err) = parse_rsc_measurement(&received_data.value, data.running.get_or_insert_default()) {
+                            warn!("Could not parse RSC measurement {:?}: {err}", received_data.value);
+                        }
                     }
+                    _ => {}
                 }
+            },
+        ).await
+    }
 
-                let _ = SENDER.send(ChannelTransferObject {
-                    timestamp: Utc::now(),
-                    hr_state: Some(state.clone()),
-                });
-            }
-        });
-        loop {
-            // wait for one second
-            sleep(Duration::from_secs(1)).await;
-            debug!("Testing connectivity...");
-            // check connection to device
-            match device.peripheral.is_connected().await {
-                // connection check not broken
-                Ok(c) => {
-                    debug!("Connectivity successful!");
-                    // if device is connected
-                    if c {
-                        // loop again
-                        continue;
-                    }
-                    // device connection lost
-                    debug!("Disconnected...");
-                }
-                // checking connection returned an error
-                Err(err) => {
-                    error!("Checking connection returned error: {err}");
-                }
-            }
-            
-            // try to reconnect
-            debug!("Reconnecting...");
-            // give the device to seconds for reconnection
-            if let Ok(value) = timeout(Duration::from_secs(2), device.peripheral.connect()).await {
-                match value {
-                    // connection successful
-                    Ok(()) => {
-                        debug!("Reconnected!");
-                        continue;
-                    }
-                    // connection got an error
-                    Err(err) => {
-                        error!("Reconnecting returned error: {err}");
-                    }
-                }
-            }
-            error!("Timeout while reconnecting to device!");
-            
-            // kill loop, which handles heart rate events
-            handle.abort();
-            
-            // deactivate all events
-            for c in &self.characteristics {
-                device.peripheral.unsubscribe(c).await?;
-            }
-            
-            // tell the api, that we are not connected anymore
-            *self.hrm_state.write().await = HrmState::Disconnected;
-            let _ = SENDER.send(ChannelTransferObject {
-                timestamp: Utc::now(),
-                hr_state: Some(HrmState::Disconnected)
-            });
-            
-            // disconnect properly
-            info!("Disconnecting from peripheral {:?}...", device.name);
-            device.peripheral.disconnect().await?;
-            
-            // tell the rest of program, that we disconnected (the program assumes, that this function is never finished)
-            return Ok(());
+    fn rate(device: &FoundDevice) -> Result<MatchConfidence, String>
+    where
+        Self: Sized
+    {
+        if has_characteristic(device, Uuid::from_u128(0x00002a37_0000_1000_8000_00805f9b34fb), CharPropFlags::NOTIFY) {
+            Ok(MatchConfidence::Generic)
+        } else {
+            Err("no Heart Rate Measurement characteristic (0x2A37)".to_owned())
         }
     }
 
@@ -194,18 +170,27 @@ impl Adaptor for Adaptor1 {
         }
         debug!("Services contains correct service.");
 
-        let mut initial_battery = None;
-        if let Some(char) = device.peripheral.characteristics().iter().find(|c| c.uuid == Uuid::from_u128(0x00002a19_0000_1000_8000_00805f9b34fb)) {
-            match device.peripheral.read(char).await {
-                Ok(v) => {
-                    info!("Device has {}% battery left!", v[0]);
-                    initial_battery = Some(v[0]);
-                    characteristics.push(char.clone());
-                }
-                Err(err) => {
-                    warn!("Error while reading battery value: {err}");
-                }
-            }
+        let (initial_battery, battery) = read_battery(&device.peripheral).await;
+        if let Some(level) = initial_battery {
+            info!("Device has {level}% battery left!");
+        }
+        characteristics.extend(battery);
+
+        let profile = read_device_profile(&device.peripheral).await;
+
+        let control_point = device.peripheral.characteristics().into_iter().find(
+            |c| c.uuid == Uuid::from_u128(0x00002a39_0000_1000_8000_00805f9b34fb) && c.properties.contains(CharPropFlags::WRITE)
+        );
+        if control_point.is_some() {
+            debug!("Device supports heart rate control point.");
+        }
+
+        // some devices provide running speed and cadence alongside the heart rate
+        if let Some(rsc) = device.peripheral.characteristics().into_iter().find(
+            |c| c.uuid == Uuid::from_u128(0x00002a53_0000_1000_8000_00805f9b34fb) && c.properties.contains(CharPropFlags::NOTIFY)
+        ) {
+            debug!("Device supports running speed and cadence.");
+            characteristics.push(rsc);
         }
 
         for characteristic in device.peripheral.characteristics() {
@@ -219,6 +204,8 @@ impl Adaptor for Adaptor1 {
             return Ok(Some(Arc::new(Self {
                 found_device: (*device).clone(),
                 characteristics,
+                control_point,
+                profile,
                 hrm_state: Arc::default(),
                 initial_battery
             })));
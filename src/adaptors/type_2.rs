@@ -0,0 +1,148 @@
+//! Adaptor for devices providing the Running Speed and Cadence service (0x1814), like footpods.
+//!
+//! Devices, which also provide the Heart Rate service (0x180D), are handled by [`Adaptor1`](super::type_1::Adaptor1).
+
+use std::sync::Arc;
+use anyhow::anyhow;
+use async_trait::async_trait;
+use btleplug::api::{Characteristic, CharPropFlags};
+use log::{debug, info, warn};
+use mac_address::MacAddress;
+use tokio::sync::RwLock;
+use uuid::Uuid;
+use crate::adaptors::{Adaptor, has_characteristic, MatchConfidence, FoundDevice, HrData, HrmState, read_battery, read_device_profile, run_notification_loop, RunningData};
+use crate::adaptors::parse::{read_u16, read_u32, read_u8};
+use crate::config::{DeviceProfile, Hrm};
+
+/// Parses a RSC Measurement (0x2A53) value into `data`.
+///
+/// Layout: flags (u8), speed (u16, 1/256 m/s), cadence (u8, 1/min),
+/// stride length (u16, 1/100 m, optional), total distance (u32, 1/10 m, optional).
+pub(super) fn parse_rsc_measurement(value: &[u8], data: &mut RunningData) -> anyhow::Result<()> {
+    let flags = read_u8(value, 0)?;
+    data.speed = f32::from(read_u16(value, 1)?) / 256.0;
+    data.cadence = read_u8(value, 3)?;
+    data.is_running = flags & 0b100 > 0;
+    let mut offset = 4;
+
+    // instantaneous stride length present
+    data.stride_length = None;
+    if flags & 0b1 > 0 {
+        data.stride_length = Some(f32::from(read_u16(value, offset)?) / 100.0);
+        offset += 2;
+    }
+
+    // total distance present
+    data.total_distance = None;
+    if flags & 0b10 > 0 {
+        #[allow(clippy::cast_precision_loss)]
+        let distance = read_u32(value, offset)? as f32 / 10.0;
+        data.total_distance = Some(distance);
+    }
+    Ok(())
+}
+
+pub(super) struct Adaptor2 {
+    found_device: FoundDevice,
+    characteristics: Vec<Characteristic>,
+    profile: Option<DeviceProfile>,
+    hrm_state: Arc<RwLock<HrmState>>,
+    initial_battery: Option<u8>
+}
+
+#[async_trait]
+impl Adaptor for Adaptor2 {
+    async fn to_hrm(&self) -> Hrm {
+        Hrm {
+            name: self.found_device.name.clone(),
+            mac: MacAddress::from(self.found_device.addr.into_inner()),
+            adaptor_id: Some(2),
+            profile: self.profile.clone(),
+            wheel_circumference: self.found_device.wheel_circumference,
+        }
+    }
+
+    fn get_addr(&self) -> MacAddress {
+        MacAddress::from(self.found_device.addr.into_inner())
+    }
+
+    async fn shutdown(&self) {
+        let _ = self.found_device.peripheral.disconnect().await;
+    }
+
+    async fn heartbeat_loop(&self) -> anyhow::Result<()> {
+        run_notification_loop(
+            &self.found_device,
+            &self.characteristics,
+            &self.hrm_state,
+            HrData {
+                battery: self.initial_battery,
+                ..HrData::default()
+            },
+            |received_data, data| {
+                match received_data.uuid.as_u128() {
+                    0x00002a19_0000_1000_8000_00805f9b34fb => {
+                        if let Some(level) = received_data.value.first() {
+                            data.battery = Some(*level);
+                        }
+                    }
+                    0x00002a53_0000_1000_8000_00805f9b34fb => {
+                        if let Err(err) = parse_rsc_measurement(&received_data.value, data.running.get_or_insert_default()) {
+                            warn!("Could not parse RSC measurement {:?}: {err}", received_data.value);
+                        }
+                    }
+                    _ => {}
+                }
+            },
+        ).await
+    }
+
+    fn rate(device: &FoundDevice) -> Result<MatchConfidence, String>
+    where
+        Self: Sized
+    {
+        if has_characteristic(device, Uuid::from_u128(0x00002a53_0000_1000_8000_00805f9b34fb), CharPropFlags::NOTIFY) {
+            Ok(MatchConfidence::Generic)
+        } else {
+            Err("no RSC Measurement characteristic (0x2A53)".to_owned())
+        }
+    }
+
+    async fn try_wrap(device: Arc<FoundDevice>) -> anyhow::Result<Option<Arc<dyn Adaptor>>>
+    where
+        Self: Sized
+    {
+        debug!("Trying adaptor2 as matcher...");
+        if !device.properties.services.contains(&Uuid::from_u128(0x00001814_0000_1000_8000_00805f9b34fb))
+            || device.properties.services.contains(&Uuid::from_u128(0x0000180d_0000_1000_8000_00805f9b34fb)) {
+            return Ok(None);
+        }
+
+        if !device.peripheral.is_connected().await.unwrap_or(false) {
+            info!("Trying to connect to {:?}...", device.name);
+            if let Err(err) = device.peripheral.connect().await {
+                return Err(anyhow!("Could not connect to {} because of {:?}!", device.name, err));
+            }
+        }
+        debug!("Discover peripheral {:?} services...", device.name);
+        device.peripheral.discover_services().await?;
+
+        let Some(measurement) = device.peripheral.characteristics().into_iter().find(
+            |c| c.uuid == Uuid::from_u128(0x00002a53_0000_1000_8000_00805f9b34fb) && c.properties.contains(CharPropFlags::NOTIFY)
+        ) else {
+            return Ok(None);
+        };
+        let mut characteristics = vec![measurement];
+        let (initial_battery, battery) = read_battery(&device.peripheral).await;
+        characteristics.extend(battery);
+
+        debug!("adaptor2 matched device!");
+        Ok(Some(Arc::new(Self {
+            found_device: (*device).clone(),
+            characteristics,
+            profile: read_device_profile(&device.peripheral).await,
+            hrm_state: Arc::default(),
+            initial_battery,
+        })))
+    }
+}
@@ -0,0 +1,177 @@
+//! Adaptor for devices providing the Cycling Power service (0x1818), like power meters and smart trainers.
+
+use std::sync::Arc;
+use anyhow::anyhow;
+use async_trait::async_trait;
+use btleplug::api::{Characteristic, CharPropFlags};
+use log::{debug, info, warn};
+use mac_address::MacAddress;
+use tokio::sync::RwLock;
+use uuid::Uuid;
+use crate::adaptors::{Adaptor, has_characteristic, MatchConfidence, CyclingData, FoundDevice, HrData, HrmState, read_battery, read_device_profile, run_notification_loop};
+use crate::adaptors::parse::{DEFAULT_WHEEL_CIRCUMFERENCE, read_u16, read_u32, read_u8, RevolutionCounter};
+use crate::config::{DeviceProfile, Hrm};
+
+/// Wheel and crank counters of a Cycling Power Measurement, kept between notifications.
+pub(super) struct PowerCounters {
+    wheel: RevolutionCounter,
+    crank: RevolutionCounter,
+    /// Circumference of the wheel in m
+    wheel_circumference: f32,
+}
+
+impl PowerCounters {
+    pub(super) fn new(wheel_circumference: f32) -> Self {
+        Self {
+            wheel: RevolutionCounter::default(),
+            crank: RevolutionCounter::default(),
+            wheel_circumference,
+        }
+    }
+}
+
+/// Parses a Cycling Power Measurement (0x2A63) value into `data`.
+///
+/// Layout: flags (u16), instantaneous power (i16, W), pedal power balance (u8, 1/2 %, optional),
+/// accumulated torque (u16, optional), wheel revolutions (u32) and last wheel event time (u16, 1/2048 s, optional),
+/// crank revolutions (u16) and last crank event time (u16, 1/1024 s, optional), followed by fields, which are ignored.
+pub(super) fn parse_cycling_power_measurement(value: &[u8], counters: &mut PowerCounters, data: &mut CyclingData) -> anyhow::Result<()> {
+    let flags = read_u16(value, 0)?;
+    data.power = Some(read_u16(value, 2)?.cast_signed());
+    let mut offset = 4;
+
+    // pedal power balance present
+    if flags & 0b1 > 0 {
+        data.pedal_balance = Some(f32::from(read_u8(value, offset)?) / 2.0);
+        offset += 1;
+    }
+
+    // accumulated torque present
+    if flags & 0b100 > 0 {
+        offset += 2;
+    }
+
+    // wheel revolution data present
+    if flags & 0b1_0000 > 0 {
+        let revolutions = read_u32(value, offset)?;
+        let event_time = read_u16(value, offset + 4)?;
+        offset += 6;
+        data.speed = counters.wheel.update(revolutions, event_time, u32::MAX, 2048.0).map(|r| r * counters.wheel_circumference);
+        #[allow(clippy::cast_precision_loss)]
+        let distance = revolutions as f32 * counters.wheel_circumference;
+        data.distance = Some(distance);
+    }
+
+    // crank revolution data present
+    if flags & 0b10_0000 > 0 {
+        let revolutions = read_u16(value, offset)?;
+        let event_time = read_u16(value, offset + 2)?;
+        data.cadence = counters.crank.update(u32::from(revolutions), event_time, u32::from(u16::MAX), 1024.0).map(|r| r * 60.0);
+    }
+    Ok(())
+}
+
+pub(super) struct Adaptor3 {
+    found_device: FoundDevice,
+    characteristics: Vec<Characteristic>,
+    profile: Option<DeviceProfile>,
+    hrm_state: Arc<RwLock<HrmState>>,
+    initial_battery: Option<u8>
+}
+
+#[async_trait]
+impl Adaptor for Adaptor3 {
+    async fn to_hrm(&self) -> Hrm {
+        Hrm {
+            name: self.found_device.name.clone(),
+            mac: MacAddress::from(self.found_device.addr.into_inner()),
+            adaptor_id: Some(3),
+            profile: self.profile.clone(),
+            wheel_circumference: self.found_device.wheel_circumference,
+        }
+    }
+
+    fn get_addr(&self) -> MacAddress {
+        MacAddress::from(self.found_device.addr.into_inner())
+    }
+
+    async fn shutdown(&self) {
+        let _ = self.found_device.peripheral.disconnect().await;
+    }
+
+    async fn heartbeat_loop(&self) -> anyhow::Result<()> {
+        let mut counters = PowerCounters::new(self.found_device.wheel_circumference.unwrap_or(DEFAULT_WHEEL_CIRCUMFERENCE));
+        run_notification_loop(
+            &self.found_device,
+            &self.characteristics,
+            &self.hrm_state,
+            HrData {
+                battery: self.initial_battery,
+                ..HrData::default()
+            },
+            move |received_data, data| {
+                match received_data.uuid.as_u128() {
+                    0x00002a19_0000_1000_8000_00805f9b34fb => {
+                        if let Some(level) = received_data.value.first() {
+                            data.battery = Some(*level);
+                        }
+                    }
+                    0x00002a63_0000_1000_8000_00805f9b34fb => {
+                        if let Err(err) = parse_cycling_power_measurement(&received_data.value, &mut counters, data.cycling.get_or_insert_default()) {
+                            warn!("Could not parse cycling power measurement {:?}: {err}", received_data.value);
+                        }
+                    }
+                    _ => {}
+                }
+            },
+        ).await
+    }
+
+    fn rate(device: &FoundDevice) -> Result<MatchConfidence, String>
+    where
+        Self: Sized
+    {
+        if has_characteristic(device, Uuid::from_u128(0x00002a63_0000_1000_8000_00805f9b34fb), CharPropFlags::NOTIFY) {
+            Ok(MatchConfidence::Generic)
+        } else {
+            Err("no Cycling Power Measurement characteristic (0x2A63)".to_owned())
+        }
+    }
+
+    async fn try_wrap(device: Arc<FoundDevice>) -> anyhow::Result<Option<Arc<dyn Adaptor>>>
+    where
+        Self: Sized
+    {
+        debug!("Trying adaptor3 as matcher...");
+        if !device.properties.services.contains(&Uuid::from_u128(0x00001818_0000_1000_8000_00805f9b34fb)) {
+            return Ok(None);
+        }
+
+        if !device.peripheral.is_connected().await.unwrap_or(false) {
+            info!("Trying to connect to {:?}...", device.name);
+            if let Err(err) = device.peripheral.connect().await {
+                return Err(anyhow!("Could not connect to {} because of {:?}!", device.name, err));
+            }
+        }
+        debug!("Discover peripheral {:?} services...", device.name);
+        device.peripheral.discover_services().await?;
+
+        let Some(measurement) = device.peripheral.characteristics().into_iter().find(
+            |c| c.uuid == Uuid::from_u128(0x00002a63_0000_1000_8000_00805f9b34fb) && c.properties.contains(CharPropFlags::NOTIFY)
+        ) else {
+            return Ok(None);
+        };
+        let mut characteristics = vec![measurement];
+        let (initial_battery, battery) = read_battery(&device.peripheral).await;
+        characteristics.extend(battery);
+
+        debug!("adaptor3 matched device!");
+        Ok(Some(Arc::new(Self {
+            found_device: (*device).clone(),
+            characteristics,
+            profile: read_device_profile(&device.peripheral).await,
+            hrm_state: Arc::default(),
+            initial_battery,
+        })))
+    }
+}
@@ -0,0 +1,167 @@
+//! Adaptor for devices providing the Cycling Speed and Cadence service (0x1816), like speed and cadence sensors.
+//!
+//! Devices, which also provide the Cycling Power service (0x1818), are handled by [`Adaptor3`](super::type_3::Adaptor3).
+
+use std::sync::Arc;
+use anyhow::anyhow;
+use async_trait::async_trait;
+use btleplug::api::{Characteristic, CharPropFlags};
+use log::{debug, info, warn};
+use mac_address::MacAddress;
+use tokio::sync::RwLock;
+use uuid::Uuid;
+use crate::adaptors::{Adaptor, has_characteristic, MatchConfidence, CyclingData, FoundDevice, HrData, HrmState, read_battery, read_device_profile, run_notification_loop};
+use crate::adaptors::parse::{DEFAULT_WHEEL_CIRCUMFERENCE, read_u16, read_u32, read_u8, RevolutionCounter};
+use crate::config::{DeviceProfile, Hrm};
+
+/// Wheel and crank counters of a CSC Measurement, kept between notifications.
+pub(super) struct CscCounters {
+    wheel: RevolutionCounter,
+    crank: RevolutionCounter,
+    /// Circumference of the wheel in m
+    wheel_circumference: f32,
+}
+
+impl CscCounters {
+    pub(super) fn new(wheel_circumference: f32) -> Self {
+        Self {
+            wheel: RevolutionCounter::default(),
+            crank: RevolutionCounter::default(),
+            wheel_circumference,
+        }
+    }
+}
+
+/// Parses a CSC Measurement (0x2A5B) value into `data`.
+///
+/// Layout: flags (u8), wheel revolutions (u32) and last wheel event time (u16, 1/1024 s, optional),
+/// crank revolutions (u16) and last crank event time (u16, 1/1024 s, optional).
+pub(super) fn parse_csc_measurement(value: &[u8], counters: &mut CscCounters, data: &mut CyclingData) -> anyhow::Result<()> {
+    let flags = read_u8(value, 0)?;
+    let mut offset = 1;
+
+    // wheel revolution data present
+    if flags & 0b1 > 0 {
+        let revolutions = read_u32(value, offset)?;
+        let event_time = read_u16(value, offset + 4)?;
+        offset += 6;
+        data.speed = counters.wheel.update(revolutions, event_time, u32::MAX, 1024.0).map(|r| r * counters.wheel_circumference);
+        #[allow(clippy::cast_precision_loss)]
+        let distance = revolutions as f32 * counters.wheel_circumference;
+        data.distance = Some(distance);
+    }
+
+    // crank revolution data present
+    if flags & 0b10 > 0 {
+        let revolutions = read_u16(value, offset)?;
+        let event_time = read_u16(value, offset + 2)?;
+        data.cadence = counters.crank.update(u32::from(revolutions), event_time, u32::from(u16::MAX), 1024.0).map(|r| r * 60.0);
+    }
+    Ok(())
+}
+
+pub(super) struct Adaptor4 {
+    found_device: FoundDevice,
+    characteristics: Vec<Characteristic>,
+    profile: Option<DeviceProfile>,
+    hrm_state: Arc<RwLock<HrmState>>,
+    initial_battery: Option<u8>
+}
+
+#[async_trait]
+impl Adaptor for Adaptor4 {
+    async fn to_hrm(&self) -> Hrm {
+        Hrm {
+            name: self.found_device.name.clone(),
+            mac: MacAddress::from(self.found_device.addr.into_inner()),
+            adaptor_id: Some(4),
+            profile: self.profile.clone(),
+            wheel_circumference: self.found_device.wheel_circumference,
+        }
+    }
+
+    fn get_addr(&self) -> MacAddress {
+        MacAddress::from(self.found_device.addr.into_inner())
+    }
+
+    async fn shutdown(&self) {
+        let _ = self.found_device.peripheral.disconnect().await;
+    }
+
+    async fn heartbeat_loop(&self) -> anyhow::Result<()> {
+        let mut counters = CscCounters::new(self.found_device.wheel_circumference.unwrap_or(DEFAULT_WHEEL_CIRCUMFERENCE));
+        run_notification_loop(
+            &self.found_device,
+            &self.characteristics,
+            &self.hrm_state,
+            HrData {
+                battery: self.initial_battery,
+                ..HrData::default()
+            },
+            move |received_data, data| {
+                match received_data.uuid.as_u128() {
+                    0x00002a19_0000_1000_8000_00805f9b34fb => {
+                        if let Some(level) = received_data.value.first() {
+                            data.battery = Some(*level);
+                        }
+                    }
+                    0x00002a5b_0000_1000_8000_00805f9b34fb => {
+                        if let Err(err) = parse_csc_measurement(&received_data.value, &mut counters, data.cycling.get_or_insert_default()) {
+                            warn!("Could not parse CSC measurement {:?}: {err}", received_data.value);
+                        }
+                    }
+                    _ => {}
+                }
+            },
+        ).await
+    }
+
+    fn rate(device: &FoundDevice) -> Result<MatchConfidence, String>
+    where
+        Self: Sized
+    {
+        if has_characteristic(device, Uuid::from_u128(0x00002a5b_0000_1000_8000_00805f9b34fb), CharPropFlags::NOTIFY) {
+            Ok(MatchConfidence::Generic)
+        } else {
+            Err("no CSC Measurement characteristic (0x2A5B)".to_owned())
+        }
+    }
+
+    async fn try_wrap(device: Arc<FoundDevice>) -> anyhow::Result<Option<Arc<dyn Adaptor>>>
+    where
+        Self: Sized
+    {
+        debug!("Trying adaptor4 as matcher...");
+        if !device.properties.services.contains(&Uuid::from_u128(0x00001816_0000_1000_8000_00805f9b34fb))
+            || device.properties.services.contains(&Uuid::from_u128(0x00001818_0000_1000_8000_00805f9b34fb)) {
+            return Ok(None);
+        }
+
+        if !device.peripheral.is_connected().await.unwrap_or(false) {
+            info!("Trying to connect to {:?}...", device.name);
+            if let Err(err) = device.peripheral.connect().await {
+                return Err(anyhow!("Could not connect to {} because of {:?}!", device.name, err));
+            }
+        }
+        debug!("Discover peripheral {:?} services...", device.name);
+        device.peripheral.discover_services().await?;
+
+        let Some(measurement) = device.peripheral.characteristics().into_iter().find(
+            |c| c.uuid == Uuid::from_u128(0x00002a5b_0000_1000_8000_00805f9b34fb) && c.properties.contains(CharPropFlags::NOTIFY)
+        ) else {
+            return Ok(None);
+        };
+        let mut characteristics = vec![measurement];
+        let (initial_battery, battery) = read_battery(&device.peripheral).await;
+        characteristics.extend(battery);
+
+        debug!("adaptor4 matched device!");
+        Ok(Some(Arc::new(Self {
+            found_device: (*device).clone(),
+            characteristics,
+            profile: read_device_profile(&device.peripheral).await,
+            hrm_state: Arc::default(),
+            initial_battery,
+        })))
+    }
+}
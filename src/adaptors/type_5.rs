@@ -0,0 +1,134 @@
+//! Adaptor for devices providing the Pulse Oximeter service (0x1822), like finger pulse oximeters.
+
+use std::sync::Arc;
+use anyhow::anyhow;
+use async_trait::async_trait;
+use btleplug::api::{Characteristic, CharPropFlags};
+use log::{debug, info, warn};
+use mac_address::MacAddress;
+use tokio::sync::RwLock;
+use uuid::Uuid;
+use crate::adaptors::{Adaptor, has_characteristic, MatchConfidence, FoundDevice, HrData, HrmState, read_battery, read_device_profile, run_notification_loop};
+use crate::adaptors::parse::read_sfloat;
+use crate::config::{DeviceProfile, Hrm};
+
+/// Parses a PLX Spot-check Measurement (0x2A5E) or PLX Continuous Measurement (0x2A5F) value into `data`.
+///
+/// Both start with flags (u8), `SpO2` (SFLOAT, %) and pulse rate (SFLOAT, bpm); all following fields are ignored.\
+/// Values, which are not available (`NaN`, `NRes` or infinite), are not published.
+#[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+pub(super) fn parse_plx_measurement(value: &[u8], data: &mut HrData) -> anyhow::Result<()> {
+    let spo2 = read_sfloat(value, 1)?;
+    let pulse_rate = read_sfloat(value, 3)?;
+    data.spo2 = spo2.is_finite().then_some(spo2);
+    data.hr = if pulse_rate.is_finite() { pulse_rate.round().max(0.0) as u16 } else { 0 };
+    Ok(())
+}
+
+pub(super) struct Adaptor5 {
+    found_device: FoundDevice,
+    characteristics: Vec<Characteristic>,
+    profile: Option<DeviceProfile>,
+    hrm_state: Arc<RwLock<HrmState>>,
+    initial_battery: Option<u8>
+}
+
+#[async_trait]
+impl Adaptor for Adaptor5 {
+    async fn to_hrm(&self) -> Hrm {
+        Hrm {
+            name: self.found_device.name.clone(),
+            mac: MacAddress::from(self.found_device.addr.into_inner()),
+            adaptor_id: Some(5),
+            profile: self.profile.clone(),
+            wheel_circumference: self.found_device.wheel_circumference,
+        }
+    }
+
+    fn get_addr(&self) -> MacAddress {
+        MacAddress::from(self.found_device.addr.into_inner())
+    }
+
+    async fn shutdown(&self) {
+        let _ = self.found_device.peripheral.disconnect().await;
+    }
+
+    async fn heartbeat_loop(&self) -> anyhow::Result<()> {
+        run_notification_loop(
+            &self.found_device,
+            &self.characteristics,
+            &self.hrm_state,
+            HrData {
+                battery: self.initial_battery,
+                ..HrData::default()
+            },
+            |received_data, data| {
+                match received_data.uuid.as_u128() {
+                    0x00002a19_0000_1000_8000_00805f9b34fb => {
+                        if let Some(level) = received_data.value.first() {
+                            data.battery = Some(*level);
+                        }
+                    }
+                    0x00002a5e_0000_1000_8000_00805f9b34fb | 0x00002a5f_0000_1000_8000_00805f9b34fb => {
+                        if let Err(err) = parse_plx_measurement(&received_data.value, data) {
+                            warn!("Could not parse PLX measurement {:?}: {err}", received_data.value);
+                        }
+                    }
+                    _ => {}
+                }
+            },
+        ).await
+    }
+
+    fn rate(device: &FoundDevice) -> Result<MatchConfidence, String>
+    where
+        Self: Sized
+    {
+        let flags = CharPropFlags::NOTIFY | CharPropFlags::INDICATE;
+        if has_characteristic(device, Uuid::from_u128(0x00002a5e_0000_1000_8000_00805f9b34fb), flags)
+            || has_characteristic(device, Uuid::from_u128(0x00002a5f_0000_1000_8000_00805f9b34fb), flags) {
+            Ok(MatchConfidence::Generic)
+        } else {
+            Err("no PLX measurement characteristic (0x2A5E or 0x2A5F)".to_owned())
+        }
+    }
+
+    async fn try_wrap(device: Arc<FoundDevice>) -> anyhow::Result<Option<Arc<dyn Adaptor>>>
+    where
+        Self: Sized
+    {
+        debug!("Trying adaptor5 as matcher...");
+        if !device.properties.services.contains(&Uuid::from_u128(0x00001822_0000_1000_8000_00805f9b34fb)) {
+            return Ok(None);
+        }
+
+        if !device.peripheral.is_connected().await.unwrap_or(false) {
+            info!("Trying to connect to {:?}...", device.name);
+            if let Err(err) = device.peripheral.connect().await {
+                return Err(anyhow!("Could not connect to {} because of {:?}!", device.name, err));
+            }
+        }
+        debug!("Discover peripheral {:?} services...", device.name);
+        device.peripheral.discover_services().await?;
+
+        // spot-check measurements are indicated, continuous measurements notified
+        let mut characteristics = device.peripheral.characteristics().into_iter().filter(
+            |c| [0x00002a5e_0000_1000_8000_00805f9b34fb, 0x00002a5f_0000_1000_8000_00805f9b34fb].contains(&c.uuid.as_u128())
+                && c.properties.intersects(CharPropFlags::NOTIFY | CharPropFlags::INDICATE)
+        ).collect::<Vec<_>>();
+        if characteristics.is_empty() {
+            return Ok(None);
+        }
+        let (initial_battery, battery) = read_battery(&device.peripheral).await;
+        characteristics.extend(battery);
+
+        debug!("adaptor5 matched device!");
+        Ok(Some(Arc::new(Self {
+            found_device: (*device).clone(),
+            characteristics,
+            profile: read_device_profile(&device.peripheral).await,
+            hrm_state: Arc::default(),
+            initial_battery,
+        })))
+    }
+}
@@ -0,0 +1,487 @@
+//! Adaptor for Polar heart rate monitors providing the Polar Measurement Data (PMD) service, like the Polar H10.
+//!
+//! Besides the heart rate, raw ECG and accelerometer samples are streamed and published on
+//! [`RAW_SENDER`](super::RAW_SENDER).\
+//! Streams are started by writing commands to the PMD control point; the device answers every command with an indication.
+//! The settings of a stream (sample rate, resolution, range) are negotiated from the values the device offers.
+
+use std::sync::Arc;
+use std::time::Duration;
+use anyhow::{anyhow, bail};
+use async_trait::async_trait;
+use btleplug::api::{Characteristic, CharPropFlags, WriteType};
+use chrono::Utc;
+use futures::StreamExt;
+use log::{debug, info, warn};
+use mac_address::MacAddress;
+use tokio::sync::RwLock;
+use tokio::time::timeout;
+use uuid::Uuid;
+use crate::adaptors::{Adaptor, has_characteristic, MatchConfidence, FoundDevice, HrData, HrmState, RAW_SENDER, RawMeasurement, RawTransferObject, read_battery, read_device_profile, run_notification_loop};
+use crate::adaptors::parse::{read_u16, read_u64, read_u8};
+use crate::adaptors::transport::{BlePeripheral, NotificationStream};
+use crate::adaptors::type_1::parse_heart_rate_measurement;
+use crate::config::{DeviceProfile, Hrm};
+
+pub(super) const PMD_CONTROL_POINT: Uuid = Uuid::from_u128(0xfb005c81_02e7_f387_1cad_8acd2d8df0c8);
+pub(super) const PMD_DATA: Uuid = Uuid::from_u128(0xfb005c82_02e7_f387_1cad_8acd2d8df0c8);
+
+/// Control point operation to query the settings of a measurement
+const GET_SETTINGS: u8 = 0x01;
+/// Control point operation to start a measurement
+const START_MEASUREMENT: u8 = 0x02;
+/// First byte of every control point response
+const CONTROL_POINT_RESPONSE: u8 = 0xF0;
+
+/// Setting types of a measurement
+const SAMPLE_RATE: u8 = 0x00;
+const RESOLUTION: u8 = 0x01;
+const RANGE: u8 = 0x02;
+
+/// Time the device has to answer a control point command
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(5);
+
+impl RawMeasurement {
+    /// Measurement type in the PMD service
+    fn pmd_type(self) -> u8 {
+        match self {
+            RawMeasurement::Ecg => 0x00,
+            RawMeasurement::Acc => 0x02,
+        }
+    }
+
+    fn from_pmd_type(value: u8) -> Option<Self> {
+        match value {
+            0x00 => Some(RawMeasurement::Ecg),
+            0x02 => Some(RawMeasurement::Acc),
+            _ => None,
+        }
+    }
+
+    /// Number of values per sample
+    fn channels(self) -> usize {
+        match self {
+            RawMeasurement::Ecg => 1,
+            RawMeasurement::Acc => 3,
+        }
+    }
+
+    /// Value to use for a setting, if the device offers it; otherwise, the first offered value is used.
+    fn preferred(self, setting: u8) -> Option<u16> {
+        match (self, setting) {
+            (RawMeasurement::Ecg, SAMPLE_RATE) => Some(130),
+            (RawMeasurement::Acc, SAMPLE_RATE) => Some(200),
+            (RawMeasurement::Acc, RANGE) => Some(8),
+            _ => None,
+        }
+    }
+}
+
+/// Settings a stream was started with.
+#[derive(Debug, Clone, Default)]
+pub(super) struct StreamSettings {
+    /// All settings as (setting type, value)
+    pub values: Vec<(u8, u16)>,
+}
+
+impl StreamSettings {
+    fn get(&self, setting: u8) -> Option<u16> {
+        self.values.iter().find(|(t, _)| *t == setting).map(|(_, v)| *v)
+    }
+
+    pub fn sample_rate(&self) -> u16 {
+        self.get(SAMPLE_RATE).unwrap_or_default()
+    }
+
+    /// Bits per value; used to decode compressed frames
+    pub fn resolution(&self) -> u16 {
+        self.get(RESOLUTION).unwrap_or(16)
+    }
+}
+
+/// Chooses a value for every setting offered in a response to [`GET_SETTINGS`].
+///
+/// Layout of the response: response code (u8), operation (u8), measurement type (u8), status (u8), more (u8),
+/// followed by the settings: setting type (u8), number of values (u8), values (u16 each).
+pub(super) fn negotiate_settings(measurement: RawMeasurement, response: &[u8]) -> anyhow::Result<StreamSettings> {
+    let status = read_u8(response, 3)?;
+    if status != 0 {
+        return Err(anyhow!("Device refused to send settings (status {status})"));
+    }
+    let mut settings = StreamSettings::default();
+    let mut offset = 5;
+    while offset < response.len() {
+        let setting = read_u8(response, offset)?;
+        let count = usize::from(read_u8(response, offset + 1)?);
+        let offered = (0..count).map(|i| read_u16(response, offset + 2 + 2 * i)).collect::<anyhow::Result<Vec<_>>>()?;
+        offset += 2 + 2 * count;
+
+        let chosen = measurement.preferred(setting).filter(|p| offered.contains(p)).or(offered.first().copied());
+        if let Some(value) = chosen {
+            settings.values.push((setting, value));
+        }
+    }
+    Ok(settings)
+}
+
+/// Builds the command to start `measurement` with `settings`.
+pub(super) fn start_command(measurement: RawMeasurement, settings: &StreamSettings) -> Vec<u8> {
+    let mut command = vec![START_MEASUREMENT, measurement.pmd_type()];
+    for (setting, value) in &settings.values {
+        command.extend([*setting, 1]);
+        command.extend(value.to_le_bytes());
+    }
+    command
+}
+
+/// A decoded PMD data frame.
+#[derive(Debug)]
+pub(super) struct PmdFrame {
+    pub measurement: RawMeasurement,
+    /// Time of the last sample in ns
+    pub sensor_timestamp: u64,
+    /// One entry per sample, each containing one value per channel
+    pub samples: Vec<Vec<i32>>,
+}
+
+/// Interprets `bytes` as little endian signed integer.
+fn read_signed(bytes: &[u8]) -> i32 {
+    let bits = 8 * bytes.len();
+    let raw = bytes.iter().rev().fold(0_u32, |acc, byte| (acc << 8) | u32::from(*byte));
+    sign_extend(raw, bits)
+}
+
+/// Interprets the lowest `bits` bits of `raw` as signed integer.
+fn sign_extend(raw: u32, bits: usize) -> i32 {
+    if bits == 0 || bits >= 32 {
+        return raw.cast_signed();
+    }
+    let shift = 32 - bits;
+    (raw << shift).cast_signed() >> shift
+}
+
+/// Decodes a PMD data frame.
+///
+/// Layout: measurement type (u8), timestamp of the last sample (u64, ns), frame type (u8), samples.\
+/// Uncompressed frames contain the samples one after another (ECG: 3 byte values; accelerometer: 1, 2 or 3 byte
+/// values, depending on the frame type).\
+/// Compressed frames (bit 7 of the frame type set) start with a reference sample (`resolution` bits per value,
+/// rounded up to whole bytes), followed by blocks of deltas to the previous sample:
+/// delta size in bits (u8), number of samples (u8) and the bit packed deltas, least significant bit first.
+pub(super) fn decode_pmd_frame(value: &[u8], resolution: u16) -> anyhow::Result<PmdFrame> {
+    let measurement_type = read_u8(value, 0)?;
+    let measurement = RawMeasurement::from_pmd_type(measurement_type)
+        .ok_or(anyhow!("Unsupported measurement type {measurement_type}"))?;
+    let sensor_timestamp = read_u64(value, 1)?;
+    let frame_type = read_u8(value, 9)?;
+    let channels = measurement.channels();
+    let data = &value[10..];
+
+    let samples = if frame_type & 0x80 == 0 {
+        let value_size = match (measurement, frame_type) {
+            (RawMeasurement::Ecg, 0x00) | (RawMeasurement::Acc, 0x02) => 3,
+            (RawMeasurement::Acc, 0x00) => 1,
+            (RawMeasurement::Acc, 0x01) => 2,
+            _ => return Err(anyhow!("Unsupported frame type {frame_type}")),
+        };
+        if !data.len().is_multiple_of(value_size * channels) {
+            return Err(anyhow!("Frame length {} does not fit sample size {}", data.len(), value_size * channels));
+        }
+        data.chunks(value_size * channels)
+            .map(|sample| sample.chunks(value_size).map(read_signed).collect())
+            .collect()
+    } else {
+        if !(1..=32).contains(&resolution) {
+            bail!("Malformed frame: unsupported resolution of {resolution} bits");
+        }
+        decode_compressed(data, channels, usize::from(resolution).div_ceil(8))?
+    };
+
+    Ok(PmdFrame {
+        measurement,
+        sensor_timestamp,
+        samples,
+    })
+}
+
+/// Decodes the samples of a delta compressed frame; see [`decode_pmd_frame`].
+fn decode_compressed(data: &[u8], channels: usize, value_size: usize) -> anyhow::Result<Vec<Vec<i32>>> {
+    let reference = data.get(..channels * value_size).ok_or(anyhow!("Frame too short for reference sample"))?;
+    let mut samples = vec![reference.chunks(value_size).map(read_signed).collect::<Vec<_>>()];
+    let mut offset = channels * value_size;
+    while offset < data.len() {
+        let delta_size = usize::from(read_u8(data, offset)?);
+        if delta_size == 0 || delta_size > 32 {
+            bail!("Malformed frame: invalid delta size of {delta_size} bits");
+        }
+        let count = usize::from(read_u8(data, offset + 1)?);
+        offset += 2;
+        let block_size = (delta_size * count * channels).div_ceil(8);
+        let block = data.get(offset..offset + block_size).ok_or(anyhow!("Frame too short for delta block"))?;
+        offset += block_size;
+
+        let mut bit = 0;
+        for _ in 0..count {
+            let previous = samples.last().cloned().unwrap_or_default();
+            let mut sample = Vec::with_capacity(channels);
+            for last in previous {
+                let mut raw = 0_u32;
+                for i in 0..delta_size {
+                    let position = bit + i;
+                    if block[position / 8] & (1 << (position % 8)) > 0 {
+                        raw |= 1 << i;
+                    }
+                }
+                bit += delta_size;
+                sample.push(last.wrapping_add(sign_extend(raw, delta_size)));
+            }
+            samples.push(sample);
+        }
+    }
+    Ok(samples)
+}
+
+/// Writes `command` to the control point and waits for the matching response.
+async fn send_command(
+    peripheral: &Arc<dyn BlePeripheral>,
+    control_point: &Characteristic,
+    stream: &mut NotificationStream,
+    command: &[u8],
+) -> anyhow::Result<Vec<u8>> {
+    peripheral.write(control_point, command, WriteType::WithResponse).await?;
+    let expected = [CONTROL_POINT_RESPONSE, command[0], command[1]];
+    timeout(COMMAND_TIMEOUT, async {
+        while let Some(notification) = stream.next().await {
+            if notification.uuid == control_point.uuid && notification.value.starts_with(&expected) {
+                return Ok(notification.value);
+            }
+        }
+        Err(anyhow!("Notification stream closed"))
+    }).await.map_err(|_| anyhow!("Device did not answer command {command:02X?}"))?
+}
+
+/// Negotiates the settings of `measurement` and starts it.
+async fn start_measurement(
+    peripheral: &Arc<dyn BlePeripheral>,
+    control_point: &Characteristic,
+    stream: &mut NotificationStream,
+    measurement: RawMeasurement,
+) -> anyhow::Result<StreamSettings> {
+    let offered = send_command(peripheral, control_point, stream, &[GET_SETTINGS, measurement.pmd_type()]).await?;
+    let settings = negotiate_settings(measurement, &offered)?;
+    debug!("Starting {} stream with settings {:?}", measurement.name(), settings.values);
+    let response = send_command(peripheral, control_point, stream, &start_command(measurement, &settings)).await?;
+    match read_u8(&response, 3)? {
+        0 => Ok(settings),
+        status => Err(anyhow!("Device refused to start the stream (status {status})")),
+    }
+}
+
+/// Measurements started on a device, with their negotiated settings
+type StartedStreams = Vec<(RawMeasurement, StreamSettings)>;
+
+/// Subscribes to the PMD characteristics and starts all `measurements`, the device accepts.
+///
+/// Returns the notification stream, which was opened before subscribing, so no response is lost.
+async fn start_streams(
+    peripheral: &Arc<dyn BlePeripheral>,
+    control_point: &Characteristic,
+    data: &Characteristic,
+    measurements: &[RawMeasurement],
+) -> anyhow::Result<(NotificationStream, StartedStreams)> {
+    let mut stream = peripheral.notifications().await?;
+    peripheral.subscribe(control_point).await?;
+    peripheral.subscribe(data).await?;
+
+    let mut streams = vec![];
+    for measurement in measurements {
+        match start_measurement(peripheral, control_point, &mut stream, *measurement).await {
+            Ok(settings) => streams.push((*measurement, settings)),
+            Err(err) => warn!("Could not start {} stream: {err}", measurement.name()),
+        }
+    }
+    Ok((stream, streams))
+}
+
+/// Decodes the frames of the `started` streams and publishes them as samples of `device`, until the notification stream closes.
+async fn publish_raw_samples(started: Option<(NotificationStream, StartedStreams)>, data_uuid: Uuid, device: MacAddress) {
+    let Some((mut stream, streams)) = started else {
+        return;
+    };
+    while let Some(notification) = stream.next().await {
+        if notification.uuid != data_uuid {
+            continue;
+        }
+        let Some((_, settings)) = streams.iter().find(|(m, _)| Some(m.pmd_type()) == notification.value.first().copied()) else {
+            continue;
+        };
+        match decode_pmd_frame(&notification.value, settings.resolution()) {
+            Ok(frame) => {
+                let _ = RAW_SENDER.send(RawTransferObject {
+                    timestamp: Utc::now(),
+                    device: Some(device),
+                    measurement: frame.measurement,
+                    sample_rate: settings.sample_rate(),
+                    sensor_timestamp: frame.sensor_timestamp,
+                    samples: frame.samples,
+                });
+            }
+            Err(err) => warn!("Could not decode PMD frame {:?}: {err}", notification.value),
+        }
+    }
+}
+
+pub(super) struct Adaptor6 {
+    found_device: FoundDevice,
+    /// Heart rate and battery characteristics
+    characteristics: Vec<Characteristic>,
+    control_point: Characteristic,
+    data: Characteristic,
+    /// Measurements the device supports
+    measurements: Vec<RawMeasurement>,
+    profile: Option<DeviceProfile>,
+    hrm_state: Arc<RwLock<HrmState>>,
+    initial_battery: Option<u8>
+}
+
+#[async_trait]
+impl Adaptor for Adaptor6 {
+    async fn to_hrm(&self) -> Hrm {
+        Hrm {
+            name: self.found_device.name.clone(),
+            mac: MacAddress::from(self.found_device.addr.into_inner()),
+            adaptor_id: Some(6),
+            profile: self.profile.clone(),
+            wheel_circumference: self.found_device.wheel_circumference,
+        }
+    }
+
+    fn get_addr(&self) -> MacAddress {
+        MacAddress::from(self.found_device.addr.into_inner())
+    }
+
+    async fn shutdown(&self) {
+        let _ = self.found_device.peripheral.disconnect().await;
+    }
+
+    async fn heartbeat_loop(&self) -> anyhow::Result<()> {
+        let peripheral = Arc::clone(&self.found_device.peripheral);
+        let control_point = self.control_point.clone();
+        let pmd_data = self.data.clone();
+        let measurements = self.measurements.clone();
+        let mut started = Some(start_streams(&peripheral, &control_point, &pmd_data, &measurements).await?);
+
+        // a reconnected device does not stream anymore, so the streams are started again after every reconnect
+        let reconnected = Arc::clone(&self.found_device.reconnected);
+        let device = self.get_addr();
+        let raw_task = tokio::spawn(async move {
+            loop {
+                tokio::select! {
+                    () = publish_raw_samples(started.take(), pmd_data.uuid, device) => reconnected.notified().await,
+                    () = reconnected.notified() => {}
+                }
+                debug!("Restarting raw streams after reconnect...");
+                started = start_streams(&peripheral, &control_point, &pmd_data, &measurements).await
+                    .inspect_err(|err| warn!("Could not restart raw streams: {err}"))
+                    .ok();
+            }
+        });
+
+        let result = run_notification_loop(
+            &self.found_device,
+            &self.characteristics,
+            &self.hrm_state,
+            HrData {
+                battery: self.initial_battery,
+                ..HrData::default()
+            },
+            |received_data, data| {
+                match received_data.uuid.as_u128() {
+                    0x00002a19_0000_1000_8000_00805f9b34fb => {
+                        if let Some(level) = received_data.value.first() {
+                            data.battery = Some(*level);
+                        }
+                    }
+                    0x00002a37_0000_1000_8000_00805f9b34fb => {
+                        if let Err(err) = parse_heart_rate_measurement(&received_data.value, data) {
+                            warn!("Could not parse heart rate measurement {:?}: {err}", received_data.value);
+                        }
+                    }
+                    _ => {}
+                }
+            },
+        ).await;
+        raw_task.abort();
+        result
+    }
+
+    fn rate(device: &FoundDevice) -> Result<MatchConfidence, String>
+    where
+        Self: Sized
+    {
+        if !has_characteristic(device, Uuid::from_u128(0x00002a37_0000_1000_8000_00805f9b34fb), CharPropFlags::NOTIFY) {
+            return Err("no Heart Rate Measurement characteristic (0x2A37)".to_owned());
+        }
+        if !has_characteristic(device, PMD_CONTROL_POINT, CharPropFlags::INDICATE) || !has_characteristic(device, PMD_DATA, CharPropFlags::NOTIFY) {
+            return Err("no Polar Measurement Data service".to_owned());
+        }
+        Ok(MatchConfidence::Model)
+    }
+
+    async fn try_wrap(device: Arc<FoundDevice>) -> anyhow::Result<Option<Arc<dyn Adaptor>>>
+    where
+        Self: Sized
+    {
+        debug!("Trying adaptor6 as matcher...");
+        if !device.properties.services.contains(&Uuid::from_u128(0x0000180d_0000_1000_8000_00805f9b34fb)) {
+            return Ok(None);
+        }
+
+        if !device.peripheral.is_connected().await.unwrap_or(false) {
+            info!("Trying to connect to {:?}...", device.name);
+            if let Err(err) = device.peripheral.connect().await {
+                return Err(anyhow!("Could not connect to {} because of {:?}!", device.name, err));
+            }
+        }
+        debug!("Discover peripheral {:?} services...", device.name);
+        device.peripheral.discover_services().await?;
+
+        let available = device.peripheral.characteristics();
+        let find = |uuid: Uuid, flags: CharPropFlags| available.iter().find(|c| c.uuid == uuid && c.properties.intersects(flags)).cloned();
+        let (Some(measurement), Some(control_point), Some(data)) = (
+            find(Uuid::from_u128(0x00002a37_0000_1000_8000_00805f9b34fb), CharPropFlags::NOTIFY),
+            find(PMD_CONTROL_POINT, CharPropFlags::INDICATE),
+            find(PMD_DATA, CharPropFlags::NOTIFY),
+        ) else {
+            return Ok(None);
+        };
+
+        // the control point reports the supported measurement types as bit field in the second byte
+        let features = match device.peripheral.read(&control_point).await {
+            Ok(v) => v.get(1).copied().unwrap_or(0xFF),
+            Err(err) => {
+                warn!("Error while reading PMD features: {err}");
+                0xFF
+            }
+        };
+        let measurements = [RawMeasurement::Ecg, RawMeasurement::Acc].into_iter()
+            .filter(|m| features & (1 << m.pmd_type()) > 0)
+            .collect();
+
+        let mut characteristics = vec![measurement];
+        let (initial_battery, battery) = read_battery(&device.peripheral).await;
+        characteristics.extend(battery);
+
+        debug!("adaptor6 matched device!");
+        Ok(Some(Arc::new(Self {
+            found_device: (*device).clone(),
+            characteristics,
+            control_point,
+            data,
+            measurements,
+            profile: read_device_profile(&device.peripheral).await,
+            hrm_state: Arc::default(),
+            initial_battery,
+        })))
+    }
+}
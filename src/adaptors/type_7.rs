@@ -0,0 +1,348 @@
+//! Adaptor for devices providing the Fitness Machine service (0x1826), like treadmills, indoor bikes and rowers.
+
+use std::sync::Arc;
+use anyhow::anyhow;
+use async_trait::async_trait;
+use btleplug::api::{Characteristic, CharPropFlags};
+use log::{debug, info, warn};
+use mac_address::MacAddress;
+use tokio::sync::RwLock;
+use uuid::Uuid;
+use crate::adaptors::{Adaptor, has_characteristic, MatchConfidence, FoundDevice, HrData, HrmState, MachineData, MachineKind, read_battery, read_device_profile, run_notification_loop};
+use crate::adaptors::parse::{read_u16, read_u24, read_u8};
+use crate::adaptors::type_1::parse_heart_rate_measurement;
+use crate::config::{DeviceProfile, Hrm};
+
+/// Treadmill Data (0x2ACD)
+const TREADMILL_DATA: u128 = 0x00002acd_0000_1000_8000_00805f9b34fb;
+/// Indoor Bike Data (0x2AD2)
+const INDOOR_BIKE_DATA: u128 = 0x00002ad2_0000_1000_8000_00805f9b34fb;
+/// Rower Data (0x2AD1)
+const ROWER_DATA: u128 = 0x00002ad1_0000_1000_8000_00805f9b34fb;
+
+/// Converts a speed in 1/100 km/h to m/s.
+fn speed(raw: u16) -> f32 {
+    f32::from(raw) / 360.0
+}
+
+/// Parses the Expended Energy fields (total energy (u16, kcal), energy per hour (u16), energy per minute (u8)).
+fn parse_energy(value: &[u8], offset: usize, data: &mut MachineData) -> anyhow::Result<usize> {
+    let total = read_u16(value, offset)?;
+    // 0xFFFF means "not available"
+    if total != u16::MAX {
+        data.energy = Some(total);
+    }
+    Ok(offset + 5)
+}
+
+/// Parses a Treadmill Data (0x2ACD) value into `data` and `hr`.
+///
+/// Layout: flags (u16), followed by the fields selected by the flags in this order: instantaneous speed
+/// (u16, 1/100 km/h; present if "more data" is NOT set), average speed (u16), total distance (u24, m),
+/// inclination (i16, 1/10 %) and ramp angle (i16), elevation gain (2 x u16), instantaneous pace (u8), average pace (u8),
+/// expended energy, heart rate (u8, bpm), metabolic equivalent (u8), elapsed time (u16, s), remaining time (u16, s),
+/// force on belt (i16, N) and power output (i16, W).\
+/// Fields, which are not present, keep their previous value, because machines may split their data across notifications.
+pub(super) fn parse_treadmill_data(value: &[u8], data: &mut MachineData, hr: &mut u16) -> anyhow::Result<()> {
+    let flags = read_u16(value, 0)?;
+    data.kind = Some(MachineKind::Treadmill);
+    let mut offset = 2;
+
+    if flags & 0b1 == 0 {
+        data.speed = Some(speed(read_u16(value, offset)?));
+        offset += 2;
+    }
+    // average speed
+    if flags & 0b10 > 0 {
+        offset += 2;
+    }
+    if flags & 0b100 > 0 {
+        data.distance = Some(read_u24(value, offset)?);
+        offset += 3;
+    }
+    if flags & 0b1000 > 0 {
+        data.incline = Some(f32::from(read_u16(value, offset)?.cast_signed()) / 10.0);
+        offset += 4;
+    }
+    // elevation gain
+    if flags & 0b1_0000 > 0 {
+        offset += 4;
+    }
+    // instantaneous and average pace
+    if flags & 0b10_0000 > 0 {
+        offset += 1;
+    }
+    if flags & 0b100_0000 > 0 {
+        offset += 1;
+    }
+    if flags & 0b1000_0000 > 0 {
+        offset = parse_energy(value, offset, data)?;
+    }
+    if flags & 0b1_0000_0000 > 0 {
+        *hr = u16::from(read_u8(value, offset)?);
+        offset += 1;
+    }
+    // metabolic equivalent
+    if flags & 0b10_0000_0000 > 0 {
+        offset += 1;
+    }
+    if flags & 0b100_0000_0000 > 0 {
+        data.elapsed_time = Some(read_u16(value, offset)?);
+        offset += 2;
+    }
+    // remaining time
+    if flags & 0b1000_0000_0000 > 0 {
+        offset += 2;
+    }
+    // force on belt, followed by power output
+    if flags & 0b1_0000_0000_0000 > 0 {
+        data.power = Some(read_u16(value, offset + 2)?.cast_signed());
+    }
+    Ok(())
+}
+
+/// Parses an Indoor Bike Data (0x2AD2) value into `data` and `hr`.
+///
+/// Layout: flags (u16), followed by the fields selected by the flags in this order: instantaneous speed
+/// (u16, 1/100 km/h; present if "more data" is NOT set), average speed (u16), instantaneous cadence (u16, 1/2 rpm),
+/// average cadence (u16), total distance (u24, m), resistance level (i16), instantaneous power (i16, W),
+/// average power (i16), expended energy, heart rate (u8, bpm), metabolic equivalent (u8), elapsed time (u16, s) and
+/// remaining time (u16, s).\
+/// Fields, which are not present, keep their previous value, because machines may split their data across notifications.
+pub(super) fn parse_indoor_bike_data(value: &[u8], data: &mut MachineData, hr: &mut u16) -> anyhow::Result<()> {
+    let flags = read_u16(value, 0)?;
+    data.kind = Some(MachineKind::IndoorBike);
+    let mut offset = 2;
+
+    if flags & 0b1 == 0 {
+        data.speed = Some(speed(read_u16(value, offset)?));
+        offset += 2;
+    }
+    // average speed
+    if flags & 0b10 > 0 {
+        offset += 2;
+    }
+    if flags & 0b100 > 0 {
+        data.cadence = Some(f32::from(read_u16(value, offset)?) / 2.0);
+        offset += 2;
+    }
+    // average cadence
+    if flags & 0b1000 > 0 {
+        offset += 2;
+    }
+    if flags & 0b1_0000 > 0 {
+        data.distance = Some(read_u24(value, offset)?);
+        offset += 3;
+    }
+    // resistance level
+    if flags & 0b10_0000 > 0 {
+        offset += 2;
+    }
+    if flags & 0b100_0000 > 0 {
+        data.power = Some(read_u16(value, offset)?.cast_signed());
+        offset += 2;
+    }
+    // average power
+    if flags & 0b1000_0000 > 0 {
+        offset += 2;
+    }
+    if flags & 0b1_0000_0000 > 0 {
+        offset = parse_energy(value, offset, data)?;
+    }
+    if flags & 0b10_0000_0000 > 0 {
+        *hr = u16::from(read_u8(value, offset)?);
+        offset += 1;
+    }
+    // metabolic equivalent
+    if flags & 0b100_0000_0000 > 0 {
+        offset += 1;
+    }
+    if flags & 0b1000_0000_0000 > 0 {
+        data.elapsed_time = Some(read_u16(value, offset)?);
+    }
+    Ok(())
+}
+
+/// Parses a Rower Data (0x2AD1) value into `data` and `hr`.
+///
+/// Layout: flags (u16), followed by the fields selected by the flags in this order: stroke rate (u8, 1/2 strokes
+/// per minute) and stroke count (u16; both present if "more data" is NOT set), average stroke rate (u8),
+/// total distance (u24, m), instantaneous pace (u16, s per 500 m), average pace (u16), instantaneous power (i16, W),
+/// average power (i16), resistance level (i16), expended energy, heart rate (u8, bpm), metabolic equivalent (u8),
+/// elapsed time (u16, s) and remaining time (u16, s).\
+/// The speed is derived from the instantaneous pace.
+/// Fields, which are not present, keep their previous value, because machines may split their data across notifications.
+pub(super) fn parse_rower_data(value: &[u8], data: &mut MachineData, hr: &mut u16) -> anyhow::Result<()> {
+    let flags = read_u16(value, 0)?;
+    data.kind = Some(MachineKind::Rower);
+    let mut offset = 2;
+
+    if flags & 0b1 == 0 {
+        data.stroke_rate = Some(f32::from(read_u8(value, offset)?) / 2.0);
+        data.stroke_count = Some(read_u16(value, offset + 1)?);
+        offset += 3;
+    }
+    // average stroke rate
+    if flags & 0b10 > 0 {
+        offset += 1;
+    }
+    if flags & 0b100 > 0 {
+        data.distance = Some(read_u24(value, offset)?);
+        offset += 3;
+    }
+    if flags & 0b1000 > 0 {
+        let pace = read_u16(value, offset)?;
+        data.speed = Some(if pace == 0 { 0.0 } else { 500.0 / f32::from(pace) });
+        offset += 2;
+    }
+    // average pace
+    if flags & 0b1_0000 > 0 {
+        offset += 2;
+    }
+    if flags & 0b10_0000 > 0 {
+        data.power = Some(read_u16(value, offset)?.cast_signed());
+        offset += 2;
+    }
+    // average power and resistance level
+    if flags & 0b100_0000 > 0 {
+        offset += 2;
+    }
+    if flags & 0b1000_0000 > 0 {
+        offset += 2;
+    }
+    if flags & 0b1_0000_0000 > 0 {
+        offset = parse_energy(value, offset, data)?;
+    }
+    if flags & 0b10_0000_0000 > 0 {
+        *hr = u16::from(read_u8(value, offset)?);
+        offset += 1;
+    }
+    // metabolic equivalent
+    if flags & 0b100_0000_0000 > 0 {
+        offset += 1;
+    }
+    if flags & 0b1000_0000_0000 > 0 {
+        data.elapsed_time = Some(read_u16(value, offset)?);
+    }
+    Ok(())
+}
+
+pub(super) struct Adaptor7 {
+    found_device: FoundDevice,
+    characteristics: Vec<Characteristic>,
+    profile: Option<DeviceProfile>,
+    hrm_state: Arc<RwLock<HrmState>>,
+    initial_battery: Option<u8>
+}
+
+#[async_trait]
+impl Adaptor for Adaptor7 {
+    async fn to_hrm(&self) -> Hrm {
+        Hrm {
+            name: self.found_device.name.clone(),
+            mac: MacAddress::from(self.found_device.addr.into_inner()),
+            adaptor_id: Some(7),
+            profile: self.profile.clone(),
+            wheel_circumference: self.found_device.wheel_circumference,
+        }
+    }
+
+    fn get_addr(&self) -> MacAddress {
+        MacAddress::from(self.found_device.addr.into_inner())
+    }
+
+    async fn shutdown(&self) {
+        let _ = self.found_device.peripheral.disconnect().await;
+    }
+
+    async fn heartbeat_loop(&self) -> anyhow::Result<()> {
+        run_notification_loop(
+            &self.found_device,
+            &self.characteristics,
+            &self.hrm_state,
+            HrData {
+                battery: self.initial_battery,
+                ..HrData::default()
+            },
+            |received_data, data| {
+                let parse = match received_data.uuid.as_u128() {
+                    0x00002a19_0000_1000_8000_00805f9b34fb => {
+                        if let Some(level) = received_data.value.first() {
+                            data.battery = Some(*level);
+                        }
+                        return;
+                    }
+                    0x00002a37_0000_1000_8000_00805f9b34fb => {
+                        if let Err(err) = parse_heart_rate_measurement(&received_data.value, data) {
+                            warn!("Could not parse heart rate measurement {:?}: {err}", received_data.value);
+                        }
+                        return;
+                    }
+                    TREADMILL_DATA => parse_treadmill_data,
+                    INDOOR_BIKE_DATA => parse_indoor_bike_data,
+                    ROWER_DATA => parse_rower_data,
+                    _ => return,
+                };
+                if let Err(err) = parse(&received_data.value, data.machine.get_or_insert_default(), &mut data.hr) {
+                    warn!("Could not parse fitness machine data {:?}: {err}", received_data.value);
+                }
+            },
+        ).await
+    }
+
+    fn rate(device: &FoundDevice) -> Result<MatchConfidence, String>
+    where
+        Self: Sized
+    {
+        if [TREADMILL_DATA, INDOOR_BIKE_DATA, ROWER_DATA].into_iter()
+            .any(|uuid| has_characteristic(device, Uuid::from_u128(uuid), CharPropFlags::NOTIFY)) {
+            Ok(MatchConfidence::Generic)
+        } else {
+            Err("no Treadmill, Indoor Bike or Rower Data characteristic (0x2ACD, 0x2AD2 or 0x2AD1)".to_owned())
+        }
+    }
+
+    async fn try_wrap(device: Arc<FoundDevice>) -> anyhow::Result<Option<Arc<dyn Adaptor>>>
+    where
+        Self: Sized
+    {
+        debug!("Trying adaptor7 as matcher...");
+        if !device.properties.services.contains(&Uuid::from_u128(0x00001826_0000_1000_8000_00805f9b34fb)) {
+            return Ok(None);
+        }
+
+        if !device.peripheral.is_connected().await.unwrap_or(false) {
+            info!("Trying to connect to {:?}...", device.name);
+            if let Err(err) = device.peripheral.connect().await {
+                return Err(anyhow!("Could not connect to {} because of {:?}!", device.name, err));
+            }
+        }
+        debug!("Discover peripheral {:?} services...", device.name);
+        device.peripheral.discover_services().await?;
+
+        let mut characteristics = device.peripheral.characteristics().into_iter().filter(
+            |c| [TREADMILL_DATA, INDOOR_BIKE_DATA, ROWER_DATA].contains(&c.uuid.as_u128()) && c.properties.contains(CharPropFlags::NOTIFY)
+        ).collect::<Vec<_>>();
+        if characteristics.is_empty() {
+            return Ok(None);
+        }
+        // machines with a heart rate receiver often provide the heart rate service as well
+        if let Some(measurement) = device.peripheral.characteristics().into_iter().find(
+            |c| c.uuid == Uuid::from_u128(0x00002a37_0000_1000_8000_00805f9b34fb) && c.properties.contains(CharPropFlags::NOTIFY)
+        ) {
+            debug!("Device supports heart rate measurement.");
+            characteristics.push(measurement);
+        }
+        let (initial_battery, battery) = read_battery(&device.peripheral).await;
+        characteristics.extend(battery);
+
+        debug!("adaptor7 matched device!");
+        Ok(Some(Arc::new(Self {
+            found_device: (*device).clone(),
+            characteristics,
+            profile: read_device_profile(&device.peripheral).await,
+            hrm_state: Arc::default(),
+            initial_battery,
+        })))
+    }
+}
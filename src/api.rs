@@ -1,19 +1,29 @@
-use std::path::Path;
+use std::future::Future;
 use std::process::exit;
 use std::sync::Arc;
 
 use anyhow::anyhow;
+use chrono::Utc;
 use futures::{SinkExt, StreamExt};
 use itertools::Itertools;
 use log::error;
+use mac_address::MacAddress;
 use poem::{Error, handler, IntoResponse, Response};
 use poem::error::{InternalServerError};
-use poem::http::StatusCode;
-use poem::web::{Data, Html, Json, Query};
-use poem::web::websocket::{Message, WebSocket};
-use serde::Deserialize;
+use poem::http::{HeaderMap, StatusCode};
+use poem::http::header::AUTHORIZATION;
+use poem::web::{Data, Html, Json, Path, Query};
+use poem::web::websocket::{Message, WebSocket, WebSocketStream};
+use serde::{Deserialize, Serialize};
 use tera::{Context, ErrorKind, Tera};
-use crate::adaptors::{ChannelTransferObject, get_receiver, HrmState};
+use tokio::sync::broadcast::error::RecvError;
+use crate::adaptors::{ChannelTransferObject, get_raw_receiver, get_receiver, HrmState};
+use crate::adaptors::hrm::{HRM, ScannedDevice};
+use crate::adaptors::reconnect::ReconnectStats;
+use crate::battery::{BATTERY_MONITOR, BatteryReport};
+use crate::events::get_event_receiver;
+use crate::ingest::{INGEST, IngestData};
+use crate::config::Hrm;
 use crate::ProgramData;
 
 // Wrapper struct needed for Poem
@@ -40,17 +50,45 @@ pub async fn list_templates(data: Data<&Arc<ProgramData>>) -> String {
     data.tera.read().await.get_template_names().sorted().join("\n")
 }
 
-/// Returns the actual HeartRate data as json.
+/// Returns the actual heart rate data as json.
 #[handler]
 pub async fn heart_rate(data: Data<&Arc<ProgramData>>) -> Json<ChannelTransferObject> {
     Json(data.0.hr_data.read().await.to_owned())
 }
 
+/// Returns the connected (or last connected) device including its profile as json.
+#[handler]
+pub async fn device() -> Json<Option<Hrm>> {
+    Json(HRM.connected_hrm().await)
+}
+
 /// Renders a specific [`tera::Tera`] template, if existing.
 #[handler]
 pub async fn template(Query(OptionalTemplateName {name}): Query<OptionalTemplateName<String>>, data: Data<&Arc<ProgramData>>) -> Result<Html<String>, poem::Error> {
+    let current = data.0.hr_data.read().await.clone();
+    render_template(&data, name, &current).await
+}
+
+/// Renders a specific [`tera::Tera`] template with the data of a single device.
+#[handler]
+pub async fn device_template(
+    Path(id): Path<String>,
+    Query(OptionalTemplateName {name}): Query<OptionalTemplateName<String>>,
+    data: Data<&Arc<ProgramData>>,
+) -> Result<Html<String>, poem::Error> {
+    let mac = find_device(&data, &id).await?;
+    let current = data.0.device_data.read().await.get(&mac).cloned().unwrap_or(ChannelTransferObject {
+        timestamp: Utc::now(),
+        device: Some(mac),
+        hr_state: None,
+    });
+    render_template(&data, name, &current).await
+}
+
+/// Renders the template `name` (default: `default.html`) with the state of a device.
+async fn render_template(data: &Arc<ProgramData>, name: Option<String>, current: &ChannelTransferObject) -> Result<Html<String>, poem::Error> {
     let mut context = Context::new();
-    if let Some(ref state) = data.0.hr_data.read().await.hr_state {
+    if let Some(ref state) = current.hr_state {
         match state {
             HrmState::Disconnected => context.insert("hr_disc", &true),
             HrmState::Ok(v) => {
@@ -58,14 +96,28 @@ pub async fn template(Query(OptionalTemplateName {name}): Query<OptionalTemplate
                 context.insert("hr_val", &v.hr);
                 context.insert("hr_connected", &v.contact_ok);
                 context.insert("hr_battery", &v.battery);
+                context.insert("hr_battery_remaining", &BATTERY_MONITOR.report(current.device).await.time_remaining);
+                context.insert("hr_rr", &v.rr_intervals);
+                context.insert("hr_energy", &v.energy_expended);
+                context.insert("hr_running", &v.running);
+                context.insert("hr_cycling", &v.cycling);
+                context.insert("hr_spo2", &v.spo2);
+                context.insert("hr_machine", &v.machine);
             }
         }
     }
 
+    let hrm = match current.device {
+        Some(mac) => HRM.connected_hrms().await.into_iter().find(|h| h.mac == mac),
+        None => HRM.connected_hrm().await,
+    };
+    context.insert("hr_device", &hrm);
+    context.insert("device", &current.device.map(|mac| mac.to_string()));
+
     let template_name_value = name.unwrap_or("default.html".to_owned());
 
     // search for template, render it and return result or error
-    data.0.tera.read().await
+    data.tera.read().await
         .render(template_name_value.as_str(), &context)
         .map_err(|err| {
             if let ErrorKind::TemplateNotFound(v) = err.kind {
@@ -106,7 +158,7 @@ pub async fn reload_templates(data: Data<&Arc<ProgramData>>) -> Result<String, E
     }
 
     // load templates, this will return as new Tera instance
-    match load_templates(path, false).await {
+    match load_templates(path.as_deref(), false).await {
         Ok(tera) => {
             // store new instance for usage
             *data.tera.write().await = tera;
@@ -121,26 +173,288 @@ pub async fn reload_templates(data: Data<&Arc<ProgramData>>) -> Result<String, E
     }
 }
 
+/// Returns the battery history of the device shown on `/heart_rate`, including an estimate of the remaining time.
+#[handler]
+pub async fn battery_history(data: Data<&Arc<ProgramData>>) -> Json<BatteryReport> {
+    let current = data.0.hr_data.read().await.device;
+    Json(BATTERY_MONITOR.report(current).await)
+}
+
+/// Returns the battery history of a single device.
+#[handler]
+pub async fn device_battery_history(Path(id): Path<String>, data: Data<&Arc<ProgramData>>) -> Result<Json<BatteryReport>, Error> {
+    let mac = find_device(&data, &id).await?;
+    Ok(Json(BATTERY_MONITOR.report(Some(mac)).await))
+}
+
+/// Connection statistics of a single device, as returned by `/reconnect_stats`.
+#[derive(Serialize)]
+pub struct DeviceReconnectStats {
+    /// mac address of the (last) device; [`None`], if no device was connected yet
+    pub device: Option<String>,
+    #[serde(flatten)]
+    pub stats: ReconnectStats,
+}
+
+/// Returns the disconnects, reconnect latencies and downtime of all devices.
+#[handler]
+pub async fn reconnect_stats() -> Json<Vec<DeviceReconnectStats>> {
+    Json(HRM.reconnect_stats().await.into_iter().map(|(mac, stats)| DeviceReconnectStats {
+        device: mac.map(|m| m.to_string()),
+        stats,
+    }).collect())
+}
+
+/// State of a single device, as returned by `/devices`.
+#[derive(Serialize)]
+pub struct DeviceState {
+    /// id used in the routes below `/devices`; the mac address
+    pub id: String,
+    /// the device, if it was connected
+    pub device: Option<Hrm>,
+    /// the last data, if any was received
+    pub data: Option<ChannelTransferObject>,
+}
+
+/// Lists all configured and connected devices with their last data.
+#[handler]
+pub async fn devices(data: Data<&Arc<ProgramData>>) -> Json<Vec<DeviceState>> {
+    let device_data = data.0.device_data.read().await;
+    let hrms = HRM.connected_hrms().await;
+    let macs = data.0.merged_config.read().await.devices.iter()
+        .chain(device_data.keys())
+        .chain(hrms.iter().map(|h| &h.mac))
+        .copied()
+        .unique()
+        .sorted_by_key(|mac| mac.bytes())
+        .collect::<Vec<_>>();
+    Json(macs.into_iter().map(|mac| DeviceState {
+        id: mac.to_string(),
+        device: hrms.iter().find(|h| h.mac == mac).cloned(),
+        data: device_data.get(&mac).cloned(),
+    }).collect())
+}
+
+/// Returns the actual heart rate data of a single device as json.
+#[handler]
+pub async fn device_heart_rate(Path(id): Path<String>, data: Data<&Arc<ProgramData>>) -> Result<Json<ChannelTransferObject>, Error> {
+    let mac = find_device(&data, &id).await?;
+    Ok(Json(data.0.device_data.read().await.get(&mac).cloned().unwrap_or(ChannelTransferObject {
+        timestamp: Utc::now(),
+        device: Some(mac),
+        hr_state: None,
+    })))
+}
+
+/// Parses the device id of a route and checks, that the device is configured or sent data.
+async fn find_device(data: &Arc<ProgramData>, id: &str) -> Result<MacAddress, Error> {
+    let not_found = || Error::from_string(format!("Device \"{id}\" was not found"), StatusCode::NOT_FOUND);
+    let mac = id.parse::<MacAddress>().map_err(|_| not_found())?;
+    let known = data.merged_config.read().await.devices.contains(&mac)
+        || data.device_data.read().await.contains_key(&mac);
+    known.then_some(mac).ok_or_else(not_found)
+}
+
+// Wrapper struct needed for Poem
+#[derive(Deserialize)]
+pub struct OptionalToken {
+    pub token: Option<String>
+}
+
+/// Receives the heart rate pushed by a companion app.
+///
+/// If a token is configured, it must be sent as bearer token or as `token` query parameter.
+#[handler]
+pub async fn ingest_data(
+    headers: &HeaderMap,
+    Query(OptionalToken {token}): Query<OptionalToken>,
+    Json(body): Json<IngestData>,
+    data: Data<&Arc<ProgramData>>,
+) -> Result<String, Error> {
+    let read = data.merged_config.read().await;
+    if !read.args.ingest {
+        return Err(Error::from_string("Ingestion is not enabled", StatusCode::NOT_FOUND));
+    }
+    if let Some(ref expected) = read.ingest_token {
+        let bearer = headers.get(AUTHORIZATION)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.strip_prefix("Bearer "));
+        if bearer.or(token.as_deref()) != Some(expected.as_str()) {
+            return Err(Error::from_string("Invalid token", StatusCode::UNAUTHORIZED));
+        }
+    }
+    drop(read);
+    INGEST.push(body).await;
+    Ok("Ok".to_owned())
+}
+
+/// Resets the energy expended counter of all connected devices supporting it.
+#[handler]
+pub async fn reset_energy_expended() -> Result<String, Error> {
+    match HRM.reset_energy_expended().await {
+        Ok(()) => Ok("Energy expended reset.".to_owned()),
+        Err(err) => Err(Error::from_string(
+            format!("Resetting energy expended failed: {err}"),
+            StatusCode::BAD_REQUEST
+        ))
+    }
+}
+
+/// Resets the energy expended counter of a connected device.
+#[handler]
+pub async fn reset_device_energy_expended(Path(id): Path<String>) -> Result<String, Error> {
+    control_device(&id, "Resetting energy expended", |mac| HRM.reset_device_energy_expended(mac)).await
+}
+
+/// Scans for devices in range and returns them, without connecting to any of them.
+///
+/// A running scan for a device is finished first; while advertisements are watched, the adapters never stop scanning.
+#[handler]
+pub async fn scan_devices(data: Data<&Arc<ProgramData>>) -> Result<Json<Vec<ScannedDevice>>, Error> {
+    if data.merged_config.read().await.args.advertisements {
+        return Err(Error::from_string("The adapters are busy watching advertisements", StatusCode::CONFLICT));
+    }
+    HRM.scan(&data).await.map(Json).map_err(|err| Error::from_string(
+        format!("Scanning failed: {err}"),
+        StatusCode::INTERNAL_SERVER_ERROR
+    ))
+}
+
+/// Returns the devices found by the last scan.
+#[handler]
+pub async fn scanned_devices() -> Json<Vec<ScannedDevice>> {
+    Json(HRM.scanned_devices().await)
+}
+
+/// Connects to a device instead of the current one, even if it is not known yet.
+#[handler]
+pub async fn connect_device(Path(id): Path<String>) -> Result<String, Error> {
+    control_device(&id, "Connecting", |mac| HRM.connect(mac)).await
+}
+
+/// Disconnects from a device, until it is connected or reconnected again.
+#[handler]
+pub async fn disconnect_device(Path(id): Path<String>) -> Result<String, Error> {
+    control_device(&id, "Disconnecting", |mac| HRM.disconnect(mac)).await
+}
+
+/// Closes the connection to a device and connects to it again.
+#[handler]
+pub async fn reconnect_device(Path(id): Path<String>) -> Result<String, Error> {
+    control_device(&id, "Reconnecting", |mac| HRM.reconnect(mac)).await
+}
+
+/// Removes a device from the known devices and disconnects from it.
+#[handler]
+pub async fn forget_device(Path(id): Path<String>, data: Data<&Arc<ProgramData>>) -> Result<String, Error> {
+    control_device(&id, "Forgetting", |mac| HRM.forget(mac, &data)).await
+}
+
+/// Parses the device id of a route, which may be any mac address, and runs `action` for the device.
+async fn control_device<F: Future<Output = anyhow::Result<()>>>(
+    id: &str,
+    name: &str,
+    action: impl FnOnce(MacAddress) -> F,
+) -> Result<String, Error> {
+    let mac = id.parse::<MacAddress>()
+        .map_err(|_| Error::from_string(format!("\"{id}\" is not a valid mac address"), StatusCode::BAD_REQUEST))?;
+    match action(mac).await {
+        Ok(()) => Ok(format!("{name} requested.")),
+        Err(err) => Err(Error::from_string(
+            format!("{name} failed: {err}"),
+            StatusCode::NOT_FOUND
+        ))
+    }
+}
+
 /// Websocket endpoint
 #[handler]
 pub fn ws(
     ws: WebSocket
 ) -> impl IntoResponse {
-    ws.on_upgrade(move |socket| async move {
-        let (mut sink, _) = socket.split();
+    ws.on_upgrade(move |socket| forward_updates(socket, None))
+}
+
+/// Websocket endpoint for the updates of a single device
+#[handler]
+pub async fn device_ws(
+    Path(id): Path<String>,
+    websocket: WebSocket,
+    data: Data<&Arc<ProgramData>>,
+) -> Result<impl IntoResponse, Error> {
+    let mac = find_device(&data, &id).await?;
+    Ok(websocket.on_upgrade(move |socket| forward_updates(socket, Some(mac))))
+}
+
+/// Forwards all data and events (only of `filter`, if set) to the websocket.
+async fn forward_updates(socket: WebSocketStream, filter: Option<MacAddress>) {
+    let (mut sink, _) = socket.split();
 
-        tokio::spawn(async move {
-            // every time we get a value from the HeartRate Manager, forward it to all clients
-            let mut receiver = get_receiver();
-            while let Ok(msg) = receiver.recv().await {
+    // every time we get a value from the HeartRate Manager or an event, forward it to all clients
+    let mut receiver = get_receiver();
+    let mut event_receiver = get_event_receiver();
+    loop {
+        let message = tokio::select! {
+            msg = receiver.recv() => match msg {
+                Ok(msg) if filter.is_some_and(|mac| msg.device != Some(mac)) => continue,
+                Ok(msg) => serde_json::to_string(&msg),
+                Err(_) => break,
+            },
+            event = event_receiver.recv() => match event {
+                Ok(event) if filter.is_some_and(|mac| event.device != Some(mac)) => continue,
+                Ok(event) => serde_json::to_string(&event),
+                Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => break,
+            },
+        };
+        if let Ok(data) = message {
+            if sink.send(Message::Text(data)).await.is_err() {
+                break;
+            }
+        }
+    }
+}
+
+/// Websocket endpoint for raw samples (ECG, accelerometer) of devices streaming them
+#[handler]
+pub fn raw_ws(
+    websocket: WebSocket
+) -> impl IntoResponse {
+    websocket.on_upgrade(move |socket| forward_raw_samples(socket, None))
+}
+
+/// Websocket endpoint for the raw samples of a single device
+#[handler]
+pub async fn device_raw_ws(
+    Path(id): Path<String>,
+    websocket: WebSocket,
+    data: Data<&Arc<ProgramData>>,
+) -> Result<impl IntoResponse, Error> {
+    let mac = find_device(&data, &id).await?;
+    Ok(websocket.on_upgrade(move |socket| forward_raw_samples(socket, Some(mac))))
+}
+
+/// Forwards all raw samples (only of `filter`, if set) to the websocket.
+async fn forward_raw_samples(socket: WebSocketStream, filter: Option<MacAddress>) {
+    let (mut sink, _) = socket.split();
+
+    // every time we get raw samples from the HeartRate Manager, forward them to all clients
+    let mut receiver = get_raw_receiver();
+    loop {
+        match receiver.recv().await {
+            Ok(msg) if filter.is_some_and(|mac| msg.device != Some(mac)) => {}
+            Ok(msg) => {
                 if let Ok(data) = serde_json::to_string(&msg) {
                     if sink.send(Message::Text(data)).await.is_err() {
                         break;
                     }
                 }
             }
-        });
-    })
+            // slow clients miss some samples, but stay connected
+            Err(RecvError::Lagged(_)) => {}
+            Err(RecvError::Closed) => break,
+        }
+    }
 }
 
 /// Loads all templates.
@@ -148,7 +462,7 @@ pub fn ws(
 /// Returns a [`tera::Tera`] instance with the templates.
 /// Adds some default templates to the instance.
 /// These default templates will not overwrite existing names.
-pub async fn load_templates(http_template_folder: &Option<Box<Path>>, do_exit: bool) -> anyhow::Result<Tera> {
+pub async fn load_templates(http_template_folder: Option<&std::path::Path>, do_exit: bool) -> anyhow::Result<Tera> {
     let mut tera = match http_template_folder {
         // if we do not have a template folder, return empty instance
         None => Tera::default(),
@@ -1,8 +1,12 @@
 //! Command line args parser
 
+use std::path::Path;
+
 use clap::Parser;
 use mac_address::MacAddress;
 
+use crate::simulator::SimulationMode;
+
 /// Capture program arguments as settings.
 /// 
 /// All arguments, which are not [`None`] will override settings set in the [`config::ProgramConfig`](crate::config::ProgramConfig).
@@ -25,12 +29,15 @@ pub struct Args {
     #[clap(default_value = "false", long, action = clap::ArgAction::SetTrue)]
     pub accept_new_device: bool,
 
-    /// HRM mac to use / pair (overrides "hrm_index")
+    /// HRM mac to use / pair (overrides `hrm_index`)
     #[clap(long)]
     pub hrm_mac: Option<MacAddress>,
-    /// HRM index to use (first is 1); will be ignored, if "accept_new_device" is active
+    /// HRM index to use (first is 1); will be ignored, if `accept_new_device` is active
     #[clap(long)]
     pub hrm_index: Option<u8>,
+    /// Connect to this device; repeat to connect to multiple devices at the same time (overrides `hrm_mac` and `hrm_index`)
+    #[clap(long = "device", conflicts_with = "debug_device")]
+    pub devices: Vec<MacAddress>,
 
     /// Pin chosen device for reconnections (set this flag, if multiple devices are used simultaneously)
     #[clap(default_value = "false", long, action = clap::ArgAction::SetTrue)]
@@ -38,8 +45,67 @@ pub struct Args {
     /// Rescan non interactively
     #[clap(default_value = "false", long, action = clap::ArgAction::SetTrue)]
     pub noninteractive_rescan: bool,
+    /// Bluetooth adapter to use: its index (first is 1) or a part of its name; all adapters are used, if not set
+    #[clap(long)]
+    pub adapter: Option<String>,
+    /// List all Bluetooth adapters and exit
+    #[clap(default_value = "false", long, action = clap::ArgAction::SetTrue)]
+    pub list_adapters: bool,
+    /// Seconds to scan for devices; the scan ends earlier, when a known or pinned device is found
+    #[clap(long)]
+    pub scan_duration: Option<u64>,
+    /// List all devices while scanning, not only those advertising a service supported by an adaptor
+    #[clap(default_value = "false", long, action = clap::ArgAction::SetTrue)]
+    pub unfiltered_scan: bool,
     
     /// Debug device; dumps EVERYTHING for the connected device in STDOUT
     #[clap(default_value = "false", long, action = clap::ArgAction::SetTrue)]
-    pub debug_device: bool
+    pub debug_device: bool,
+    /// Write the session of "debug-device" to this capture file
+    #[clap(long, requires = "debug_device")]
+    pub capture: Option<Box<Path>>,
+    /// Print a capture file written with "capture" and exit
+    #[clap(long)]
+    pub show_capture: Option<Box<Path>>,
+    /// Scan once, print which device would be chosen and why, and exit
+    #[clap(default_value = "false", long, action = clap::ArgAction::SetTrue, conflicts_with = "debug_device")]
+    pub dry_run_selection: bool,
+    /// List all built-in and declarative adaptors and exit
+    #[clap(default_value = "false", long, action = clap::ArgAction::SetTrue)]
+    pub list_adaptors: bool,
+
+    /// Do not connect to a device, but publish simulated data instead
+    #[clap(long, value_enum, conflicts_with = "debug_device")]
+    pub simulate: Option<SimulationMode>,
+
+    /// Do not connect to a device, but replay a csv log written by this program instead
+    #[clap(long, conflicts_with_all = ["debug_device", "simulate"])]
+    pub replay: Option<Box<Path>>,
+    /// Speed multiplier for "replay" (0.001 to 1000000); 0 replays as fast as possible
+    #[clap(long, default_value = "1.0", requires = "replay")]
+    pub replay_speed: f64,
+
+    /// Do not connect to a device, but receive the heart rate on "POST /ingest" instead
+    #[clap(default_value = "false", long, action = clap::ArgAction::SetTrue, conflicts_with_all = ["debug_device", "simulate", "replay"])]
+    pub ingest: bool,
+    /// Token required for "POST /ingest"
+    #[clap(long)]
+    pub ingest_token: Option<String>,
+
+    /// Do not connect to a device, but receive the heart rate as OSC message, JSON or plain number over UDP instead
+    #[clap(default_value = "false", long, action = clap::ArgAction::SetTrue, conflicts_with_all = ["debug_device", "simulate", "replay", "ingest"])]
+    pub udp: bool,
+    /// Host to listen on for "udp"
+    #[clap(long)]
+    pub udp_host: Option<String>,
+    /// UDP port to listen on for "udp"
+    #[clap(long)]
+    pub udp_port: Option<u16>,
+    /// OSC address, whose messages contain the heart rate for "udp"
+    #[clap(long)]
+    pub osc_address: Option<String>,
+
+    /// Do not connect to devices, but read the heart rate from their advertisements (passive scan)
+    #[clap(default_value = "false", long, action = clap::ArgAction::SetTrue, conflicts_with_all = ["debug_device", "simulate", "replay", "ingest", "udp"])]
+    pub advertisements: bool,
 }
\ No newline at end of file
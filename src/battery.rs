@@ -0,0 +1,205 @@
+//! Battery history and low battery warnings
+//!
+//! The [`BatteryMonitor`] listens for data on [`SENDER`](crate::adaptors::SENDER) and keeps every change of the
+//! battery level of each device in memory. It estimates the remaining time from the observed drain rate and publishes an
+//! [`Event::LowBattery`], when the level reaches one of the configured thresholds.
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, LazyLock};
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use log::warn;
+use mac_address::MacAddress;
+use serde::Serialize;
+use tokio::sync::RwLock;
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::adaptors::{get_receiver, HrmState};
+use crate::events::{Event, publish};
+use crate::ProgramData;
+
+/// Number of battery level changes kept in memory
+const MAX_ENTRIES: usize = 1000;
+
+/// Static to allow access from "outside".
+pub static BATTERY_MONITOR: LazyLock<BatteryMonitor> = LazyLock::new(|| BatteryMonitor {
+    histories: RwLock::default(),
+});
+
+/// A battery level at a specific time.
+#[derive(Serialize, Clone, Copy, Debug)]
+pub struct BatteryEntry {
+    pub timestamp: DateTime<Utc>,
+    /// battery level in %
+    pub level: u8,
+}
+
+/// Summary of the battery history, as returned by the api.
+#[derive(Serialize, Debug, Default)]
+pub struct BatteryReport {
+    /// last known battery level in %
+    pub level: Option<u8>,
+    /// estimated time until the battery is empty in seconds
+    pub time_remaining: Option<u64>,
+    /// all changes of the battery level, oldest first
+    pub history: Vec<BatteryEntry>,
+}
+
+/// Changes of the battery level and the thresholds to warn at.
+#[derive(Default, Debug)]
+pub struct BatteryHistory {
+    entries: VecDeque<BatteryEntry>,
+    /// thresholds in %
+    thresholds: Vec<u8>,
+    /// thresholds, which were already reported; they are reported again after the battery was charged above them
+    reported: Vec<u8>,
+}
+
+impl BatteryHistory {
+    pub fn new(thresholds: Vec<u8>) -> Self {
+        Self {
+            thresholds,
+            ..Self::default()
+        }
+    }
+
+    /// Records a battery level.
+    ///
+    /// Returns an event, if the level reached a threshold, which was not reported yet.
+    /// If several thresholds are reached at once, only the lowest one is reported.
+    pub fn record(&mut self, timestamp: DateTime<Utc>, level: u8) -> Option<Event> {
+        if self.entries.back().is_some_and(|e| e.level == level) {
+            return None;
+        }
+        self.entries.push_back(BatteryEntry { timestamp, level });
+        if self.entries.len() > MAX_ENTRIES {
+            self.entries.pop_front();
+        }
+
+        self.reported.retain(|t| level <= *t);
+        let reached = self.thresholds.iter()
+            .filter(|t| level <= **t && !self.reported.contains(t))
+            .copied()
+            .collect::<Vec<_>>();
+        self.reported.extend(&reached);
+        let threshold = reached.into_iter().min()?;
+        Some(Event::LowBattery {
+            level,
+            threshold,
+            time_remaining: self.time_remaining().map(|d| d.as_secs()),
+        })
+    }
+
+    /// Estimates the time until the battery is empty from the drain rate since it was charged the last time.
+    ///
+    /// Returns [`None`], if the level did not drop yet.
+    pub fn time_remaining(&self) -> Option<Duration> {
+        let last = self.entries.back()?;
+        // find the first entry after the last increase of the level
+        let mut first = last;
+        for entry in self.entries.iter().rev().skip(1) {
+            if entry.level < first.level {
+                break;
+            }
+            first = entry;
+        }
+        let drained = f64::from(first.level.checked_sub(last.level).filter(|d| *d > 0)?);
+        let elapsed = (last.timestamp - first.timestamp).to_std().ok()?.as_secs_f64();
+        Some(Duration::from_secs_f64(elapsed / drained * f64::from(last.level)))
+    }
+
+    pub fn report(&self) -> BatteryReport {
+        BatteryReport {
+            level: self.entries.back().map(|e| e.level),
+            time_remaining: self.time_remaining().map(|d| d.as_secs()),
+            history: self.entries.iter().copied().collect(),
+        }
+    }
+}
+
+/// Keeps the battery history of the connected devices.
+pub struct BatteryMonitor {
+    /// History of each device; simulated and replayed data uses [`None`]
+    histories: RwLock<HashMap<Option<MacAddress>, BatteryHistory>>,
+}
+
+impl BatteryMonitor {
+    /// Records all battery levels received on [`SENDER`](crate::adaptors::SENDER) until the program exits.
+    pub async fn run(&self, program_data: Arc<ProgramData>) {
+        let thresholds = program_data.merged_config.read().await.low_battery_thresholds.clone();
+
+        let mut receiver = get_receiver();
+        loop {
+            match receiver.recv().await {
+                Ok(data) => {
+                    let Some(HrmState::Ok(hr)) = data.hr_state else {
+                        continue;
+                    };
+                    let Some(battery) = hr.battery else {
+                        continue;
+                    };
+                    let mut histories = self.histories.write().await;
+                    let history = histories.entry(data.device).or_insert_with(|| BatteryHistory::new(thresholds.clone()));
+                    if let Some(event) = history.record(data.timestamp, battery) {
+                        match event {
+                            Event::LowBattery { level, threshold, .. } => {
+                                warn!("Battery level of {} is {level}%; it reached the threshold of {threshold}%!",
+                                    data.device.map_or("the device".to_owned(), |d| d.to_string()));
+                            }
+                        }
+                        publish(data.device, event);
+                    }
+                }
+                Err(RecvError::Lagged(_)) => {}
+                Err(RecvError::Closed) => break,
+            }
+        }
+    }
+
+    /// Returns the report for `device`; it is empty, if no battery level of the device was received yet.
+    pub async fn report(&self, device: Option<MacAddress>) -> BatteryReport {
+        self.histories.read().await.get(&device).map(BatteryHistory::report).unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeDelta;
+
+    use super::*;
+
+    fn at(minutes: i64) -> DateTime<Utc> {
+        DateTime::UNIX_EPOCH + TimeDelta::minutes(minutes)
+    }
+
+    #[test]
+    fn reports_each_threshold_once_until_charged() {
+        let mut history = BatteryHistory::new(vec![20, 10, 5]);
+        assert_eq!(history.record(at(0), 25), None);
+        assert!(matches!(history.record(at(10), 20), Some(Event::LowBattery { level: 20, threshold: 20, .. })));
+        assert_eq!(history.record(at(20), 19), None);
+        // skipping a threshold reports only the lowest one
+        assert!(matches!(history.record(at(30), 4), Some(Event::LowBattery { threshold: 5, .. })));
+        assert_eq!(history.record(at(40), 8), None);
+
+        // charged above all thresholds
+        assert_eq!(history.record(at(50), 90), None);
+        assert!(matches!(history.record(at(60), 20), Some(Event::LowBattery { threshold: 20, .. })));
+        assert_eq!(history.report().history.len(), 7);
+    }
+
+    #[test]
+    fn estimates_time_remaining_since_last_charge() {
+        let mut history = BatteryHistory::new(vec![]);
+        history.record(at(0), 40);
+        assert_eq!(history.time_remaining(), None);
+        history.record(at(10), 35);
+        history.record(at(20), 100);
+        assert_eq!(history.time_remaining(), None);
+        // 2 % in 60 minutes
+        history.record(at(80), 98);
+        assert_eq!(history.time_remaining(), Some(Duration::from_hours(49)));
+        assert_eq!(history.report().time_remaining, Some(49 * 3600));
+    }
+}
@@ -0,0 +1,171 @@
+//! Shared base of the csv loggers
+//!
+//! A [`BufferedLogger`] listens on a broadcast channel and caches the received values.
+//! Every minute and on shutdown, all non saved values are handed to its [`LogFormat`], which appends them to csv files.
+//! The files are only created, when the first values arrive.
+
+use std::collections::VecDeque;
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use chrono::Utc;
+use log::{debug, warn};
+use tokio::sync::RwLock;
+use tokio::sync::broadcast::Receiver;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::time::sleep;
+
+use crate::ProgramData;
+use crate::config::MergedConfig;
+use crate::shutdown_handler::{Shutdown, ShutdownHandler};
+
+/// Defines what a [`BufferedLogger`] receives and how it is written to disk.
+pub trait LogFormat: Default + Send + Sync + 'static {
+    /// Value received on the channel
+    type Message: Clone + Send + 'static;
+    /// Value cached until it is saved
+    type Item: Send + Sync + 'static;
+    /// Name of the logger in log messages
+    const NAME: &'static str;
+    /// Start of the file name, followed by the start time of the logger
+    const FILE_PREFIX: &'static str;
+
+    /// Returns the static logger using this format; it is saved on shutdown.
+    fn logger() -> &'static BufferedLogger<Self>;
+    /// Subscribes to the channel to log.
+    fn subscribe() -> Receiver<Self::Message>;
+    /// Returns the value to cache for `message`, if it should be logged.
+    fn item(message: Self::Message) -> Option<Self::Item>;
+    /// Adapts the format to the configuration, before the logger starts.
+    fn configure(&mut self, _config: &MergedConfig) {}
+    /// Appends `items` to the csv file at `filepath` (or files next to it).
+    ///
+    /// Items, which could not be saved, must remain in `items` to retry them with the next save.
+    fn write(&mut self, filepath: &Path, items: &mut VecDeque<Self::Item>);
+}
+
+/// Caches values and saves them periodically with its [`LogFormat`].
+pub struct BufferedLogger<F: LogFormat> {
+    data: Arc<RwLock<VecDeque<F::Item>>>,
+    format: RwLock<F>,
+    filepath: RwLock<Option<Box<Path>>>,
+    started: AtomicBool,
+    hook_registered: AtomicBool,
+}
+
+impl<F: LogFormat> Default for BufferedLogger<F> {
+    fn default() -> Self {
+        Self {
+            data: Arc::default(),
+            format: RwLock::default(),
+            filepath: RwLock::new(None),
+            started: AtomicBool::from(false),
+            hook_registered: AtomicBool::from(false),
+        }
+    }
+}
+
+#[async_trait]
+impl<F: LogFormat> Shutdown for BufferedLogger<F> {
+    /// Saves all values to the file on shutdown.
+    async fn register_shutdown_hook(&self, shutdown_handler: Arc<ShutdownHandler>) {
+        if self.hook_registered.swap(true, Ordering::Acquire) {
+            warn!("Shutdown hook for {} already exists, aborting append.", F::NAME);
+            return;
+        }
+        shutdown_handler.register_hook(
+            Box::new(|| Box::pin(async {
+                F::logger().write_data().await;
+            }))
+        ).await;
+    }
+}
+
+impl<F: LogFormat> BufferedLogger<F> {
+    /// Start running this logger, if csv logging is enabled.
+    ///
+    /// It will subscribe to the channel of its format to receive values and store them.
+    /// Every minute, all not saved values will be appended to the csv file on disk.
+    pub async fn run(&self, program_data: Arc<ProgramData>) {
+        // if logging is disabled, return
+        if !program_data.merged_config.read().await.enable_csv_log {
+            return;
+        }
+
+        // else if logging was already started, show warning and return
+        // this ensures, that the logger is not running multiple times
+        if self.started.swap(true, Ordering::Acquire) {
+            warn!("{} started multiple times! Stopping all but one.", F::NAME);
+            return;
+        }
+
+        // generate the filepath to log to
+        let read = program_data.merged_config.read().await;
+        let Some(path) = &read.log_filepath else {
+            warn!("Filepath for {} is not set, disabling it!", F::NAME);
+            self.started.store(false, Ordering::Release);
+            return;
+        };
+        *self.filepath.write().await = Some(
+            Box::from(
+                path.join(
+                    format!("{}-{}.csv", F::FILE_PREFIX, Utc::now().format("%Y-%m-%d %H:%M:%S"))
+                )
+            )
+        );
+        self.format.write().await.configure(&read);
+        drop(read);
+
+        // spawn task to receive values and append them to unsaved data list
+        let data_clone = Arc::clone(&self.data);
+        tokio::spawn(async move {
+            let mut receiver = F::subscribe();
+            loop {
+                match receiver.recv().await {
+                    Ok(message) => {
+                        if let Some(item) = F::item(message) {
+                            data_clone.write().await.push_back(item);
+                        }
+                    }
+                    Err(RecvError::Lagged(count)) => {
+                        warn!("{} skipped {count} values, because it could not keep up!", F::NAME);
+                    }
+                    Err(RecvError::Closed) => break,
+                }
+            }
+        });
+
+        // save unsaved values every minute
+        loop {
+            sleep(Duration::from_mins(1)).await;
+            self.write_data().await;
+        }
+    }
+
+    /// Writes all non saved values to the csv file and removes them from the buffer.
+    async fn write_data(&self) {
+        // if logger is not active, return
+        if !self.started.load(Ordering::Acquire) {
+            debug!("Skipping saving of {} data, because logger not active.", F::NAME);
+            return;
+        }
+
+        let write_lock = self.filepath.read().await;
+        let Some(filepath) = write_lock.as_ref() else {
+            warn!("No filepath set for {}", F::NAME);
+            return;
+        };
+
+        // write lock must be held until the saved values are removed, to prevent data loss
+        // this also prevents a second thread from going beyond this point while one thread is saving data
+        let mut data = self.data.write().await;
+        if data.is_empty() {
+            return;
+        }
+        debug!("Saving data of {}", F::NAME);
+        self.format.write().await.write(filepath, &mut data);
+    }
+}
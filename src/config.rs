@@ -1,14 +1,21 @@
 use std::fs::File;
 use std::path::Path;
 use std::process::exit;
+use std::time::Duration;
 use clap::{Parser};
 use config::{Config, File as CFile};
 use log::{error, info};
 use mac_address::MacAddress;
 use serde::{Deserialize, Serialize};
 use serde_json::{to_writer_pretty};
+use crate::adaptors::declarative::AdaptorDefinition;
+use crate::adaptors::reconnect::ReconnectStrategy;
+use crate::adaptors::selection::SelectionPolicy;
 use crate::args::Args;
 
+/// Name of the config file
+const CONFIG_FILE: &str = "settings.json";
+
 /// Program config read from file
 #[derive(Serialize, Deserialize, Debug, Default)]
 #[allow(clippy::module_name_repetitions)]
@@ -18,6 +25,10 @@ pub struct ProgramConfig {
     #[serde(default)]
     pub hrm_list: Vec<Hrm>,
 
+    /// Mac addresses of all devices to connect to at the same time
+    #[serde(default)]
+    pub devices: Option<Vec<MacAddress>>,
+
     /// If the http server should be enabled at all
     #[serde(default)]
     pub enable_http_server: Option<bool>,
@@ -38,26 +49,112 @@ pub struct ProgramConfig {
     /// Where to store the files
     #[serde(default)]
     pub csv_folder: Option<Box<Path>>,
+
+    /// Bluetooth adapter to use: its index (first is 1) or a part of its name; [`None`] uses all adapters
+    #[serde(default)]
+    pub adapter: Option<String>,
+    /// Seconds to scan for devices
+    #[serde(default)]
+    pub scan_duration: Option<u64>,
+    /// If scans should only find devices advertising a service supported by an adaptor
+    #[serde(default)]
+    pub filter_scan: Option<bool>,
+
+    /// Battery levels in %, at which a low battery event is emitted
+    #[serde(default)]
+    pub low_battery_thresholds: Option<Vec<u8>>,
+
+    /// Token required for pushing the heart rate over HTTP; [`None`] accepts all pushes
+    #[serde(default)]
+    pub ingest_token: Option<String>,
+    /// Seconds without a push over HTTP or UDP, after which the state changes to disconnected
+    #[serde(default)]
+    pub ingest_timeout: Option<u64>,
+
+    /// Host to receive UDP datagrams on
+    #[serde(default)]
+    pub udp_host: Option<String>,
+    /// Port to receive UDP datagrams on
+    #[serde(default)]
+    pub udp_port: Option<u16>,
+    /// OSC address, whose messages contain the heart rate
+    #[serde(default)]
+    pub osc_address: Option<String>,
+
+    /// Seconds without an advertisement of a device, after which it is reported as disconnected
+    #[serde(default)]
+    pub advertisement_timeout: Option<u64>,
+
+    /// Rules to choose a device without asking; [`None`] only chooses known or requested devices automatically
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub selection: Option<SelectionPolicy>,
+
+    /// How to reconnect to lost devices; [`None`] uses the defaults of [`ReconnectStrategy`]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reconnect: Option<ReconnectStrategy>,
+
+    /// Adaptors described in the config instead of code
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub adaptors: Vec<AdaptorDefinition>,
+
+    /// File this config was loaded from and is saved to; [`None`] keeps all changes in memory
+    #[serde(skip)]
+    pub file: Option<Box<Path>>,
 }
 
 impl ProgramConfig {
     /// Loads config from file
     pub fn load() -> anyhow::Result<Self> {
-        Ok(
-            Config::builder()
-                .add_source(CFile::with_name("settings.json"))
-                .build()?
-                .try_deserialize()?
-        )
+        let mut config: Self = Config::builder()
+            .add_source(CFile::with_name(CONFIG_FILE))
+            .build()?
+            .try_deserialize()?;
+        config.file = Some(Box::from(Path::new(CONFIG_FILE)));
+        Ok(config)
     }
 
     /// Save the config to file.
     /// 
     /// needed to update `ProgramConfig::hrm_list`
     pub fn save(&self) -> anyhow::Result<()> {
-        Ok(to_writer_pretty(File::create("settings.json")?, &self)?)
+        let Some(ref file) = self.file else {
+            return Ok(());
+        };
+        Ok(to_writer_pretty(File::create(file)?, &self)?)
     }
     
+    /// Updates the stored profile of an already known device.
+    ///
+    /// This will also save the file to disk, if the profile changed.
+    pub fn update_profile(&mut self, mac: MacAddress, profile: Option<DeviceProfile>) {
+        let Some(hrm) = self.hrm_list.iter_mut().find(|d| d.mac == mac) else {
+            return;
+        };
+        if profile.is_none() || hrm.profile == profile {
+            return;
+        }
+        info!("Updating profile of device {}...", hrm.name);
+        hrm.profile = profile;
+        if let Err(error) = self.save() {
+            error!("Error while saving config: {error}");
+        }
+    }
+
+    /// Removes a known device.
+    ///
+    /// This will also save the file to disk. Returns, if the device was known.
+    pub fn remove_hrm(&mut self, mac: MacAddress) -> bool {
+        let Some(position) = self.hrm_list.iter().position(|d| d.mac == mac) else {
+            return false;
+        };
+        let hrm = self.hrm_list.remove(position);
+        info!("Forgetting device {}...", hrm.name);
+        if let Err(error) = self.save() {
+            error!("Error while saving config: {error}");
+        }
+        true
+    }
+
     /// Stores a new device.
     /// 
     /// This will also save the file to disk.\
@@ -68,13 +165,13 @@ impl ProgramConfig {
             self.hrm_list.push(hrm);
             if let Err(error) = self.save() {
                 error!("Error while saving config: {error}");
-            };
+            }
         }
     }
 }
 
 /// Represents a specific previously connected heart rate monitor.
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Hrm {
     /// Name of the monitor
     pub name: String,
@@ -82,7 +179,55 @@ pub struct Hrm {
     pub mac: MacAddress,
     /// The internal id of the adapter to read values and parse them
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub adaptor_id: Option<u16>
+    pub adaptor_id: Option<u16>,
+    /// Information the device provides about itself
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub profile: Option<DeviceProfile>,
+    /// Circumference of the wheel in m, used by cycling sensors to calculate speed and distance (default 2.105)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub wheel_circumference: Option<f32>,
+}
+
+/// Information read from the Device Information Service (0x180A) and the Body Sensor Location (0x2A38).
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+#[serde(default)]
+pub struct DeviceProfile {
+    pub body_sensor_location: Option<BodySensorLocation>,
+    pub manufacturer: Option<String>,
+    pub model: Option<String>,
+    pub serial_number: Option<String>,
+    pub firmware_revision: Option<String>,
+    pub hardware_revision: Option<String>,
+    pub software_revision: Option<String>,
+}
+
+/// Where the heart rate sensor is worn, as reported by the device.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum BodySensorLocation {
+    Other,
+    Chest,
+    Wrist,
+    Finger,
+    Hand,
+    EarLobe,
+    Foot,
+    Unknown,
+}
+
+impl From<u8> for BodySensorLocation {
+    fn from(value: u8) -> Self {
+        match value {
+            0 => Self::Other,
+            1 => Self::Chest,
+            2 => Self::Wrist,
+            3 => Self::Finger,
+            4 => Self::Hand,
+            5 => Self::EarLobe,
+            6 => Self::Foot,
+            _ => Self::Unknown,
+        }
+    }
 }
 
 /// The merged configs from [`ProgramConfig`] and [`Args`]
@@ -100,6 +245,30 @@ pub struct MergedConfig {
     pub enable_csv_log: bool,
     /// Folder where the csv files will be stored
     pub log_filepath: Option<Box<Path>>,
+    /// Bluetooth adapter to use: its index (first is 1) or a part of its name; [`None`] uses all adapters
+    pub adapter: Option<String>,
+    /// Time to scan for devices
+    pub scan_duration: Duration,
+    /// If scans should only find devices advertising a service supported by an adaptor
+    pub filter_scan: bool,
+    /// Battery levels in %, at which a low battery event is emitted
+    pub low_battery_thresholds: Vec<u8>,
+    /// Devices to connect to at the same time; if empty, a single device is chosen as before
+    pub devices: Vec<MacAddress>,
+    /// Token required for pushing the heart rate over HTTP
+    pub ingest_token: Option<String>,
+    /// Time without a push over HTTP or UDP, after which the state changes to disconnected
+    pub ingest_timeout: Duration,
+    /// Host to receive UDP datagrams on
+    pub udp_host: String,
+    /// Port to receive UDP datagrams on
+    pub udp_port: u16,
+    /// OSC address, whose messages contain the heart rate
+    pub osc_address: String,
+    /// Time without an advertisement of a device, after which it is reported as disconnected
+    pub advertisement_timeout: Duration,
+    /// How to reconnect to lost devices
+    pub reconnect: ReconnectStrategy,
     /// The cli [`Args`] object used for this config
     pub args: Args
 }
@@ -114,14 +283,34 @@ impl MergedConfig {
                 exit(0);
             }
         };
-        let config = ProgramConfig::load()?;
-        Ok(Self {
-            program_config: ProgramConfig::load()?,
-            args: cli.clone(),
-            enable_http_server: cli.enable_http_server.or(config.enable_http_server).unwrap_or(false),
-            http_port: cli.http_port.or(config.http_port).unwrap_or(8080),
-            enable_csv_log: cli.enable_csv_log.or(config.enable_csv_log).unwrap_or(false),
-            log_filepath: config.csv_folder,
-        })
+        Ok(Self::new(ProgramConfig::load()?, cli))
+    }
+
+    /// Merges an already loaded [`ProgramConfig`] and [`Args`]
+    pub fn new(program_config: ProgramConfig, cli: Args) -> Self {
+        Self {
+            enable_http_server: cli.enable_http_server.or(program_config.enable_http_server).unwrap_or(false),
+            http_port: cli.http_port.or(program_config.http_port).unwrap_or(8080),
+            enable_csv_log: cli.enable_csv_log.or(program_config.enable_csv_log).unwrap_or(false),
+            log_filepath: program_config.csv_folder.clone(),
+            adapter: cli.adapter.clone().or(program_config.adapter.clone()),
+            scan_duration: Duration::from_secs(cli.scan_duration.or(program_config.scan_duration).unwrap_or(5)),
+            filter_scan: !cli.unfiltered_scan && program_config.filter_scan.unwrap_or(true),
+            low_battery_thresholds: program_config.low_battery_thresholds.clone().unwrap_or(vec![20, 10, 5]),
+            ingest_token: cli.ingest_token.clone().or(program_config.ingest_token.clone()),
+            ingest_timeout: Duration::from_secs(program_config.ingest_timeout.unwrap_or(5)),
+            udp_host: cli.udp_host.clone().or(program_config.udp_host.clone()).unwrap_or("127.0.0.1".to_owned()),
+            udp_port: cli.udp_port.or(program_config.udp_port).unwrap_or(9000),
+            osc_address: cli.osc_address.clone().or(program_config.osc_address.clone()).unwrap_or("/hr".to_owned()),
+            advertisement_timeout: Duration::from_secs(program_config.advertisement_timeout.unwrap_or(10)),
+            reconnect: program_config.reconnect.unwrap_or_default(),
+            devices: if cli.devices.is_empty() {
+                program_config.devices.clone().unwrap_or_default()
+            } else {
+                cli.devices.clone()
+            },
+            program_config,
+            args: cli,
+        }
     }
 }
\ No newline at end of file
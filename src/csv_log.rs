@@ -1,187 +1,165 @@
 //! CSV Logger to write datapoints to file
 //!
 //! This csv logger listens for data on [`hrm::SENDER`] and caches all received values.
-//! Every minute, all non saved data points are saved to a csv file.
+//! Every minute, all non saved data points are saved to a csv file; with multiple devices, to one file per device.
+//! Buffering and saving is done by [`BufferedLogger`].
 
-use std::collections::VecDeque;
+use std::collections::{HashSet, VecDeque};
 use std::fs::OpenOptions;
-use std::path::Path;
-use std::sync::{Arc, LazyLock};
-use std::sync::atomic::{AtomicBool, Ordering};
-use std::time::Duration;
+use std::path::{Path, PathBuf};
+use std::sync::LazyLock;
 
-use async_trait::async_trait;
 use chrono::{DateTime, Local, Utc};
-use log::{error, info, warn};
-use tokio::sync::RwLock;
-use tokio::time::sleep;
+use itertools::Itertools;
+use log::error;
+use mac_address::MacAddress;
+use tokio::sync::broadcast::Receiver;
 
-use crate::adaptors::{get_receiver, HrmState};
-use crate::ProgramData;
-use crate::shutdown_handler::{Shutdown, ShutdownHandler};
+use crate::adaptors::{get_receiver, ChannelTransferObject, HrData, HrmState};
+use crate::buffered_log::{BufferedLogger, LogFormat};
+use crate::config::MergedConfig;
 
 /// Static to allow access from "outside".
-pub static CSV_LOGGER: LazyLock<CsvLogger> = LazyLock::new(|| CsvLogger {
-    data: Arc::default(),
-    filepath: RwLock::new(None),
-    first_save: AtomicBool::from(true),
-    started: AtomicBool::from(false),
-    hook_registered: AtomicBool::from(false),
-});
+pub static CSV_LOGGER: LazyLock<CsvLogger> = LazyLock::new(CsvLogger::default);
 
 /// Logs the heart rate to csv.
-pub struct CsvLogger {
-    #[allow(clippy::type_complexity)]
-    data: Arc<RwLock<VecDeque<(DateTime<Utc>, u16)>>>,
-    first_save: AtomicBool,
-    filepath: RwLock<Option<Box<Path>>>,
-    started: AtomicBool,
-    hook_registered: AtomicBool,
+pub type CsvLogger = BufferedLogger<HeartRateFormat>;
+
+/// A received data point and the device it was received from.
+type DataPoint = (DateTime<Utc>, Option<MacAddress>, HrData);
+
+/// Writes data points to csv, one row per data point.
+#[derive(Default)]
+pub struct HeartRateFormat {
+    /// Files, which already contain the column headers
+    headers_written: HashSet<PathBuf>,
+    /// If each device is logged to its own file; the mac address is appended to the name for each device
+    per_device: bool,
 }
 
-#[async_trait]
-impl Shutdown for CsvLogger {
-    /// Saves all data to the file on shutdown.
-    async fn register_shutdown_hook(&self, shutdown_handler: Arc<ShutdownHandler>) {
-        if self.hook_registered.swap(true, Ordering::Acquire) {
-            warn!("Shutdown hook for csv logger already exists, aborting append.");
-            return;
-        }
-        shutdown_handler.register_hook(
-            Box::new(|| Box::pin(async {
-                CSV_LOGGER.write_data().await;
-            }))
-        ).await;
+impl LogFormat for HeartRateFormat {
+    type Message = ChannelTransferObject;
+    type Item = DataPoint;
+    const NAME: &'static str = "csv logger";
+    const FILE_PREFIX: &'static str = "heartrate-log";
+
+    fn logger() -> &'static CsvLogger {
+        &CSV_LOGGER
     }
-}
 
-impl CsvLogger {
-    /// Start running this logger.
-    ///
-    /// It will subscribe to [`hrm::SENDER`] to receive values and store them.
-    /// Every minute, all not saved points will be appended to the csv file on disk.
-    pub async fn run(&self, program_data: Arc<ProgramData>) {
-        // if logging is disabled, return
-        if !program_data.merged_config.read().await.enable_csv_log {
-            return;
-        }
+    fn subscribe() -> Receiver<ChannelTransferObject> {
+        get_receiver()
+    }
 
-        // else if logging was already started, show warning and return
-        // this ensures, that the logger is not running multiple times
-        if self.started.swap(true, Ordering::Acquire) {
-            warn!("Csv logger started multiple times! Stopping all but one.");
-            return;
+    fn item(message: ChannelTransferObject) -> Option<DataPoint> {
+        match message.hr_state? {
+            HrmState::Disconnected => None,
+            HrmState::Ok(hr) => Some((message.timestamp, message.device, hr)),
         }
+    }
 
-        // generate the filepath to log to
-        match &program_data.merged_config.read().await.log_filepath {
-            None => {
-                warn!("Filepath for csv logger is not set, disabling it!");
-                self.started.store(false, Ordering::Release);
-                return;
-            }
-            Some(path) => {
-                *self.filepath.write().await = Some(
-                    Box::from(
-                        path.join(
-                            format!("heartrate-log-{}.csv", Utc::now().format("%Y-%m-%d %H:%M:%S"))
-                        )
-                    )
-                );
-            }
-        }
+    fn configure(&mut self, config: &MergedConfig) {
+        self.per_device = !config.devices.is_empty() || config.args.advertisements;
+    }
 
-        let data_clone = Arc::clone(&self.data);
-
-        // spawn task to receive data and append it to unsaved data list
-        tokio::spawn(async move {
-            let mut receiver = get_receiver();
-            loop {
-                if let Ok(data) = receiver.recv().await {
-                    if let Some(state) = data.hr_state {
-                        match state {
-                            HrmState::Disconnected => {}
-                            HrmState::Ok(hr) => {
-                                data_clone.write().await.push_back((data.timestamp, hr.hr));
-                            }
-                        }
-                    }
+    fn write(&mut self, filepath: &Path, items: &mut VecDeque<DataPoint>) {
+        // with multiple devices, each device gets its own file
+        let file_of = |device: &Option<MacAddress>| match device {
+            Some(mac) if self.per_device => device_filepath(filepath, *mac),
+            _ => filepath.to_path_buf(),
+        };
+        let files = items.iter().map(|(_, device, _)| file_of(device)).unique().collect::<Vec<_>>();
+        let mut failed = HashSet::new();
+        for file in files {
+            let points = items.iter().filter(|(_, device, _)| file_of(device) == file);
+            match append(&file, points, !self.headers_written.contains(&file)) {
+                Ok(()) => {
+                    self.headers_written.insert(file);
+                }
+                Err(err) => {
+                    error!("Error while saving csv file \"{}\": {err}", file.display());
+                    failed.insert(file);
                 }
             }
-        });
-
-        // save unsaved data every minute
-        loop {
-            self.write_data().await;
-            sleep(Duration::from_secs(60)).await;
-        }
-    }
-
-    /// Writes all non saved points to the csv files and clears the buffer.
-    async fn write_data(&self) {
-        // if logger is not active, return
-        if !self.started.load(Ordering::Acquire) {
-            info!("Skipping saving of csv data, because logger not active.");
-            return;
         }
 
-        // get filepath or return
-        info!("Saving csv data");
-        let write_lock = self.filepath.read().await;
-        let Some(filepath) = write_lock.as_ref() else {
-            warn!("No filepath set for saving csv data");
-            return;
-        };
-
-        // write lock must be held until "data.clear()", to prevent data loss
-        // this also prevents a second thread from going beyond this point while one thread is saving data
-        let mut data = self.data.write().await;
-
-        // open file in append and create mode
-        match OpenOptions::new().append(true).create(true).open(filepath) {
-            Ok(file) => {
-                let mut wtr = csv::Writer::from_writer(file);
-                // if this is the first time we store data, add the column headers
-                if self.first_save.load(Ordering::Acquire) {
-                    // add header to record
-                    if let Err(err) = wtr.write_record(["timestamp (utc)", "time (local)", "heart rate (bpm)"]) {
-                        error!("Error while appending csv header: {err}");
-                        return;
-                    }
-                    // flush changes to file
-                    // do not remove here, because if we get errors later while appending actual data,
-                    // the headers will be lost!
-                    if let Err(err) = wtr.flush() {
-                        error!("Could not write csv header to file: {err}");
-                        return;
-                    }
-                    // prevent function from writing headers a second time
-                    self.first_save.store(false, Ordering::Release);
-                }
+        // clear all saved data; we do not need it anymore, because we append to the files
+        items.retain(|(_, device, _)| failed.contains(&file_of(device)));
+    }
+}
 
-                // add all data to the csv writer
-                for (time, hr) in data.iter() {
-                    if let Err(err) = wtr.write_record(&[
-                        time.timestamp().to_string(),
-                        time.with_timezone(&Local::now().timezone()).format("%H:%M:%S").to_string(),
-                        hr.to_string()
-                    ]) {
-                        error!("Error while appending csv data: {err}");
-                    }
-                }
+/// Returns the file for `device`, next to the file used for a single device.
+pub(crate) fn device_filepath(filepath: &Path, device: MacAddress) -> PathBuf {
+    let stem = filepath.file_stem().map(|s| s.to_string_lossy().into_owned()).unwrap_or_default();
+    let mac = device.bytes().iter().map(|b| format!("{b:02X}")).join("");
+    filepath.with_file_name(format!("{stem}-{mac}.csv"))
+}
 
-                // flush writer to file
-                if let Err(err) = wtr.flush() {
-                    error!("Could not write csv data to file: {err}");
-                    return;
-                }
+/// Appends `points` to the csv file at `filepath`; the column headers are written first, if `header` is set.
+fn append<'a>(filepath: &Path, points: impl Iterator<Item=&'a DataPoint>, header: bool) -> anyhow::Result<()> {
+    // open file in append and create mode
+    let file = OpenOptions::new().append(true).create(true).open(filepath)?;
+    let mut wtr = csv::Writer::from_writer(file);
+    // if this is the first time we store data, add the column headers
+    if header {
+        // add header to record
+        wtr.write_record([
+            "timestamp (utc)",
+            "time (local)",
+            "heart rate (bpm)",
+            "speed (m/s)",
+            "cadence (1/min)",
+            "stride length (m)",
+            "total distance (m)",
+            "power (W)",
+            "cycling speed (m/s)",
+            "cycling cadence (1/min)",
+            "SpO2 (%)",
+            "machine speed (m/s)",
+            "incline (%)",
+            "machine power (W)",
+            "stroke rate (1/min)",
+            "elapsed time (s)",
+        ])?;
+        // flush changes to file
+        // do not remove here, because if we get errors later while appending actual data,
+        // the headers will be lost!
+        wtr.flush()?;
+    }
 
-                // clear all collected data; we do not need it anymore, because we append to the file
-                data.clear();
-            }
-            Err(err) => {
-                error!("Error while saving csv file: {err}");
-            }
+    // add all data to the csv writer
+    for (time, _, hr) in points {
+        let running = hr.running.as_ref();
+        let cycling = hr.cycling.as_ref();
+        let machine = hr.machine.as_ref();
+        if let Err(err) = wtr.write_record(&[
+            time.timestamp().to_string(),
+            time.with_timezone(&Local::now().timezone()).format("%H:%M:%S").to_string(),
+            hr.hr.to_string(),
+            optional_value(running.map(|r| r.speed)),
+            optional_value(running.map(|r| r.cadence)),
+            optional_value(running.and_then(|r| r.stride_length)),
+            optional_value(running.and_then(|r| r.total_distance)),
+            optional_value(cycling.and_then(|c| c.power)),
+            optional_value(cycling.and_then(|c| c.speed)),
+            optional_value(cycling.and_then(|c| c.cadence)),
+            optional_value(hr.spo2),
+            optional_value(machine.and_then(|m| m.speed)),
+            optional_value(machine.and_then(|m| m.incline)),
+            optional_value(machine.and_then(|m| m.power)),
+            optional_value(machine.and_then(|m| m.stroke_rate)),
+            optional_value(machine.and_then(|m| m.elapsed_time)),
+        ]) {
+            error!("Error while appending csv data: {err}");
         }
     }
-}
\ No newline at end of file
+
+    // flush writer to file
+    wtr.flush()?;
+    Ok(())
+}
+
+/// Formats an optional value for a csv cell; [`None`] results in an empty cell.
+fn optional_value<T: ToString>(value: Option<T>) -> String {
+    value.map(|v| v.to_string()).unwrap_or_default()
+}
@@ -0,0 +1,52 @@
+//! Events, which are no measurements, like a low battery
+//!
+//! Events are published on [`EVENT_SENDER`] and forwarded to websocket clients alongside the heart rate data.
+
+use std::sync::LazyLock;
+
+use chrono::{DateTime, Utc};
+use mac_address::MacAddress;
+use serde::Serialize;
+use tokio::sync::broadcast::{channel, Receiver, Sender};
+
+// subscribe to this to get events
+pub static EVENT_SENDER: LazyLock<Sender<EventTransferObject>> = LazyLock::new(|| channel::<EventTransferObject>(64).0);
+
+/// use this to get a receiver for `EVENT_SENDER`
+pub fn get_event_receiver() -> Receiver<EventTransferObject> {
+    EVENT_SENDER.subscribe()
+}
+
+/// contains an event sent through the channel for all receivers
+#[derive(Clone, Serialize, Debug)]
+#[serde(rename_all = "snake_case")]
+pub struct EventTransferObject {
+    pub timestamp: DateTime<Utc>,
+    /// mac address of the device, the event belongs to; [`None`] for simulated or replayed data
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub device: Option<MacAddress>,
+    pub event: Event,
+}
+
+#[derive(Clone, Serialize, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Event {
+    /// The battery level fell to or below a configured threshold
+    LowBattery {
+        /// battery level in %
+        level: u8,
+        /// the threshold in %, which was reached
+        threshold: u8,
+        /// estimated time until the battery is empty in seconds, if enough data is available
+        time_remaining: Option<u64>,
+    },
+}
+
+/// Publishes `event` of `device` with the current time.
+pub fn publish(device: Option<MacAddress>, event: Event) {
+    let _ = EVENT_SENDER.send(EventTransferObject {
+        timestamp: Utc::now(),
+        device,
+        event,
+    });
+}
@@ -0,0 +1,139 @@
+//! Heart rate source fed over HTTP
+//!
+//! Devices, which cannot act as heart rate monitor themselves (like some watches), can push their values to
+//! `POST /ingest` through a companion app. Every push is published on [`SENDER`](crate::adaptors::SENDER), as if it
+//! was received from a connected device. If no push arrives within the configured timeout, the state changes to
+//! [`HrmState::Disconnected`].
+
+use std::sync::{Arc, LazyLock};
+use std::time::Duration;
+
+use chrono::Utc;
+use log::info;
+use serde::Deserialize;
+use tokio::sync::RwLock;
+use tokio::sync::broadcast::Sender;
+use tokio::time::{Instant, sleep, sleep_until};
+
+use crate::adaptors::{ChannelTransferObject, HrData, HrmState, SENDER};
+use crate::CANCELLATION_TOKEN;
+
+/// Static to allow access from "outside".
+pub static INGEST: LazyLock<Ingest> = LazyLock::new(|| Ingest::new(SENDER.clone()));
+
+/// Values pushed to `POST /ingest`.
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct IngestData {
+    /// heart rate in bpm
+    pub hr: u16,
+    pub contact_ok: Option<bool>,
+    /// battery level in %
+    pub battery: Option<u8>,
+    /// RR intervals in ms
+    #[serde(default)]
+    pub rr_intervals: Vec<u16>,
+}
+
+/// Publishes pushed values and watches for missing pushes.
+pub struct Ingest {
+    sender: Sender<ChannelTransferObject>,
+    /// Time of the last push; [`None`] while disconnected
+    last_push: Arc<RwLock<Option<Instant>>>,
+}
+
+impl Ingest {
+    pub fn new(sender: Sender<ChannelTransferObject>) -> Self {
+        Self {
+            sender,
+            last_push: Arc::default(),
+        }
+    }
+
+    /// Publishes pushed values.
+    pub async fn push(&self, data: IngestData) {
+        let mut last_push = self.last_push.write().await;
+        if last_push.is_none() {
+            info!("Receiving heart rate over HTTP.");
+        }
+        *last_push = Some(Instant::now());
+        let _ = self.sender.send(ChannelTransferObject {
+            timestamp: Utc::now(),
+            device: None,
+            hr_state: Some(HrmState::Ok(HrData {
+                hr: data.hr,
+                contact_ok: data.contact_ok,
+                battery: data.battery,
+                rr_intervals: data.rr_intervals,
+                ..HrData::default()
+            })),
+        });
+    }
+
+    /// Publishes [`HrmState::Disconnected`], whenever no push arrived for `timeout`, until the program shuts down.
+    pub async fn run(&self, timeout: Duration) {
+        info!("Waiting for heart rate pushed over HTTP...");
+        while !CANCELLATION_TOKEN.is_cancelled() {
+            let Some(last_push) = *self.last_push.read().await else {
+                sleep(Duration::from_millis(100)).await;
+                continue;
+            };
+            sleep_until(last_push + timeout).await;
+
+            let mut write = self.last_push.write().await;
+            // a push may have arrived while sleeping
+            if write.is_some_and(|t| t.elapsed() >= timeout) {
+                info!("No heart rate was pushed for {} seconds; disconnected.", timeout.as_secs());
+                *write = None;
+                let _ = self.sender.send(ChannelTransferObject {
+                    timestamp: Utc::now(),
+                    device: None,
+                    hr_state: Some(HrmState::Disconnected),
+                });
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::panic)]
+mod tests {
+    use tokio::sync::broadcast::channel;
+
+    use super::*;
+
+    fn data(hr: u16) -> IngestData {
+        IngestData {
+            hr,
+            contact_ok: Some(true),
+            battery: Some(50),
+            rr_intervals: vec![800],
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn publishes_pushes_and_disconnects_after_timeout() {
+        let (sender, mut receiver) = channel(16);
+        let ingest: &'static Ingest = Box::leak(Box::new(Ingest::new(sender)));
+        tokio::spawn(ingest.run(Duration::from_secs(5)));
+
+        ingest.push(data(80)).await;
+        let Some(HrmState::Ok(hr)) = receiver.recv().await.ok().and_then(|d| d.hr_state) else {
+            panic!("expected data");
+        };
+        assert_eq!((hr.hr, hr.contact_ok, hr.battery, hr.rr_intervals), (80, Some(true), Some(50), vec![800]));
+
+        // pushes in time keep the state
+        sleep(Duration::from_secs(4)).await;
+        ingest.push(data(81)).await;
+        sleep(Duration::from_secs(4)).await;
+        assert!(matches!(receiver.recv().await.ok().and_then(|d| d.hr_state), Some(HrmState::Ok(_))));
+        assert!(receiver.is_empty());
+
+        sleep(Duration::from_secs(2)).await;
+        assert!(matches!(receiver.recv().await.ok().and_then(|d| d.hr_state), Some(HrmState::Disconnected)));
+
+        // a new push connects again
+        ingest.push(data(90)).await;
+        assert!(matches!(receiver.recv().await.ok().and_then(|d| d.hr_state), Some(HrmState::Ok(HrData { hr: 90, .. }))));
+    }
+}
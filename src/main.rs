@@ -17,6 +17,7 @@
     clippy::use_debug,
 )]
 
+use std::collections::HashMap;
 use std::error::Error;
 use std::process::exit;
 use std::sync::{Arc, LazyLock};
@@ -25,7 +26,8 @@ use std::time::Duration;
 
 use chrono::Utc;
 use log::{error, info, warn};
-use poem::{EndpointExt, get, Route, Server};
+use mac_address::MacAddress;
+use poem::{EndpointExt, get, post, Route, Server};
 use poem::listener::TcpListener;
 use poem::middleware::Cors;
 use tera::Tera;
@@ -34,21 +36,35 @@ use tokio::time::sleep;
 use tokio_util::sync::CancellationToken;
 use tracing_subscriber::{EnvFilter, fmt};
 
-use crate::adaptors::{ChannelTransferObject, HrmState};
+use crate::adaptors::{ChannelTransferObject, describe_adaptors, HrmState};
+use crate::adaptors::capture::Capture;
+use crate::adaptors::declarative::check_definitions;
+use crate::adaptors::selection::SelectionPolicy;
 use crate::adaptors::hrm::HRM;
-use crate::api::{heart_rate, index, list_templates, load_templates, reload_templates, template, ws};
+use crate::api::{battery_history, connect_device, device, device_battery_history, device_heart_rate, device_raw_ws, device_template, device_ws, devices, disconnect_device, forget_device, heart_rate, index, ingest_data, list_templates, load_templates, reconnect_device, reload_templates, raw_ws, reconnect_stats, reset_device_energy_expended, reset_energy_expended, scan_devices, scanned_devices, template, ws};
 use crate::config::MergedConfig;
+use crate::battery::BATTERY_MONITOR;
 use crate::csv_log::CSV_LOGGER;
+use crate::ingest::INGEST;
+use crate::raw_log::RAW_LOGGER;
 use crate::shutdown_handler::{Shutdown, ShutdownHandler};
-use crate::stdin::run as run_stdin;
+use crate::stdin::{command_loop, run as run_stdin};
 
 mod config;
 mod args;
 mod api;
 mod stdin;
+mod buffered_log;
 mod csv_log;
+mod raw_log;
 mod shutdown_handler;
 mod adaptors;
+mod simulator;
+mod replay;
+mod events;
+mod battery;
+mod ingest;
+mod udp;
 
 pub static CANCELLATION_TOKEN: LazyLock<CancellationToken> = LazyLock::new(CancellationToken::new);
 
@@ -58,8 +74,10 @@ pub struct ProgramData {
     pub merged_config: Arc<RwLock<MergedConfig>>,
     /// All found [`tera::Tera`] templates + the default templates
     pub tera: RwLock<Tera>,
-    /// The last HR data
+    /// The last HR data of any device
     pub hr_data: Arc<RwLock<ChannelTransferObject>>,
+    /// The last HR data of each device
+    pub device_data: Arc<RwLock<HashMap<MacAddress, ChannelTransferObject>>>,
 }
 
 #[allow(clippy::too_many_lines)]
@@ -79,6 +97,48 @@ async fn main() -> Result<(), Box<dyn Error>> {
 
     let mut config = MergedConfig::load()?;
 
+    if config.args.list_adaptors {
+        println!("{}", describe_adaptors(&config.program_config.adaptors));
+        exit(0);
+    }
+    if config.args.list_adapters {
+        if let Err(err) = HRM.list_adapters(config.adapter.as_deref()).await {
+            error!("Could not list Bluetooth adapters: {err}");
+            exit(1);
+        }
+        exit(0);
+    }
+    if let Some(ref path) = config.args.show_capture {
+        match Capture::load(path) {
+            Ok(capture) => {
+                capture.header.print();
+                for notification in &capture.notifications {
+                    notification.print();
+                }
+                exit(0);
+            }
+            Err(err) => {
+                error!("Could not load capture: {err:#}");
+                exit(1);
+            }
+        }
+    }
+
+    if let Err(err) = replay::check_speed(config.args.replay_speed) {
+        error!("{err}");
+        exit(1);
+    }
+    // the adapter is only used, if data is received over Bluetooth
+    let source = &config.args;
+    if let Some(ref selector) = config.adapter {
+        if source.simulate.is_none() && source.replay.is_none() && !source.ingest && !source.udp {
+            if let Err(err) = HRM.check_adapter(selector).await {
+                error!("Invalid adapter: {err}");
+                exit(1);
+            }
+        }
+    }
+
     let debug_active = config.args.debug_device;
     
     let data;
@@ -89,8 +149,10 @@ async fn main() -> Result<(), Box<dyn Error>> {
             merged_config: Arc::new(RwLock::new(config)),
             hr_data: Arc::new(RwLock::new(ChannelTransferObject {
                 timestamp: Utc::now(),
+                device: None,
                 hr_state: None,
             })),
+            device_data: Arc::default(),
         });
         
     } else {
@@ -107,6 +169,19 @@ async fn main() -> Result<(), Box<dyn Error>> {
             }
         }
 
+        if let Err(err) = check_definitions(&config.program_config.adaptors) {
+            error!("Invalid adaptor definition: {err}");
+            exit(1);
+        }
+        if let Some(Err(err)) = config.program_config.selection.as_ref().map(SelectionPolicy::check) {
+            error!("Invalid selection policy: {err}");
+            exit(1);
+        }
+        if let Err(err) = config.reconnect.check() {
+            error!("Invalid reconnect strategy: {err}");
+            exit(1);
+        }
+
         if config.enable_csv_log {
             if let Some(ref folder) = config.log_filepath {
                 if config.enable_csv_log {
@@ -133,12 +208,12 @@ async fn main() -> Result<(), Box<dyn Error>> {
                     exit(1);
                 }
             }
-            load_templates(&config.program_config.http_template_folder, true).await?
+            load_templates(config.program_config.http_template_folder.as_deref(), true).await?
         } else {
             Tera::default()
         };
 
-        if !config.enable_http_server && !config.enable_csv_log {
+        if !config.enable_http_server && !config.enable_csv_log && !config.args.dry_run_selection {
             warn!("No http server and no csv logger active, exiting!");
             exit(0);
         }
@@ -149,13 +224,24 @@ async fn main() -> Result<(), Box<dyn Error>> {
             merged_config: Arc::new(RwLock::new(config)),
             hr_data: Arc::new(RwLock::new(ChannelTransferObject {
                 timestamp: Utc::now(),
+                device: None,
                 hr_state: None,
             })),
+            device_data: Arc::default(),
         });
     }
 
+    if data.merged_config.read().await.args.dry_run_selection {
+        if let Err(err) = HRM.explain_selection(&data).await {
+            error!("Could not scan for devices: {err}");
+            exit(1);
+        }
+        exit(0);
+    }
+
     // watch stdin
     thread::spawn(run_stdin);
+    tokio::spawn(command_loop());
 
     // create a shutdown handler
     // it will run some cleanup hooks for structs when the program panics, receives a signal or exits normally
@@ -163,9 +249,32 @@ async fn main() -> Result<(), Box<dyn Error>> {
     ShutdownHandler::create_watchers();
     let sh = Arc::new(sh);
 
-    // create and start a HeartRate Manager, to observer heart rate
-    HRM.register_shutdown_hook(Arc::clone(&sh)).await;
-    tokio::spawn(HRM.run(Arc::clone(&data)));
+    let args = data.merged_config.read().await.args.clone();
+    if let Some(mode) = args.simulate {
+        // publish simulated data instead of connecting to a real device
+        tokio::spawn(simulator::run(mode));
+    } else if let Some(path) = args.replay {
+        // publish recorded data instead of connecting to a real device
+        tokio::spawn(replay::run(path, args.replay_speed));
+    } else if args.ingest {
+        // publish data pushed over HTTP instead of connecting to a real device
+        if !data.merged_config.read().await.enable_http_server {
+            warn!("\"ingest\" is active, but the HTTP server is disabled; no data can be received!");
+        }
+        let timeout = data.merged_config.read().await.ingest_timeout;
+        tokio::spawn(INGEST.run(timeout));
+    } else if args.udp {
+        // publish data received over UDP instead of connecting to a real device
+        let read = data.merged_config.read().await;
+        tokio::spawn(udp::run(read.udp_host.clone(), read.udp_port, read.osc_address.clone(), read.ingest_timeout));
+    } else if args.advertisements {
+        // read the heart rate of all devices in range from their advertisements, without connecting
+        tokio::spawn(HRM.watch_advertisements(Arc::clone(&data)));
+    } else {
+        // create and start a HeartRate Manager, to observer heart rate
+        HRM.register_shutdown_hook(Arc::clone(&sh)).await;
+        tokio::spawn(HRM.run(Arc::clone(&data)));
+    }
     
     if debug_active {
         info!("Because \"debug device\" is active, server and logger are disabled.");
@@ -176,20 +285,41 @@ async fn main() -> Result<(), Box<dyn Error>> {
     // create and start csv logger; store handle for joining later
     CSV_LOGGER.register_shutdown_hook(Arc::clone(&sh)).await;
     let csv_handle = tokio::spawn(CSV_LOGGER.run(Arc::clone(&data)));
+    RAW_LOGGER.register_shutdown_hook(Arc::clone(&sh)).await;
+    tokio::spawn(RAW_LOGGER.run(Arc::clone(&data)));
 
     // start a loop to store new data in program data created above
     HrmState::storage_loop(Arc::clone(&data));
+    tokio::spawn(BATTERY_MONITOR.run(Arc::clone(&data)));
 
     // setup poem with all routes, middlewares etc
     let app = Route::new()
         .at("/", get(index))
         .at("/heart_rate", get(heart_rate))
         .at("/data", get(heart_rate))
+        .at("/device", get(device))
+        .at("/battery", get(battery_history))
         .at("/template", get(template))
         .at("/reload_templates", get(reload_templates))
         .at("/list_templates", get(list_templates))
+        .at("/reset_energy_expended", post(reset_energy_expended))
         .at("/ws", get(ws))
         .at("/websocket", get(ws))
+        .at("/ws/raw", get(raw_ws))
+        .at("/ingest", post(ingest_data))
+        .at("/devices", get(devices))
+        .at("/reconnect_stats", get(reconnect_stats))
+        .at("/scan", get(scanned_devices).post(scan_devices))
+        .at("/devices/:id/connect", post(connect_device))
+        .at("/devices/:id/disconnect", post(disconnect_device))
+        .at("/devices/:id/reconnect", post(reconnect_device))
+        .at("/devices/:id/forget", post(forget_device))
+        .at("/devices/:id/reset_energy_expended", post(reset_device_energy_expended))
+        .at("/devices/:id/heart_rate", get(device_heart_rate))
+        .at("/devices/:id/battery", get(device_battery_history))
+        .at("/devices/:id/template", get(device_template))
+        .at("/devices/:id/ws", get(device_ws))
+        .at("/devices/:id/ws/raw", get(device_raw_ws))
         .with(Cors::new())
         .data(Arc::clone(&data));
 
@@ -211,7 +341,7 @@ async fn main() -> Result<(), Box<dyn Error>> {
     } else {
         // if we do not have a http server, join csv_handler
         let _ = csv_handle.await;
-    };
+    }
     // drop shutdown handler to trigger all shutdown hooks for all structs
     drop(sh);
     sleep(Duration::from_secs(1)).await;
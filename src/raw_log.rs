@@ -0,0 +1,124 @@
+//! CSV Logger to write raw samples to file
+//!
+//! This logger listens for raw samples (ECG, accelerometer) on [`RAW_SENDER`](crate::adaptors::RAW_SENDER)
+//! and caches them. Every minute, all non saved samples are saved to a separate csv file, one row per sample;
+//! with multiple devices, to one file per device.\
+//! Buffering and saving is done by [`BufferedLogger`].
+
+use std::collections::{HashSet, VecDeque};
+use std::fs::OpenOptions;
+use std::path::{Path, PathBuf};
+use std::sync::LazyLock;
+
+use itertools::Itertools;
+use log::error;
+use mac_address::MacAddress;
+use tokio::sync::broadcast::Receiver;
+
+use crate::adaptors::{get_raw_receiver, RawTransferObject};
+use crate::buffered_log::{BufferedLogger, LogFormat};
+use crate::config::MergedConfig;
+use crate::csv_log::device_filepath;
+
+/// Static to allow access from "outside".
+pub static RAW_LOGGER: LazyLock<RawLogger> = LazyLock::new(RawLogger::default);
+
+/// Logs raw samples to csv.
+pub type RawLogger = BufferedLogger<RawFormat>;
+
+/// Writes raw samples to csv, one row per sample.
+#[derive(Default)]
+pub struct RawFormat {
+    /// Files, which already contain the column headers
+    headers_written: HashSet<PathBuf>,
+    /// If each device is logged to its own file; the mac address is appended to the name for each device
+    per_device: bool,
+}
+
+impl LogFormat for RawFormat {
+    type Message = RawTransferObject;
+    type Item = RawTransferObject;
+    const NAME: &'static str = "raw logger";
+    const FILE_PREFIX: &'static str = "raw-log";
+
+    fn logger() -> &'static RawLogger {
+        &RAW_LOGGER
+    }
+
+    fn subscribe() -> Receiver<RawTransferObject> {
+        get_raw_receiver()
+    }
+
+    fn item(message: RawTransferObject) -> Option<RawTransferObject> {
+        Some(message)
+    }
+
+    fn configure(&mut self, config: &MergedConfig) {
+        self.per_device = !config.devices.is_empty() || config.args.advertisements;
+    }
+
+    fn write(&mut self, filepath: &Path, items: &mut VecDeque<RawTransferObject>) {
+        // with multiple devices, each device gets its own file
+        let file_of = |device: &Option<MacAddress>| match device {
+            Some(mac) if self.per_device => device_filepath(filepath, *mac),
+            _ => filepath.to_path_buf(),
+        };
+        let files = items.iter().map(|frame| file_of(&frame.device)).unique().collect::<Vec<_>>();
+        let mut failed = HashSet::new();
+        for file in files {
+            let frames = items.iter().filter(|frame| file_of(&frame.device) == file);
+            match append(&file, frames, !self.headers_written.contains(&file)) {
+                Ok(()) => {
+                    self.headers_written.insert(file);
+                }
+                Err(err) => {
+                    error!("Error while saving raw csv file \"{}\": {err}", file.display());
+                    failed.insert(file);
+                }
+            }
+        }
+
+        // clear all saved samples; we do not need them anymore, because we append to the files
+        items.retain(|frame| failed.contains(&file_of(&frame.device)));
+    }
+}
+
+/// Appends the samples of `frames` to the csv file at `filepath`; the column headers are written first, if `header` is set.
+fn append<'a>(filepath: &Path, frames: impl Iterator<Item=&'a RawTransferObject>, header: bool) -> anyhow::Result<()> {
+    let file = OpenOptions::new().append(true).create(true).open(filepath)?;
+    let mut wtr = csv::Writer::from_writer(file);
+    if header {
+        wtr.write_record([
+            "received (utc, ms)",
+            "measurement",
+            "sensor timestamp (ns)",
+            "value 1",
+            "value 2",
+            "value 3",
+        ])?;
+        // flush the header on its own, so it is not lost, if appending the samples fails
+        wtr.flush()?;
+    }
+
+    for frame in frames {
+        // the sensor timestamp belongs to the last sample; calculate the others from the sample rate
+        let interval = 1_000_000_000 / u64::from(frame.sample_rate.max(1));
+        let count = frame.samples.len() as u64;
+        for (index, sample) in (0_u64..).zip(&frame.samples) {
+            let sensor_timestamp = frame.sensor_timestamp.saturating_sub((count - 1 - index) * interval);
+            let mut record = vec![
+                frame.timestamp.timestamp_millis().to_string(),
+                frame.measurement.name().to_owned(),
+                sensor_timestamp.to_string(),
+            ];
+            record.extend(sample.iter().map(ToString::to_string));
+            record.resize(6, String::new());
+            if let Err(err) = wtr.write_record(&record) {
+                error!("Error while appending raw csv data: {err}");
+            }
+        }
+    }
+
+    wtr.flush()?;
+    Ok(())
+}
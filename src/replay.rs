@@ -0,0 +1,182 @@
+//! Replays csv logs
+//!
+//! Reads a file written by the [`CsvLogger`](crate::csv_log::CsvLogger) and publishes its data points on
+//! [`SENDER`](crate::adaptors::SENDER) again, as if they were received from a device.
+
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+use anyhow::anyhow;
+use chrono::{DateTime, Utc};
+use log::{error, info};
+use tokio::sync::broadcast::Sender;
+use tokio::task::yield_now;
+use tokio::time::sleep;
+
+use crate::adaptors::{ChannelTransferObject, HrData, HrmState, SENDER};
+use crate::CANCELLATION_TOKEN;
+
+/// Largest speed multiplier; larger ones are not distinguishable from replaying as fast as possible
+const MAX_SPEED: f64 = 1e6;
+/// Smallest speed multiplier (except `0`); it keeps the time between two data points within [`std::time::Duration`]
+const MIN_SPEED: f64 = 1e-3;
+
+/// Reads all data points (timestamp and heart rate) from a csv log.
+fn read_log(path: &Path) -> anyhow::Result<Vec<(DateTime<Utc>, u16)>> {
+    parse_log(File::open(path)?)
+}
+
+/// Parses the data points of a csv log; the first line contains the column headers.
+fn parse_log(log: impl Read) -> anyhow::Result<Vec<(DateTime<Utc>, u16)>> {
+    let mut reader = csv::Reader::from_reader(log);
+    let mut points = vec![];
+    for (line, record) in reader.records().enumerate() {
+        let record = record?;
+        let (Some(timestamp), Some(hr)) = (record.get(0), record.get(2)) else {
+            return Err(anyhow!("Line {} has not enough columns", line + 2));
+        };
+        let timestamp = DateTime::from_timestamp(timestamp.parse()?, 0)
+            .ok_or(anyhow!("Line {} has an invalid timestamp", line + 2))?;
+        points.push((timestamp, hr.parse()?));
+    }
+    Ok(points)
+}
+
+/// Checks, that `speed` is a valid multiplier for the time between two data points; `0` is valid as well.
+pub fn check_speed(speed: f64) -> anyhow::Result<()> {
+    if speed != 0.0 && !(MIN_SPEED..=MAX_SPEED).contains(&speed) {
+        return Err(anyhow!("The replay speed must be 0 or between {MIN_SPEED} and {MAX_SPEED}, not {speed}"));
+    }
+    Ok(())
+}
+
+/// Publishes all data points of the csv log at `path`.
+///
+/// `speed` is a multiplier for the time between two data points; `0` publishes them as fast as possible.\
+/// After the last data point, the state is set to [`HrmState::Disconnected`].
+pub async fn run(path: Box<Path>, speed: f64) {
+    let points = match read_log(&path) {
+        Ok(v) => v,
+        Err(err) => {
+            error!("Could not read csv log \"{}\": {err}", path.display());
+            return;
+        }
+    };
+    info!("Replaying {} data points from \"{}\"...", points.len(), path.display());
+    publish(points, speed, &SENDER).await;
+    info!("Replay finished.");
+}
+
+/// Publishes `points` on `sender`, waiting as long as the recording did between two of them, divided by `speed`.
+///
+/// With a `speed` of `0`, the points are published without waiting.
+async fn publish(points: Vec<(DateTime<Utc>, u16)>, speed: f64, sender: &Sender<ChannelTransferObject>) {
+    let mut previous: Option<DateTime<Utc>> = None;
+    for (timestamp, hr) in points {
+        if CANCELLATION_TOKEN.is_cancelled() {
+            return;
+        }
+        // wait as long as the recording did between both data points
+        if let Some(previous) = previous {
+            if speed > 0.0 {
+                let delay = (timestamp - previous).to_std().unwrap_or_default();
+                sleep(delay.div_f64(speed)).await;
+            } else {
+                // give receivers a chance to keep up
+                yield_now().await;
+            }
+        }
+        previous = Some(timestamp);
+
+        let _ = sender.send(ChannelTransferObject {
+            timestamp,
+            device: None,
+            hr_state: Some(HrmState::Ok(HrData {
+                hr,
+                ..HrData::default()
+            })),
+        });
+    }
+
+    let _ = sender.send(ChannelTransferObject {
+        timestamp: Utc::now(),
+        device: None,
+        hr_state: Some(HrmState::Disconnected),
+    });
+}
+
+#[cfg(test)]
+#[allow(clippy::expect_used)]
+mod tests {
+    use std::time::Duration;
+
+    use tokio::sync::broadcast::channel;
+    use tokio::time::Instant;
+
+    use super::*;
+
+    const LOG: &str = "timestamp (utc),time (local),heart rate (bpm),speed (m/s)\n\
+        1700000000,23:13:20,60,\n\
+        1700000010,23:13:30,62,\n\
+        1700000030,23:13:50,65,1.5\n";
+
+    #[test]
+    fn parses_csv_log() {
+        let points = parse_log(LOG.as_bytes()).expect("valid log");
+        assert_eq!(points.iter().map(|(_, hr)| *hr).collect::<Vec<_>>(), vec![60, 62, 65]);
+        assert_eq!(points.first().map(|(t, _)| t.timestamp()), Some(1_700_000_000));
+
+        assert!(parse_log("timestamp,time,hr\n1700000000,23:13:20\n".as_bytes()).is_err());
+        assert!(parse_log("timestamp,time,hr\nyesterday,23:13:20,60\n".as_bytes()).is_err());
+        assert!(parse_log("timestamp,time,hr\n1700000000,23:13:20,fast\n".as_bytes()).is_err());
+    }
+
+    #[test]
+    fn rejects_invalid_speed() {
+        for speed in [0.0, 0.5, 1e6] {
+            assert!(check_speed(speed).is_ok(), "{speed} should be accepted");
+        }
+        for speed in [-1.0, 1e-300, 1e7, f64::NAN, f64::INFINITY] {
+            assert!(check_speed(speed).is_err(), "{speed} should be rejected");
+        }
+    }
+
+    /// Publishes the data points of [`LOG`] with `speed` and returns when each of them was received.
+    async fn replay(speed: f64) -> Vec<(Duration, Option<u16>)> {
+        let (sender, mut receiver) = channel(16);
+        let points = parse_log(LOG.as_bytes()).expect("valid log");
+        let start = Instant::now();
+        tokio::spawn(async move { publish(points, speed, &sender).await });
+
+        let mut published = vec![];
+        while let Ok(object) = receiver.recv().await {
+            let hr = match object.hr_state {
+                Some(HrmState::Ok(data)) => Some(data.hr),
+                _ => None,
+            };
+            published.push((start.elapsed(), hr));
+        }
+        published
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn waits_between_data_points_scaled_by_speed() {
+        assert_eq!(replay(2.0).await, vec![
+            (Duration::ZERO, Some(60)),
+            (Duration::from_secs(5), Some(62)),
+            (Duration::from_secs(15), Some(65)),
+            (Duration::from_secs(15), None),
+        ]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn replays_as_fast_as_possible() {
+        assert_eq!(replay(0.0).await, vec![
+            (Duration::ZERO, Some(60)),
+            (Duration::ZERO, Some(62)),
+            (Duration::ZERO, Some(65)),
+            (Duration::ZERO, None),
+        ]);
+    }
+}
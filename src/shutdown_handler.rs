@@ -1,4 +1,4 @@
-//! ShutdownHandler
+//! `ShutdownHandler`
 //!
 //! Waits for the program to shut down and calls predefined hooks anywhere in the program to execute cleanup tasks.
 //!
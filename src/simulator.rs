@@ -0,0 +1,177 @@
+//! Synthetic heart rate source
+//!
+//! Publishes generated data on [`SENDER`](crate::adaptors::SENDER), so all consumers (api, csv logger, templates)
+//! can be used without a Bluetooth adapter or heart rate monitor.
+
+use std::f64::consts::PI;
+use std::time::Duration;
+
+use chrono::Utc;
+use clap::ValueEnum;
+use log::info;
+use tokio::time::sleep;
+
+use crate::adaptors::{ChannelTransferObject, HrData, HrmState, SENDER};
+use crate::CANCELLATION_TOKEN;
+
+/// Pattern the simulator uses to generate data.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SimulationMode {
+    /// Resting heart rate around 60 bpm with slow breathing variation
+    Resting,
+    /// Constant load around 130 bpm
+    Steady,
+    /// Alternating work (1 minute) and recovery (1.5 minutes) phases
+    Intervals,
+    /// Heart rate wanders randomly between 45 and 190 bpm
+    RandomWalk,
+    /// Resting heart rate, but skin contact is lost for 5 seconds every 30 seconds
+    ContactLoss,
+    /// Resting heart rate, but the device disconnects for 10 seconds every minute
+    Disconnects,
+    /// Resting heart rate, but the battery drains by 1% every 10 seconds
+    BatteryDrain,
+}
+
+/// Minimal xorshift generator; good enough for noise, not for anything else.
+struct Rng(u64);
+
+impl Rng {
+    fn new() -> Self {
+        #[allow(clippy::cast_sign_loss)]
+        Self(Utc::now().timestamp_nanos_opt().unwrap_or(1) as u64 | 1)
+    }
+
+    /// Returns a value between -1.0 and 1.0.
+    #[allow(clippy::cast_precision_loss)]
+    fn next(&mut self) -> f64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        (self.0 % 2001) as f64 / 1000.0 - 1.0
+    }
+}
+
+/// Generates one data point per tick for a [`SimulationMode`].
+struct Simulator {
+    mode: SimulationMode,
+    rng: Rng,
+    tick: u64,
+    hr: f64,
+    battery: u8,
+}
+
+impl Simulator {
+    fn new(mode: SimulationMode) -> Self {
+        Self {
+            mode,
+            rng: Rng::new(),
+            tick: 0,
+            hr: if mode == SimulationMode::Steady { 130.0 } else { 60.0 },
+            battery: 100,
+        }
+    }
+
+    /// Calculates the state for the next tick (one second).
+    #[allow(clippy::cast_precision_loss, clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    fn next(&mut self) -> HrmState {
+        let tick = self.tick;
+        self.tick += 1;
+        let noise = self.rng.next();
+
+        let mut contact_ok = Some(true);
+        match self.mode {
+            SimulationMode::Resting | SimulationMode::ContactLoss | SimulationMode::Disconnects | SimulationMode::BatteryDrain => {
+                self.hr = 60.0 + 3.0 * (tick as f64 * 2.0 * PI / 60.0).sin() + noise;
+            }
+            SimulationMode::Steady => {
+                self.hr = 130.0 + 2.0 * noise;
+            }
+            SimulationMode::Intervals => {
+                let target = if tick % 150 < 60 { 165.0 } else { 110.0 };
+                self.hr += (target - self.hr) * 0.1 + noise;
+            }
+            SimulationMode::RandomWalk => {
+                self.hr = (self.hr + 3.0 * noise).clamp(45.0, 190.0);
+            }
+        }
+        if self.mode == SimulationMode::ContactLoss && tick % 30 >= 25 {
+            contact_ok = Some(false);
+        }
+        if self.mode == SimulationMode::Disconnects && tick % 60 >= 50 {
+            return HrmState::Disconnected;
+        }
+        if self.mode == SimulationMode::BatteryDrain && tick % 10 == 9 {
+            self.battery = self.battery.saturating_sub(1);
+        }
+
+        let hr = self.hr.round().max(1.0) as u16;
+        HrmState::Ok(HrData {
+            hr,
+            contact_ok,
+            battery: Some(self.battery),
+            rr_intervals: vec![60_000 / hr],
+            ..HrData::default()
+        })
+    }
+}
+
+/// Publishes simulated data every second until the program shuts down.
+pub async fn run(mode: SimulationMode) {
+    if let Some(value) = mode.to_possible_value() {
+        info!("Simulating heart rate monitor in mode \"{}\"...", value.get_name());
+    }
+    let mut simulator = Simulator::new(mode);
+    while !CANCELLATION_TOKEN.is_cancelled() {
+        let _ = SENDER.send(ChannelTransferObject {
+            timestamp: Utc::now(),
+            device: None,
+            hr_state: Some(simulator.next()),
+        });
+        sleep(Duration::from_secs(1)).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns the data of the next `ticks` ticks; [`None`] while disconnected.
+    fn simulate(mode: SimulationMode, ticks: usize) -> Vec<Option<HrData>> {
+        let mut simulator = Simulator::new(mode);
+        (0..ticks).map(|_| match simulator.next() {
+            HrmState::Ok(data) => Some(data),
+            HrmState::Disconnected => None,
+        }).collect()
+    }
+
+    #[test]
+    fn keeps_heart_rate_in_range_of_mode() {
+        for (mode, range) in [
+            (SimulationMode::Resting, 56..=64),
+            (SimulationMode::Steady, 128..=132),
+            (SimulationMode::RandomWalk, 45..=190),
+            (SimulationMode::Intervals, 60..=170),
+        ] {
+            for data in simulate(mode, 600).into_iter().flatten() {
+                assert!(range.contains(&data.hr), "{mode:?}: {} is out of range", data.hr);
+                assert_eq!(data.rr_intervals, vec![60_000 / data.hr]);
+            }
+        }
+    }
+
+    #[test]
+    fn simulates_contact_loss_disconnects_and_battery_drain() {
+        let contact = simulate(SimulationMode::ContactLoss, 60);
+        let lost = contact.iter().flatten().filter(|d| d.contact_ok == Some(false)).count();
+        assert_eq!(lost, 10);
+        assert_eq!(contact.get(25).and_then(|d| d.as_ref()).and_then(|d| d.contact_ok), Some(false));
+
+        let disconnects = simulate(SimulationMode::Disconnects, 120);
+        assert_eq!(disconnects.iter().filter(|d| d.is_none()).count(), 20);
+        assert!(disconnects.get(50).is_some_and(Option::is_none));
+
+        let battery = simulate(SimulationMode::BatteryDrain, 100);
+        assert_eq!(battery.last().and_then(|d| d.as_ref()).and_then(|d| d.battery), Some(90));
+    }
+}
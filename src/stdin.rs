@@ -1,21 +1,45 @@
 //! Provides a way to get a line from stdin entered by the user.
 //! 
 //! Can also be used to get the actual next line of input while ignoring previous lines.
+//!
+//! Lines matching a [`Command`] are not queued, but executed by [`command_loop`].
 
 use std::collections::{VecDeque};
 use std::io;
 use std::io::BufRead;
-use std::sync::{Arc};
+use std::str::FromStr;
+use std::sync::{Arc, LazyLock};
 use std::time::Duration;
-use lazy_static::lazy_static;
-use log::error;
+use log::{error, info, warn};
 use tokio::sync::{RwLock};
+use tokio::sync::broadcast::{channel, Sender};
+use tokio::sync::broadcast::error::RecvError;
 use tokio::time::{Instant, sleep};
+use crate::adaptors::hrm::HRM;
 
-lazy_static!(
-    /// Contains all data read from stdin.
-    static ref STDIN_QUEUE: Arc<RwLock<VecDeque<String>>> = Arc::new(RwLock::new(VecDeque::new()));
-);
+/// Contains all data read from stdin.
+static STDIN_QUEUE: LazyLock<Arc<RwLock<VecDeque<String>>>> = LazyLock::new(|| Arc::new(RwLock::new(VecDeque::new())));
+
+/// Commands entered on stdin are sent through this channel.
+static COMMAND_SENDER: LazyLock<Sender<Command>> = LazyLock::new(|| channel::<Command>(16).0);
+
+/// Commands which can be entered on stdin at any time.
+#[derive(Clone, Copy, Debug)]
+pub enum Command {
+    /// Reset the energy expended counter of the connected device
+    ResetEnergyExpended,
+}
+
+impl FromStr for Command {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "reset_energy" => Ok(Self::ResetEnergyExpended),
+            _ => Err(()),
+        }
+    }
+}
 
 pub fn run() {
     match tokio::runtime::Builder::new_current_thread()
@@ -27,6 +51,10 @@ pub fn run() {
                     // read from stdin and add to buffer
                     let stdin = io::stdin().lock();
                     for line in stdin.lines().map_while(Result::ok) {
+                        if let Ok(command) = line.parse::<Command>() {
+                            let _ = COMMAND_SENDER.send(command);
+                            continue;
+                        }
                         STDIN_QUEUE.write().await.push_front(line);
                     }
                 }
@@ -63,4 +91,28 @@ pub async fn next_line(clear: bool, timeout: Option<Duration>) -> Option<String>
         }
         sleep(Duration::from_millis(1)).await;
     }
-}
\ No newline at end of file
+}
+
+/// Executes all [`Command`]s entered on stdin.
+pub async fn command_loop() {
+    let mut receiver = COMMAND_SENDER.subscribe();
+    loop {
+        let command = match receiver.recv().await {
+            Ok(command) => command,
+            // commands entered too fast are skipped, but later ones are still executed
+            Err(RecvError::Lagged(skipped)) => {
+                warn!("Skipped {skipped} commands entered too fast");
+                continue;
+            }
+            Err(RecvError::Closed) => break,
+        };
+        match command {
+            Command::ResetEnergyExpended => {
+                match HRM.reset_energy_expended().await {
+                    Ok(()) => info!("Energy expended reset."),
+                    Err(err) => error!("Resetting energy expended failed: {err}"),
+                }
+            }
+        }
+    }
+}
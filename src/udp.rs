@@ -0,0 +1,224 @@
+//! Heart rate source fed over UDP
+//!
+//! Listens for datagrams sent by other tools and publishes them through [`INGEST`], like pushes to `POST /ingest`.
+//! A datagram may be
+//! - an [OSC](https://opensoundcontrol.stanford.edu/spec-1_0.html) message (or bundle); the first numeric argument of
+//!   messages to the configured address is the heart rate, all other messages are ignored
+//! - a JSON object in the format of [`IngestData`]
+//! - a plain number (heart rate in bpm)
+
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use log::{debug, error, info, warn};
+use tokio::net::UdpSocket;
+
+use crate::CANCELLATION_TOKEN;
+use crate::ingest::{INGEST, IngestData};
+
+/// Largest datagram, which is read completely.
+const MAX_DATAGRAM_SIZE: usize = 65_507;
+/// Deepest nesting of OSC bundles, which is parsed
+const MAX_BUNDLE_DEPTH: usize = 8;
+
+/// Parses a datagram.
+///
+/// Returns [`None`] for OSC messages to other addresses than `osc_address`.
+pub fn parse_datagram(datagram: &[u8], osc_address: &str) -> Result<Option<IngestData>> {
+    match datagram.first() {
+        Some(b'/' | b'#') => parse_osc(datagram, osc_address, 0),
+        Some(b'{') => Ok(Some(serde_json::from_slice(datagram)?)),
+        Some(_) => {
+            let text = std::str::from_utf8(datagram)?.trim();
+            let hr = text.parse::<f64>().map_err(|_| anyhow!("\"{text}\" is not a number"))?;
+            Ok(Some(IngestData {
+                hr: to_hr(hr)?,
+                contact_ok: None,
+                battery: None,
+                rr_intervals: vec![],
+            }))
+        }
+        None => Err(anyhow!("The datagram is empty")),
+    }
+}
+
+/// Converts a received number to a heart rate.
+#[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+fn to_hr(value: f64) -> Result<u16> {
+    if !(0.0..=f64::from(u16::MAX)).contains(&value) {
+        return Err(anyhow!("{value} is not a valid heart rate"));
+    }
+    Ok(value.round() as u16)
+}
+
+/// Parses an OSC message or bundle; for bundles, the last matching message wins.
+///
+/// `depth` is the number of bundles containing the packet; bundles nested deeper than [`MAX_BUNDLE_DEPTH`] are rejected.
+fn parse_osc(packet: &[u8], osc_address: &str, depth: usize) -> Result<Option<IngestData>> {
+    let mut reader = OscReader { data: packet };
+    let address = reader.string()?;
+    if address == "#bundle" {
+        if depth >= MAX_BUNDLE_DEPTH {
+            return Err(anyhow!("OSC bundles are nested deeper than {MAX_BUNDLE_DEPTH} levels"));
+        }
+        // time tag; messages are published on arrival
+        reader.take(8)?;
+        let mut result = None;
+        while !reader.data.is_empty() {
+            let size = usize::try_from(reader.int()?).map_err(|_| anyhow!("Invalid OSC bundle element size"))?;
+            if let Some(data) = parse_osc(reader.take(size)?, osc_address, depth + 1)? {
+                result = Some(data);
+            }
+        }
+        return Ok(result);
+    }
+    if address != osc_address {
+        return Ok(None);
+    }
+
+    let tags = reader.string()?;
+    let tags = tags.strip_prefix(',').ok_or(anyhow!("OSC message to {address} has no type tags"))?;
+    for tag in tags.chars() {
+        let value = match tag {
+            'i' => f64::from(reader.int()?),
+            'f' => f64::from(f32::from_be_bytes(reader.array()?)),
+            'd' => f64::from_be_bytes(reader.array()?),
+            's' => {
+                reader.string()?;
+                continue;
+            }
+            'T' | 'F' | 'N' | 'I' => continue,
+            _ => return Err(anyhow!("OSC type tag '{tag}' is not supported")),
+        };
+        return Ok(Some(IngestData {
+            hr: to_hr(value)?,
+            contact_ok: None,
+            battery: None,
+            rr_intervals: vec![],
+        }));
+    }
+    Err(anyhow!("OSC message to {address} has no numeric argument"))
+}
+
+/// Reads the 4 byte aligned parts of an OSC packet.
+struct OscReader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> OscReader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        if len > self.data.len() {
+            return Err(anyhow!("The OSC packet is truncated"));
+        }
+        let (taken, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(taken)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N]> {
+        Ok(self.take(N)?.try_into()?)
+    }
+
+    fn int(&mut self) -> Result<i32> {
+        Ok(i32::from_be_bytes(self.array()?))
+    }
+
+    /// Reads a null terminated string, padded to a multiple of 4 bytes.
+    fn string(&mut self) -> Result<&'a str> {
+        let len = self.data.iter().position(|b| *b == 0).ok_or(anyhow!("OSC string is not terminated"))?;
+        let value = self.take((len / 4 + 1) * 4)?;
+        Ok(std::str::from_utf8(value.get(..len).unwrap_or_default())?)
+    }
+}
+
+/// Receives datagrams on `host`:`port` and publishes them, until the program shuts down.
+///
+/// If nothing is received for `timeout`, the state changes to [`HrmState::Disconnected`](crate::adaptors::HrmState).
+pub async fn run(host: String, port: u16, osc_address: String, timeout: Duration) {
+    let socket = match UdpSocket::bind((host.as_str(), port)).await {
+        Ok(v) => v,
+        Err(err) => {
+            error!("Could not listen on UDP {host}:{port}: {err}");
+            return;
+        }
+    };
+    info!("Waiting for heart rate on UDP {host}:{port} (OSC address {osc_address})...");
+    tokio::spawn(INGEST.run(timeout));
+
+    let mut buffer = vec![0; MAX_DATAGRAM_SIZE];
+    loop {
+        let received = tokio::select! {
+            () = CANCELLATION_TOKEN.cancelled() => return,
+            received = socket.recv_from(&mut buffer) => received,
+        };
+        let (len, sender) = match received {
+            Ok(v) => v,
+            Err(err) => {
+                warn!("Could not receive UDP datagram: {err}");
+                continue;
+            }
+        };
+        match parse_datagram(buffer.get(..len).unwrap_or_default(), &osc_address) {
+            Ok(Some(data)) => INGEST.push(data).await,
+            Ok(None) => debug!("Ignoring OSC message of {sender} to another address"),
+            Err(err) => warn!("Ignoring invalid datagram of {sender}: {err}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Pads `value` with zeros to a multiple of 4 bytes, like OSC strings.
+    fn osc_string(value: &str) -> Vec<u8> {
+        let mut bytes = value.as_bytes().to_vec();
+        bytes.resize((value.len() / 4 + 1) * 4, 0);
+        bytes
+    }
+
+    fn osc_message(address: &str, tags: &str, args: &[u8]) -> Vec<u8> {
+        [osc_string(address), osc_string(tags), args.to_vec()].concat()
+    }
+
+    /// Wraps `element` in an OSC bundle.
+    fn osc_bundle(element: Vec<u8>) -> Vec<u8> {
+        [
+            osc_string("#bundle"),
+            vec![0, 0, 0, 0, 0, 0, 0, 1],
+            u32::try_from(element.len()).unwrap_or_default().to_be_bytes().to_vec(),
+            element,
+        ].concat()
+    }
+
+    fn hr(datagram: &[u8]) -> Option<u16> {
+        parse_datagram(datagram, "/hr").ok().flatten().map(|d| d.hr)
+    }
+
+    #[test]
+    fn parses_datagrams() {
+        assert_eq!(hr(b"72"), Some(72));
+        assert_eq!(hr(b" 71.6\n"), Some(72));
+        assert_eq!(hr(br#"{"hr": 73, "battery": 50}"#), Some(73));
+        assert_eq!(hr(&osc_message("/hr", ",i", &74_i32.to_be_bytes())), Some(74));
+        assert_eq!(hr(&osc_message("/hr", ",f", &75.2_f32.to_be_bytes())), Some(75));
+        assert_eq!(hr(&osc_message("/hr", ",sd", &[osc_string("bpm"), 76.0_f64.to_be_bytes().to_vec()].concat())), Some(76));
+
+        // other addresses are ignored
+        assert!(matches!(parse_datagram(&osc_message("/other", ",i", &1_i32.to_be_bytes()), "/hr"), Ok(None)));
+
+        let message = osc_message("/hr", ",i", &77_i32.to_be_bytes());
+        assert_eq!(hr(&osc_bundle(message.clone())), Some(77));
+        // nested bundles
+        let nested = (0..MAX_BUNDLE_DEPTH).fold(message.clone(), |element, _| osc_bundle(element));
+        assert_eq!(hr(&nested), Some(77));
+        let too_deep = (0..=MAX_BUNDLE_DEPTH).fold(message, |element, _| osc_bundle(element));
+        assert!(parse_datagram(&too_deep, "/hr").is_err());
+
+        assert!(parse_datagram(b"", "/hr").is_err());
+        assert!(parse_datagram(b"fast", "/hr").is_err());
+        assert!(parse_datagram(b"-5", "/hr").is_err());
+        assert!(parse_datagram(&osc_message("/hr", ",s", &osc_string("72")), "/hr").is_err());
+        assert!(parse_datagram(&osc_message("/hr", ",i", &[0, 0]), "/hr").is_err());
+    }
+}